@@ -2,8 +2,10 @@ export type {
   FrontendApiFieldError,
   FrontendApiMutationError,
   FrontendApiMutationSuccess,
+  FrontendCursorPage,
   FrontendNoAccessData,
   FrontendNoAccessState,
+  FrontendPaginated,
   FrontendShellCurrentUser,
   FrontendShellData,
   FrontendShellNavigationItem,
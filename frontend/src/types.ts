@@ -73,6 +73,33 @@ export type FrontendApiMutationError = {
   fieldErrors: FrontendApiFieldError[];
 };
 
+/**
+ * One page of a list as serialized by `pushkind_common::pagination::Paginated`.
+ * `pages` holds page numbers for navigation controls, `null` marks an ellipsis.
+ * `perPage` and `totalItems` are absent when only the page count is known.
+ */
+export type FrontendPaginated<TItem> = {
+  items: TItem[];
+  pages: (number | null)[];
+  page: number;
+  perPage?: number;
+  totalItems?: number;
+  totalPages: number;
+  hasNext: boolean;
+  hasPrevious: boolean;
+};
+
+/**
+ * One page of a keyset-paginated list as serialized by
+ * `pushkind_common::pagination::cursor::CursorPage`. Cursors are opaque tokens
+ * to pass back in the query string.
+ */
+export type FrontendCursorPage<TItem> = {
+  items: TItem[];
+  next: string | null;
+  prev: string | null;
+};
+
 export type FrontendNoAccessState<
   TData extends FrontendNoAccessData = FrontendNoAccessData,
 > =
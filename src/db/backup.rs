@@ -0,0 +1,338 @@
+//! Consistent snapshots of live SQLite databases.
+//!
+//! Copying the database file while the service runs can miss pages that
+//! still sit in the WAL. [`snapshot`] uses `VACUUM INTO`, which reads the
+//! database through a regular transaction and writes a compact, self-contained
+//! copy. [`BackupRotation`] keeps a fixed number of timestamped snapshots in a
+//! directory:
+//!
+//! ```ignore
+//! let backups = BackupRotation::new("/var/backups/crm", "crm", 7);
+//! let path = backups.create(&pool)?;
+//! log::info!("Database saved to {}", path.display());
+//! ```
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use diesel::sqlite::SqliteConnection;
+use thiserror::Error;
+
+use crate::db::{DbPool, get_connection};
+
+const SNAPSHOT_EXTENSION: &str = "sqlite3";
+
+/// Errors produced while creating, verifying or restoring snapshots.
+#[derive(Debug, Error)]
+pub enum BackupError {
+    #[error("backup io error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("backup database error: {0}")]
+    Database(#[source] Box<dyn std::error::Error + Send + Sync>),
+
+    #[error("snapshot failed integrity check: {0}")]
+    Integrity(String),
+}
+
+impl From<diesel::result::Error> for BackupError {
+    fn from(err: diesel::result::Error) -> Self {
+        BackupError::Database(err.into())
+    }
+}
+
+impl From<diesel::ConnectionError> for BackupError {
+    fn from(err: diesel::ConnectionError) -> Self {
+        BackupError::Database(err.into())
+    }
+}
+
+impl From<diesel::r2d2::PoolError> for BackupError {
+    fn from(err: diesel::r2d2::PoolError) -> Self {
+        BackupError::Database(err.into())
+    }
+}
+
+#[derive(QueryableByName)]
+struct IntegrityRow {
+    #[diesel(sql_type = Text)]
+    integrity_check: String,
+}
+
+/// Write a consistent copy of the database behind `pool` to `dest` and
+/// verify it.
+///
+/// `dest` must not exist yet. A snapshot that fails verification is removed.
+pub fn snapshot(pool: &DbPool, dest: &Path) -> Result<(), BackupError> {
+    let mut conn = get_connection(pool)?;
+    snapshot_connection(&mut conn, dest)
+}
+
+/// Connection-level variant of [`snapshot`].
+pub fn snapshot_connection(conn: &mut SqliteConnection, dest: &Path) -> Result<(), BackupError> {
+    if dest.exists() {
+        return Err(BackupError::Io(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("snapshot target already exists: {}", dest.display()),
+        )));
+    }
+
+    diesel::sql_query("VACUUM INTO ?")
+        .bind::<Text, _>(path_str(dest)?)
+        .execute(conn)?;
+
+    if let Err(err) = verify_integrity(dest) {
+        let _ = fs::remove_file(dest);
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Run `PRAGMA integrity_check` on the database file at `path`.
+pub fn verify_integrity(path: &Path) -> Result<(), BackupError> {
+    if !path.is_file() {
+        return Err(BackupError::Io(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("snapshot not found: {}", path.display()),
+        )));
+    }
+
+    let mut conn = SqliteConnection::establish(path_str(path)?)?;
+    let rows = diesel::sql_query("PRAGMA integrity_check").load::<IntegrityRow>(&mut conn)?;
+    let problems = rows
+        .into_iter()
+        .map(|row| row.integrity_check)
+        .filter(|message| message != "ok")
+        .collect::<Vec<_>>();
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(BackupError::Integrity(problems.join("; ")))
+    }
+}
+
+/// Replace the database file at `target` with the contents of `snapshot`.
+///
+/// The snapshot is verified first and copied next to `target` before being
+/// renamed over it, so an interrupted restore leaves the old file intact.
+/// Stale `-wal` and `-shm` files of the target are removed. Every connection
+/// to `target` must be closed while restoring.
+pub fn restore(snapshot: &Path, target: &Path) -> Result<(), BackupError> {
+    verify_integrity(snapshot)?;
+
+    let staging = target.with_extension("restore");
+    if staging.exists() {
+        fs::remove_file(&staging)?;
+    }
+    let mut source = SqliteConnection::establish(path_str(snapshot)?)?;
+    diesel::sql_query("VACUUM INTO ?")
+        .bind::<Text, _>(path_str(&staging)?)
+        .execute(&mut source)?;
+    drop(source);
+
+    for suffix in ["-wal", "-shm"] {
+        let mut sidecar = target.as_os_str().to_owned();
+        sidecar.push(suffix);
+        match fs::remove_file(PathBuf::from(sidecar)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+    }
+    fs::rename(&staging, target)?;
+    log::info!(
+        "Restored database {} from {}",
+        target.display(),
+        snapshot.display()
+    );
+    Ok(())
+}
+
+/// Timestamped snapshots in a directory with a retention limit.
+#[derive(Clone, Debug)]
+pub struct BackupRotation {
+    dir: PathBuf,
+    prefix: String,
+    keep: usize,
+}
+
+impl BackupRotation {
+    /// Store snapshots named `<prefix>-<timestamp>.sqlite3` in `dir` and keep
+    /// the newest `keep` of them.
+    pub fn new(dir: impl Into<PathBuf>, prefix: impl Into<String>, keep: usize) -> Self {
+        Self {
+            dir: dir.into(),
+            prefix: prefix.into(),
+            keep: keep.max(1),
+        }
+    }
+
+    /// Snapshot the database, prune old snapshots and return the new path.
+    pub fn create(&self, pool: &DbPool) -> Result<PathBuf, BackupError> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.next_path()?;
+        snapshot(pool, &path)?;
+
+        let removed = self.prune()?;
+        log::info!(
+            "Database snapshot written to {} ({} old snapshots removed)",
+            path.display(),
+            removed.len()
+        );
+        Ok(path)
+    }
+
+    /// Existing snapshots, oldest first.
+    pub fn snapshots(&self) -> Result<Vec<PathBuf>, BackupError> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut snapshots = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if self.timestamp_of(&path).is_some() {
+                snapshots.push(path);
+            }
+        }
+        snapshots.sort_by_key(|path| self.timestamp_of(path));
+        Ok(snapshots)
+    }
+
+    /// The most recent snapshot, if any.
+    pub fn latest(&self) -> Result<Option<PathBuf>, BackupError> {
+        Ok(self.snapshots()?.pop())
+    }
+
+    /// Delete snapshots beyond the retention limit and return their paths.
+    pub fn prune(&self) -> Result<Vec<PathBuf>, BackupError> {
+        let snapshots = self.snapshots()?;
+        let excess = snapshots.len().saturating_sub(self.keep);
+        let removed = snapshots.into_iter().take(excess).collect::<Vec<_>>();
+        for path in &removed {
+            fs::remove_file(path)?;
+        }
+        Ok(removed)
+    }
+
+    fn next_path(&self) -> Result<PathBuf, BackupError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| io::Error::other(e.to_string()))?
+            .as_micros();
+        let mut stamp = now;
+        loop {
+            let path = self
+                .dir
+                .join(format!("{}-{stamp}.{SNAPSHOT_EXTENSION}", self.prefix));
+            if !path.exists() {
+                return Ok(path);
+            }
+            stamp += 1;
+        }
+    }
+
+    fn timestamp_of(&self, path: &Path) -> Option<u128> {
+        let name = path.file_name()?.to_str()?;
+        name.strip_prefix(&self.prefix)?
+            .strip_prefix('-')?
+            .strip_suffix(SNAPSHOT_EXTENSION)?
+            .strip_suffix('.')?
+            .parse()
+            .ok()
+    }
+}
+
+fn path_str(path: &Path) -> Result<&str, BackupError> {
+    path.to_str().ok_or_else(|| {
+        BackupError::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("path is not valid UTF-8: {}", path.display()),
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::establish_connection_pool;
+    use crate::testing::count_rows;
+    use diesel::connection::SimpleConnection;
+
+    fn count(path: &Path) -> i64 {
+        let mut conn = SqliteConnection::establish(path.to_str().unwrap()).unwrap();
+        count_rows(&mut conn, "items")
+    }
+
+    fn live_database(dir: &Path) -> (PathBuf, DbPool) {
+        let path = dir.join("live.db");
+        let pool = establish_connection_pool(path.to_str().unwrap()).unwrap();
+        pool.get()
+            .unwrap()
+            .batch_execute(
+                "CREATE TABLE items (id INTEGER PRIMARY KEY);
+                 INSERT INTO items DEFAULT VALUES;
+                 INSERT INTO items DEFAULT VALUES;",
+            )
+            .unwrap();
+        (path, pool)
+    }
+
+    #[test]
+    fn snapshot_contains_uncheckpointed_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, pool) = live_database(dir.path());
+        let dest = dir.path().join("copy.sqlite3");
+
+        snapshot(&pool, &dest).unwrap();
+
+        assert_eq!(count(&dest), 2);
+        assert!(snapshot(&pool, &dest).is_err());
+    }
+
+    #[test]
+    fn rotation_keeps_newest_snapshots() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, pool) = live_database(dir.path());
+        let rotation = BackupRotation::new(dir.path().join("backups"), "crm", 2);
+
+        let created = (0..3)
+            .map(|_| rotation.create(&pool).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(rotation.snapshots().unwrap(), created[1..].to_vec());
+        assert_eq!(rotation.latest().unwrap().as_ref(), created.last());
+    }
+
+    #[test]
+    fn corrupt_file_fails_verification() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("broken.sqlite3");
+        fs::write(&path, b"definitely not a database").unwrap();
+
+        assert!(verify_integrity(&path).is_err());
+    }
+
+    #[test]
+    fn restore_replaces_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let (live, pool) = live_database(dir.path());
+        let dest = dir.path().join("copy.sqlite3");
+        snapshot(&pool, &dest).unwrap();
+        pool.get()
+            .unwrap()
+            .batch_execute("DELETE FROM items")
+            .unwrap();
+        drop(pool);
+
+        restore(&dest, &live).unwrap();
+
+        assert_eq!(count(&live), 2);
+    }
+}
@@ -0,0 +1,303 @@
+//! Running Diesel work from async Actix handlers.
+//!
+//! [`DbPoolExt::run`] moves a closure onto the blocking thread pool, checks
+//! out a connection there and converts every failure into a
+//! [`RepositoryError`]:
+//!
+//! ```ignore
+//! let customers = pool.run(move |conn| load_customers(conn, hub_id)).await?;
+//! ```
+//!
+//! Concurrency is then bounded by the size of the pool, with blocking threads
+//! waiting for a free connection. [`AsyncDbPool`] bounds the number of
+//! closures running at once, so a burst of requests waits asynchronously
+//! instead of parking blocking threads on the r2d2 pool.
+//!
+//! The [`DbConn`] extractor uses the `web::Data<AsyncDbPool>` registered at
+//! startup, so every request shares its concurrency limit, and falls back to
+//! a registered `web::Data<DbPool>`.
+//!
+//! ```ignore
+//! App::new().app_data(web::Data::new(AsyncDbPool::new(pool)));
+//!
+//! async fn list(db: DbConn, user: AuthenticatedUser) -> Result<HttpResponse, ServiceError> {
+//!     let customers = db
+//!         .run(move |conn| customers::table.filter(customers::hub_id.eq(user.hub_id)).load(conn).map_err(Into::into))
+//!         .await?;
+//!     Ok(HttpResponse::Ok().json(customers))
+//! }
+//! ```
+
+use std::future::{Future, Ready, ready};
+use std::sync::Arc;
+
+use actix_web::error::ErrorInternalServerError;
+use actix_web::web::Data;
+use actix_web::{Error, FromRequest, HttpRequest, dev::Payload};
+use tokio::sync::Semaphore;
+
+use crate::db::instrumentation::timed_get;
+use crate::db::{DbConnection, DbPool};
+use crate::repository::errors::{RepositoryError, RepositoryResult};
+
+/// Adds [`run`](DbPoolExt::run) to [`DbPool`].
+pub trait DbPoolExt {
+    /// Run `f` with a pooled connection on the blocking thread pool.
+    ///
+    /// Pool errors become [`RepositoryError::ConnectionError`] and a panic
+    /// inside `f` becomes [`RepositoryError::Unexpected`].
+    fn run<F, T>(&self, f: F) -> impl Future<Output = RepositoryResult<T>> + Send
+    where
+        F: FnOnce(&mut DbConnection) -> RepositoryResult<T> + Send + 'static,
+        T: Send + 'static;
+}
+
+impl DbPoolExt for DbPool {
+    fn run<F, T>(&self, f: F) -> impl Future<Output = RepositoryResult<T>> + Send
+    where
+        F: FnOnce(&mut DbConnection) -> RepositoryResult<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        run_blocking(self.clone(), f)
+    }
+}
+
+async fn run_blocking<F, T>(pool: DbPool, f: F) -> RepositoryResult<T>
+where
+    F: FnOnce(&mut DbConnection) -> RepositoryResult<T> + Send + 'static,
+    T: Send + 'static,
+{
+    actix_web::rt::task::spawn_blocking(move || {
+        let mut conn = timed_get(&pool)?;
+        f(&mut conn)
+    })
+    .await
+    .map_err(|e| RepositoryError::Unexpected(format!("Database task failed: {e}")))?
+}
+
+/// A [`DbPool`] whose work runs on the blocking thread pool with bounded
+/// concurrency.
+#[derive(Clone)]
+pub struct AsyncDbPool {
+    pool: DbPool,
+    permits: Arc<Semaphore>,
+}
+
+impl AsyncDbPool {
+    /// Wrap `pool`, allowing as many concurrent closures as it has
+    /// connections.
+    pub fn new(pool: DbPool) -> Self {
+        let max_concurrency = pool.max_size() as usize;
+        Self::with_max_concurrency(pool, max_concurrency)
+    }
+
+    /// Wrap `pool`, allowing at most `max_concurrency` closures at once.
+    pub fn with_max_concurrency(pool: DbPool, max_concurrency: usize) -> Self {
+        Self {
+            pool,
+            permits: Arc::new(Semaphore::new(max_concurrency.max(1))),
+        }
+    }
+
+    /// The wrapped connection pool.
+    pub fn pool(&self) -> &DbPool {
+        &self.pool
+    }
+
+    /// Run `f` with a pooled connection on the blocking thread pool.
+    ///
+    /// The connection is [`Writable`](crate::db::Writable), so `f` can call
+    /// write helpers such as
+    /// [`write_transaction`](crate::db::transaction::write_transaction).
+    ///
+    /// Pool errors become [`RepositoryError::ConnectionError`] and a panic
+    /// inside `f` becomes [`RepositoryError::Unexpected`].
+    pub async fn run<F, T>(&self, f: F) -> RepositoryResult<T>
+    where
+        F: FnOnce(&mut DbConnection) -> RepositoryResult<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let permit = Arc::clone(&self.permits)
+            .acquire_owned()
+            .await
+            .map_err(|e| RepositoryError::Unexpected(e.to_string()))?;
+        run_blocking(self.pool.clone(), move |conn| {
+            let _permit = permit;
+            f(conn)
+        })
+        .await
+    }
+}
+
+impl From<DbPool> for AsyncDbPool {
+    fn from(pool: DbPool) -> Self {
+        Self::new(pool)
+    }
+}
+
+/// Extractor giving handlers access to the registered [`AsyncDbPool`], or to
+/// the registered [`DbPool`] when there is none.
+///
+/// Extraction fails with an internal server error when neither is
+/// registered.
+#[derive(Clone)]
+pub struct DbConn(Executor);
+
+#[derive(Clone)]
+enum Executor {
+    Bounded(AsyncDbPool),
+    Pool(DbPool),
+}
+
+impl DbConn {
+    /// See [`AsyncDbPool::run`] and [`DbPoolExt::run`].
+    pub async fn run<F, T>(&self, f: F) -> RepositoryResult<T>
+    where
+        F: FnOnce(&mut DbConnection) -> RepositoryResult<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        match &self.0 {
+            Executor::Bounded(pool) => pool.run(f).await,
+            Executor::Pool(pool) => pool.run(f).await,
+        }
+    }
+}
+
+impl FromRequest for DbConn {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        if let Some(pool) = req.app_data::<Data<AsyncDbPool>>() {
+            return ready(Ok(DbConn(Executor::Bounded(pool.get_ref().clone()))));
+        }
+        match req.app_data::<Data<DbPool>>() {
+            Some(pool) => ready(Ok(DbConn(Executor::Pool(pool.get_ref().clone())))),
+            None => ready(Err(ErrorInternalServerError(
+                "Database pool not registered; register web::Data<AsyncDbPool> or web::Data<DbPool>",
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::DbPoolBuilder;
+    use actix_web::test::TestRequest;
+    use diesel::connection::SimpleConnection;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    fn pool(max_size: u32) -> (tempfile::NamedTempFile, DbPool) {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let pool = DbPoolBuilder::new()
+            .max_size(max_size)
+            .build(file.path().to_str().unwrap())
+            .unwrap();
+        (file, pool)
+    }
+
+    #[actix_web::test]
+    async fn run_returns_closure_result_and_errors() {
+        let (_file, pool) = pool(2);
+        let db = AsyncDbPool::new(pool);
+
+        let value = db
+            .run(|conn| {
+                conn.batch_execute("CREATE TABLE t (id INTEGER PRIMARY KEY)")?;
+                Ok(42)
+            })
+            .await
+            .unwrap();
+        assert_eq!(value, 42);
+
+        let error = db
+            .run(|conn| {
+                conn.batch_execute("SELECT * FROM missing")?;
+                Ok(())
+            })
+            .await
+            .unwrap_err();
+        assert!(matches!(error, RepositoryError::DatabaseError(..)));
+    }
+
+    #[actix_web::test]
+    async fn run_accepts_write_helpers() {
+        use crate::db::transaction::{RetryPolicy, write_transaction};
+
+        let (_file, pool) = pool(1);
+        let db = AsyncDbPool::new(pool);
+
+        db.run(|conn| {
+            write_transaction(conn, &RetryPolicy::no_retry(), |tx| {
+                tx.batch_execute("CREATE TABLE t (id INTEGER PRIMARY KEY)")?;
+                Ok(())
+            })
+        })
+        .await
+        .unwrap();
+    }
+
+    #[actix_web::test]
+    async fn concurrency_is_bounded() {
+        let (_file, pool) = pool(4);
+        let db = AsyncDbPool::with_max_concurrency(pool, 1);
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+
+        let tasks = (0..4).map(|_| {
+            let running = Arc::clone(&running);
+            let peak = Arc::clone(&peak);
+            db.run(move |_| {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(20));
+                running.fetch_sub(1, Ordering::SeqCst);
+                Ok(())
+            })
+        });
+        for result in futures_util::future::join_all(tasks).await {
+            result.unwrap();
+        }
+
+        assert_eq!(peak.load(Ordering::SeqCst), 1);
+    }
+
+    #[actix_web::test]
+    async fn extractor_requires_registered_pool() {
+        let req = TestRequest::default().to_http_request();
+        assert!(DbConn::extract(&req).await.is_err());
+
+        let (_file, pool) = pool(1);
+        let req = TestRequest::default()
+            .app_data(Data::new(AsyncDbPool::from(pool)))
+            .to_http_request();
+        let db = DbConn::extract(&req).await.unwrap();
+        assert_eq!(db.run(|_| Ok("ok")).await.unwrap(), "ok");
+    }
+
+    #[actix_web::test]
+    async fn extractor_falls_back_to_plain_pool() {
+        let (_file, pool) = pool(1);
+        let req = TestRequest::default()
+            .app_data(Data::new(pool))
+            .to_http_request();
+        let db = DbConn::extract(&req).await.unwrap();
+        assert_eq!(db.run(|_| Ok("ok")).await.unwrap(), "ok");
+    }
+
+    #[actix_web::test]
+    async fn pool_extension_runs_closures() {
+        let (_file, pool) = pool(1);
+
+        let value = pool
+            .run(|conn| {
+                conn.batch_execute("CREATE TABLE t (id INTEGER PRIMARY KEY)")?;
+                Ok(42)
+            })
+            .await
+            .unwrap();
+        assert_eq!(value, 42);
+    }
+}
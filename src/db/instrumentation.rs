@@ -0,0 +1,242 @@
+//! Query timing, slow-query logging and connection pool wait metrics.
+//!
+//! Pools built with [`DbPoolBuilder`](crate::db::DbPoolBuilder) attach a
+//! [`QueryLogger`] to every connection. It counts and times queries in
+//! process-wide counters. When
+//! [`slow_query_threshold`](crate::db::DbPoolBuilder::slow_query_threshold)
+//! is set, e.g. to [`DEFAULT_SLOW_QUERY_THRESHOLD`], it also logs queries
+//! slower than the threshold at `WARN` level.
+//! Only the SQL text is logged: bind values carry user data such as emails
+//! and tokens and are never written. [`get_connection`](crate::db::get_connection) and
+//! [`AsyncDbPool`](crate::db::executor::AsyncDbPool) record how long callers
+//! waited for a pooled connection. A metrics endpoint reports the totals:
+//!
+//! ```ignore
+//! async fn metrics() -> HttpResponse {
+//!     HttpResponse::Ok().json(pushkind_common::db::instrumentation::metrics())
+//! }
+//! ```
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use diesel::connection::{Instrumentation, InstrumentationEvent};
+use diesel::r2d2::PoolError;
+use serde::Serialize;
+
+use crate::db::{DbConnection, DbPool};
+
+/// Suggested threshold above which a query is logged as slow.
+pub const DEFAULT_SLOW_QUERY_THRESHOLD: Duration = Duration::from_millis(500);
+
+static METRICS: Counters = Counters::new();
+
+struct Counters {
+    queries: AtomicU64,
+    failed_queries: AtomicU64,
+    slow_queries: AtomicU64,
+    query_time_us: AtomicU64,
+    max_query_time_us: AtomicU64,
+    checkouts: AtomicU64,
+    failed_checkouts: AtomicU64,
+    wait_time_us: AtomicU64,
+    max_wait_time_us: AtomicU64,
+}
+
+impl Counters {
+    const fn new() -> Self {
+        Self {
+            queries: AtomicU64::new(0),
+            failed_queries: AtomicU64::new(0),
+            slow_queries: AtomicU64::new(0),
+            query_time_us: AtomicU64::new(0),
+            max_query_time_us: AtomicU64::new(0),
+            checkouts: AtomicU64::new(0),
+            failed_checkouts: AtomicU64::new(0),
+            wait_time_us: AtomicU64::new(0),
+            max_wait_time_us: AtomicU64::new(0),
+        }
+    }
+}
+
+/// Point-in-time copy of the process-wide database counters.
+///
+/// Times are in microseconds.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct DbMetrics {
+    pub queries: u64,
+    pub failed_queries: u64,
+    pub slow_queries: u64,
+    pub query_time_us: u64,
+    pub max_query_time_us: u64,
+    pub connection_checkouts: u64,
+    pub failed_connection_checkouts: u64,
+    pub connection_wait_time_us: u64,
+    pub max_connection_wait_time_us: u64,
+}
+
+/// Current values of the database counters.
+pub fn metrics() -> DbMetrics {
+    DbMetrics {
+        queries: METRICS.queries.load(Ordering::Relaxed),
+        failed_queries: METRICS.failed_queries.load(Ordering::Relaxed),
+        slow_queries: METRICS.slow_queries.load(Ordering::Relaxed),
+        query_time_us: METRICS.query_time_us.load(Ordering::Relaxed),
+        max_query_time_us: METRICS.max_query_time_us.load(Ordering::Relaxed),
+        connection_checkouts: METRICS.checkouts.load(Ordering::Relaxed),
+        failed_connection_checkouts: METRICS.failed_checkouts.load(Ordering::Relaxed),
+        connection_wait_time_us: METRICS.wait_time_us.load(Ordering::Relaxed),
+        max_connection_wait_time_us: METRICS.max_wait_time_us.load(Ordering::Relaxed),
+    }
+}
+
+/// Check out a connection from `pool` and record the time spent waiting.
+pub fn timed_get(pool: &DbPool) -> Result<DbConnection, PoolError> {
+    let started = Instant::now();
+    let result = pool.get();
+    let waited = micros(started.elapsed());
+
+    METRICS.checkouts.fetch_add(1, Ordering::Relaxed);
+    METRICS.wait_time_us.fetch_add(waited, Ordering::Relaxed);
+    METRICS
+        .max_wait_time_us
+        .fetch_max(waited, Ordering::Relaxed);
+    if result.is_err() {
+        METRICS.failed_checkouts.fetch_add(1, Ordering::Relaxed);
+    }
+    result
+}
+
+/// Diesel instrumentation that times queries and logs the slow ones.
+#[derive(Debug)]
+pub struct QueryLogger {
+    slow_query_threshold: Option<Duration>,
+    started: Option<Instant>,
+}
+
+impl QueryLogger {
+    /// Count and time queries, and log those that take longer than
+    /// `slow_query_threshold` when it is set.
+    pub fn new(slow_query_threshold: Option<Duration>) -> Self {
+        Self {
+            slow_query_threshold,
+            started: None,
+        }
+    }
+}
+
+impl Instrumentation for QueryLogger {
+    fn on_connection_event(&mut self, event: InstrumentationEvent<'_>) {
+        match event {
+            InstrumentationEvent::StartQuery { .. } => self.started = Some(Instant::now()),
+            InstrumentationEvent::FinishQuery { query, error, .. } => {
+                let Some(started) = self.started.take() else {
+                    return;
+                };
+                let elapsed = started.elapsed();
+                let elapsed_us = micros(elapsed);
+
+                METRICS.queries.fetch_add(1, Ordering::Relaxed);
+                METRICS
+                    .query_time_us
+                    .fetch_add(elapsed_us, Ordering::Relaxed);
+                METRICS
+                    .max_query_time_us
+                    .fetch_max(elapsed_us, Ordering::Relaxed);
+                if let Some(error) = error {
+                    METRICS.failed_queries.fetch_add(1, Ordering::Relaxed);
+                    if log::log_enabled!(log::Level::Debug) {
+                        let query = query.to_string();
+                        let sql = without_binds(&query);
+                        log::debug!("Query failed after {elapsed:?}: {error}: {sql}");
+                    }
+                }
+                if self
+                    .slow_query_threshold
+                    .is_some_and(|threshold| elapsed >= threshold)
+                {
+                    METRICS.slow_queries.fetch_add(1, Ordering::Relaxed);
+                    let query = query.to_string();
+                    let sql = without_binds(&query);
+                    log::warn!("Slow query took {elapsed:?}: {sql}");
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// SQL text of a formatted Diesel query, without the `-- binds: [...]`
+/// suffix Diesel appends.
+fn without_binds(query: &str) -> &str {
+    query
+        .split_once(" -- binds: ")
+        .map_or(query, |(sql, _)| sql)
+}
+
+fn micros(duration: Duration) -> u64 {
+    u64::try_from(duration.as_micros()).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::DbPoolBuilder;
+    use diesel::connection::SimpleConnection;
+    use diesel::prelude::*;
+
+    #[test]
+    fn counts_queries_slow_queries_and_checkouts() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let pool = DbPoolBuilder::new()
+            .slow_query_threshold(Some(Duration::ZERO))
+            .build(file.path().to_str().unwrap())
+            .unwrap();
+        let before = metrics();
+
+        let mut conn = timed_get(&pool).unwrap();
+        conn.batch_execute("CREATE TABLE items (id INTEGER PRIMARY KEY)")
+            .unwrap();
+        diesel::sql_query("INSERT INTO items DEFAULT VALUES")
+            .execute(&mut conn)
+            .unwrap();
+        assert!(
+            diesel::sql_query("SELECT * FROM missing")
+                .execute(&mut conn)
+                .is_err()
+        );
+
+        let after = metrics();
+        assert!(after.queries >= before.queries + 3);
+        assert!(after.slow_queries >= before.slow_queries + 3);
+        assert!(after.failed_queries > before.failed_queries);
+        assert!(after.connection_checkouts > before.connection_checkouts);
+    }
+
+    #[test]
+    fn default_pools_count_queries() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let pool = DbPoolBuilder::new()
+            .build(file.path().to_str().unwrap())
+            .unwrap();
+        let mut conn = pool.get().unwrap();
+        let before = metrics();
+
+        diesel::sql_query("SELECT 1").execute(&mut conn).unwrap();
+
+        assert!(metrics().queries > before.queries);
+    }
+
+    #[test]
+    fn logged_sql_has_no_bind_values() {
+        let query = diesel::sql_query("SELECT * FROM users WHERE email = ?")
+            .bind::<diesel::sql_types::Text, _>("secret@example.com");
+        let formatted = diesel::debug_query::<diesel::sqlite::Sqlite, _>(&query).to_string();
+
+        assert!(formatted.contains("secret@example.com"));
+        assert_eq!(
+            without_binds(&formatted),
+            "SELECT * FROM users WHERE email = ?"
+        );
+    }
+}
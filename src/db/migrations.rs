@@ -0,0 +1,162 @@
+//! Embedded schema migrations.
+//!
+//! Services embed their `migrations` directory with [`embed_migrations!`] and
+//! call [`run_pending_migrations`] on startup:
+//!
+//! ```ignore
+//! use pushkind_common::db::migrations::{EmbeddedMigrations, embed_migrations, run_pending_migrations};
+//!
+//! pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+//!
+//! let pool = establish_connection_pool(&database_url)?;
+//! run_pending_migrations(&pool, &MIGRATIONS)?;
+//! ```
+//!
+//! Startup fails with [`MigrationError::Drift`] when the database contains
+//! migrations that the binary does not know about, which usually means an
+//! older build is running against a newer schema.
+
+use diesel::migration::{Migration, MigrationSource};
+use diesel::sqlite::{Sqlite, SqliteConnection};
+use diesel_migrations::MigrationHarness;
+use thiserror::Error;
+
+pub use diesel_migrations::{EmbeddedMigrations, embed_migrations};
+
+use crate::db::{DbPool, get_connection};
+
+/// Errors produced while inspecting or applying migrations.
+#[derive(Debug, Error)]
+pub enum MigrationError {
+    #[error("database has migrations unknown to this build: {}", .0.join(", "))]
+    Drift(Vec<String>),
+
+    #[error("database connection error: {0}")]
+    Connection(#[from] diesel::r2d2::PoolError),
+
+    #[error("migration failed: {0}")]
+    Migration(#[from] Box<dyn std::error::Error + Send + Sync>),
+}
+
+/// Schema state of a database compared to a migration source.
+///
+/// Versions are sorted in ascending order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MigrationStatus {
+    /// Migrations from the source that are already applied.
+    pub applied: Vec<String>,
+    /// Migrations from the source that still have to run.
+    pub pending: Vec<String>,
+    /// Applied migrations that are missing from the source.
+    pub unknown: Vec<String>,
+}
+
+impl MigrationStatus {
+    /// Whether the database contains migrations the source does not know.
+    pub fn has_drift(&self) -> bool {
+        !self.unknown.is_empty()
+    }
+}
+
+/// Compare the applied migrations of `conn` with `source`.
+pub fn migration_status<S>(
+    conn: &mut SqliteConnection,
+    source: &S,
+) -> Result<MigrationStatus, MigrationError>
+where
+    S: MigrationSource<Sqlite>,
+{
+    let known = source.migrations()?;
+    Ok(status_of(conn, &known)?.0)
+}
+
+/// Apply every pending migration of `source` and return their versions.
+///
+/// Nothing is applied when the database shows drift. A summary of the run is
+/// logged at `info` level.
+pub fn run_pending_migrations<S>(pool: &DbPool, source: &S) -> Result<Vec<String>, MigrationError>
+where
+    S: MigrationSource<Sqlite>,
+{
+    let mut conn = get_connection(pool)?;
+    run_pending_migrations_on(&mut conn, source)
+}
+
+/// Connection-level variant of [`run_pending_migrations`].
+pub fn run_pending_migrations_on<S>(
+    conn: &mut SqliteConnection,
+    source: &S,
+) -> Result<Vec<String>, MigrationError>
+where
+    S: MigrationSource<Sqlite>,
+{
+    let known = source.migrations()?;
+    let (status, pending) = status_of(conn, &known)?;
+
+    if status.has_drift() {
+        log::error!(
+            "Refusing to start: database has migrations unknown to this build: {}",
+            status.unknown.join(", ")
+        );
+        return Err(MigrationError::Drift(status.unknown));
+    }
+
+    for migration in &pending {
+        conn.run_migration(migration.as_ref())?;
+    }
+
+    if status.pending.is_empty() {
+        log::info!(
+            "Database schema is up to date ({} migrations applied)",
+            status.applied.len()
+        );
+    } else {
+        log::info!(
+            "Applied {} pending migrations ({}); {} were already applied",
+            status.pending.len(),
+            status.pending.join(", "),
+            status.applied.len()
+        );
+    }
+
+    Ok(status.pending)
+}
+
+type SqliteMigration = Box<dyn Migration<Sqlite>>;
+
+/// Build the status and collect the pending migrations in version order.
+fn status_of<'m>(
+    conn: &mut SqliteConnection,
+    known: &'m [SqliteMigration],
+) -> Result<(MigrationStatus, Vec<&'m SqliteMigration>), MigrationError> {
+    let applied = conn
+        .applied_migrations()?
+        .into_iter()
+        .map(|version| version.to_string())
+        .collect::<Vec<_>>();
+
+    let mut status = MigrationStatus::default();
+    let mut pending = Vec::new();
+    for migration in known {
+        let version = migration.name().version().to_string();
+        if applied.contains(&version) {
+            status.applied.push(version);
+        } else {
+            pending.push(migration);
+        }
+    }
+    status.unknown = applied
+        .into_iter()
+        .filter(|version| !status.applied.contains(version))
+        .collect();
+
+    status.applied.sort();
+    status.unknown.sort();
+    pending.sort_by_key(|migration| migration.name().version().to_string());
+    status.pending = pending
+        .iter()
+        .map(|migration| migration.name().version().to_string())
+        .collect();
+
+    Ok((status, pending))
+}
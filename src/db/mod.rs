@@ -0,0 +1,203 @@
+//! Database connection helpers.
+//!
+//! This module provides a small wrapper around the Diesel connection pool and
+//! utilities to establish a connection to the SQLite database used in the
+//! application. Pools are configured with [`DbPoolBuilder`] and schema
+//! migrations are handled by [`migrations`]. With the `actix` feature,
+//! `executor` runs Diesel work from async handlers. Write transactions that
+//! retry lock contention live in [`transaction`], and [`SplitDbPool`] separates
+//! the single writer from read-only connections. Snapshots are taken with
+//! [`backup`], and query and pool wait metrics are collected by
+//! [`instrumentation`]. [`unicode`] registers Unicode-aware SQL
+//! functions and a Russian collation.
+
+pub mod backup;
+#[cfg(feature = "actix")]
+pub mod executor;
+pub mod instrumentation;
+pub mod migrations;
+pub mod pool;
+pub mod split;
+pub mod transaction;
+pub mod unicode;
+
+use std::time::Duration;
+
+use diesel::connection::{Connection, SimpleConnection};
+use diesel::r2d2::{ConnectionManager, CustomizeConnection, Pool, PoolError, PooledConnection};
+use diesel::sqlite::SqliteConnection;
+use log;
+use serde::Deserialize;
+
+pub use pool::{DatabaseConfig, DbPoolBuilder};
+pub use split::{
+    ReadConnection, ReadPool, SplitDbPool, Writable, WriteConnection, WritePool, WriteTx,
+};
+
+pub type DbPool = Pool<ConnectionManager<SqliteConnection>>;
+pub type DbConnection = PooledConnection<ConnectionManager<SqliteConnection>>;
+
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+/// Options that are applied each time a connection is acquired from the pool.
+///
+/// New settings are added over time, so the struct cannot be built with a
+/// literal outside this crate. Configure connections through
+/// [`DbPoolBuilder`], or start from [`ConnectionOptions::default`] and assign
+/// the fields to change.
+pub struct ConnectionOptions {
+    /// Enable Write Ahead Logging mode for SQLite.
+    pub enable_wal: bool,
+    /// Enforce foreign key checks for SQLite.
+    pub enable_foreign_keys: bool,
+    /// Timeout to wait for a locked database.
+    pub busy_timeout: Option<Duration>,
+    /// `PRAGMA synchronous` level. `None` uses `NORMAL`, which is durable
+    /// enough in WAL mode, when [`enable_wal`](Self::enable_wal) is set and
+    /// keeps SQLite's default (`FULL`) otherwise.
+    pub synchronous: Option<Synchronous>,
+    /// `PRAGMA cache_size`; positive values are pages, negative values KiB.
+    pub cache_size: Option<i64>,
+    /// `PRAGMA mmap_size` in bytes.
+    pub mmap_size: Option<u64>,
+    /// `PRAGMA temp_store` location.
+    pub temp_store: Option<TempStore>,
+    /// Reject writes on the connection with `PRAGMA query_only`.
+    pub query_only: bool,
+    /// Log the SQL text, without bind values, of queries slower than this
+    /// threshold at `WARN` level. `None`, the default, disables the log;
+    /// queries are counted and timed either way.
+    pub slow_query_threshold: Option<Duration>,
+    /// Register the functions and collation of [`unicode`] and the
+    /// `fuzzy_similarity` function of
+    /// [`FuzzySearch`](crate::repository::fts::FuzzySearch).
+    pub unicode_functions: bool,
+}
+
+impl Default for ConnectionOptions {
+    fn default() -> Self {
+        Self {
+            enable_wal: true,
+            enable_foreign_keys: true,
+            busy_timeout: Some(Duration::from_secs(30)),
+            synchronous: None,
+            cache_size: None,
+            mmap_size: None,
+            temp_store: None,
+            query_only: false,
+            slow_query_threshold: None,
+            unicode_functions: false,
+        }
+    }
+}
+
+/// Values of `PRAGMA synchronous`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Synchronous {
+    Off,
+    Normal,
+    Full,
+    Extra,
+}
+
+impl Synchronous {
+    fn as_sql(self) -> &'static str {
+        match self {
+            Synchronous::Off => "OFF",
+            Synchronous::Normal => "NORMAL",
+            Synchronous::Full => "FULL",
+            Synchronous::Extra => "EXTRA",
+        }
+    }
+}
+
+/// Values of `PRAGMA temp_store`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TempStore {
+    Default,
+    File,
+    Memory,
+}
+
+impl TempStore {
+    fn as_sql(self) -> &'static str {
+        match self {
+            TempStore::Default => "DEFAULT",
+            TempStore::File => "FILE",
+            TempStore::Memory => "MEMORY",
+        }
+    }
+}
+
+impl ConnectionOptions {
+    /// Pragmas executed for every new connection.
+    fn pragmas(&self) -> String {
+        let mut sql = String::new();
+        if self.enable_wal {
+            sql.push_str("PRAGMA journal_mode = WAL;");
+        }
+        let synchronous = match self.synchronous {
+            None if self.enable_wal => Some(Synchronous::Normal),
+            level => level,
+        };
+        if let Some(level) = synchronous {
+            sql.push_str(&format!("PRAGMA synchronous = {};", level.as_sql()));
+        }
+        if self.enable_foreign_keys {
+            sql.push_str("PRAGMA foreign_keys = ON;");
+        }
+        if let Some(d) = self.busy_timeout {
+            sql.push_str(&format!("PRAGMA busy_timeout = {};", d.as_millis()));
+        }
+        if let Some(pages) = self.cache_size {
+            sql.push_str(&format!("PRAGMA cache_size = {pages};"));
+        }
+        if let Some(bytes) = self.mmap_size {
+            sql.push_str(&format!("PRAGMA mmap_size = {bytes};"));
+        }
+        if let Some(store) = self.temp_store {
+            sql.push_str(&format!("PRAGMA temp_store = {};", store.as_sql()));
+        }
+        if self.query_only {
+            sql.push_str("PRAGMA query_only = ON;");
+        }
+        sql
+    }
+}
+
+impl CustomizeConnection<SqliteConnection, diesel::r2d2::Error> for ConnectionOptions {
+    fn on_acquire(&self, conn: &mut SqliteConnection) -> Result<(), diesel::r2d2::Error> {
+        conn.batch_execute(&self.pragmas())
+            .map_err(diesel::r2d2::Error::QueryError)?;
+        if self.unicode_functions {
+            unicode::register(conn).map_err(diesel::r2d2::Error::QueryError)?;
+            crate::repository::fts::fuzzy::register(conn)
+                .map_err(diesel::r2d2::Error::QueryError)?;
+        }
+        conn.set_instrumentation(instrumentation::QueryLogger::new(self.slow_query_threshold));
+        Ok(())
+    }
+}
+
+/// Create a Diesel connection pool for the given database URL.
+///
+/// Uses the defaults of [`DbPoolBuilder`]; build the pool through it to tune
+/// pool sizes, timeouts or pragmas.
+pub fn establish_connection_pool(database_url: &str) -> Result<DbPool, PoolError> {
+    DbPoolBuilder::new().build(database_url)
+}
+
+/// Retrieve a connection from the pool
+///
+/// The time spent waiting is recorded in [`instrumentation::metrics`].
+pub fn get_connection(pool: &DbPool) -> Result<DbConnection, PoolError> {
+    match instrumentation::timed_get(pool) {
+        Ok(conn) => Ok(conn),
+        Err(e) => {
+            log::error!("Failed to get connection from pool: {e}");
+            Err(e)
+        }
+    }
+}
@@ -0,0 +1,314 @@
+//! Configurable construction of [`DbPool`].
+//!
+//! [`DbPoolBuilder`] exposes the r2d2 pool settings together with every
+//! [`ConnectionOptions`] field. Services that read their settings from a
+//! config file deserialize a [`DatabaseConfig`] and convert it:
+//!
+//! ```ignore
+//! let config: DatabaseConfig = settings.get("database")?;
+//! let pool = DbPoolBuilder::from(&config).build(&database_url)?;
+//! ```
+
+use std::time::Duration;
+
+use diesel::r2d2::{ConnectionManager, Pool, PoolError};
+use diesel::sqlite::SqliteConnection;
+use serde::Deserialize;
+
+use crate::db::{ConnectionOptions, DbPool, Synchronous, TempStore};
+
+/// Default maximum number of pooled connections.
+pub const DEFAULT_MAX_SIZE: u32 = 10;
+/// Default time to wait for a free connection.
+pub const DEFAULT_CONNECTION_TIMEOUT: Duration = Duration::from_secs(30);
+/// Default time after which idle connections are closed.
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Builder for a SQLite connection pool.
+#[derive(Clone, Debug)]
+pub struct DbPoolBuilder {
+    options: ConnectionOptions,
+    max_size: u32,
+    min_idle: Option<u32>,
+    connection_timeout: Duration,
+    idle_timeout: Option<Duration>,
+}
+
+impl Default for DbPoolBuilder {
+    fn default() -> Self {
+        Self {
+            options: ConnectionOptions::default(),
+            max_size: DEFAULT_MAX_SIZE,
+            min_idle: None,
+            connection_timeout: DEFAULT_CONNECTION_TIMEOUT,
+            idle_timeout: Some(DEFAULT_IDLE_TIMEOUT),
+        }
+    }
+}
+
+impl DbPoolBuilder {
+    /// Builder with the same settings as [`establish_connection_pool`].
+    ///
+    /// [`establish_connection_pool`]: crate::db::establish_connection_pool
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace all connection options at once.
+    pub fn connection_options(mut self, options: ConnectionOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Maximum number of connections managed by the pool.
+    pub fn max_size(mut self, max_size: u32) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Minimum number of idle connections kept open; `None` keeps `max_size`.
+    pub fn min_idle(mut self, min_idle: Option<u32>) -> Self {
+        self.min_idle = min_idle;
+        self
+    }
+
+    /// Time to wait for a free connection before failing.
+    pub fn connection_timeout(mut self, timeout: Duration) -> Self {
+        self.connection_timeout = timeout;
+        self
+    }
+
+    /// Close connections idle for longer than `timeout`; `None` keeps them.
+    pub fn idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Enable Write Ahead Logging mode.
+    pub fn enable_wal(mut self, enable: bool) -> Self {
+        self.options.enable_wal = enable;
+        self
+    }
+
+    /// Enforce foreign key checks.
+    pub fn enable_foreign_keys(mut self, enable: bool) -> Self {
+        self.options.enable_foreign_keys = enable;
+        self
+    }
+
+    /// Time to wait for a locked database.
+    pub fn busy_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.options.busy_timeout = timeout;
+        self
+    }
+
+    /// `PRAGMA synchronous` level; `None` picks `NORMAL` in WAL mode and
+    /// SQLite's default otherwise.
+    pub fn synchronous(mut self, level: Option<Synchronous>) -> Self {
+        self.options.synchronous = level;
+        self
+    }
+
+    /// `PRAGMA cache_size`; positive values are pages, negative values KiB.
+    pub fn cache_size(mut self, cache_size: Option<i64>) -> Self {
+        self.options.cache_size = cache_size;
+        self
+    }
+
+    /// `PRAGMA mmap_size` in bytes.
+    pub fn mmap_size(mut self, mmap_size: Option<u64>) -> Self {
+        self.options.mmap_size = mmap_size;
+        self
+    }
+
+    /// `PRAGMA temp_store` location.
+    pub fn temp_store(mut self, temp_store: Option<TempStore>) -> Self {
+        self.options.temp_store = temp_store;
+        self
+    }
+
+    /// Log queries slower than `threshold`, such as
+    /// [`DEFAULT_SLOW_QUERY_THRESHOLD`](crate::db::instrumentation::DEFAULT_SLOW_QUERY_THRESHOLD);
+    /// `None`, the default, disables the log. Queries are counted either way.
+    pub fn slow_query_threshold(mut self, threshold: Option<Duration>) -> Self {
+        self.options.slow_query_threshold = threshold;
+        self
+    }
+
+    /// Reject writes with `PRAGMA query_only`.
+    pub fn query_only(mut self, enable: bool) -> Self {
+        self.options.query_only = enable;
+        self
+    }
+
+    /// Register Unicode-aware SQL functions, the Russian collation and the
+    /// `fuzzy_similarity` function, see [`unicode`](crate::db::unicode) and
+    /// [`fuzzy`](crate::repository::fts::fuzzy).
+    pub fn unicode_functions(mut self, enable: bool) -> Self {
+        self.options.unicode_functions = enable;
+        self
+    }
+
+    /// Options applied to every connection of the pool.
+    pub fn options(&self) -> &ConnectionOptions {
+        &self.options
+    }
+
+    /// Create the pool for `database_url`.
+    pub fn build(self, database_url: &str) -> Result<DbPool, PoolError> {
+        let manager = ConnectionManager::<SqliteConnection>::new(database_url);
+        Pool::builder()
+            .max_size(self.max_size)
+            .min_idle(self.min_idle)
+            .connection_timeout(self.connection_timeout)
+            .idle_timeout(self.idle_timeout)
+            .connection_customizer(Box::new(self.options))
+            .build(manager)
+    }
+}
+
+/// Deserializable pool settings, for example the `[database]` section of a
+/// service config file. Missing keys fall back to the builder defaults.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct DatabaseConfig {
+    pub max_size: u32,
+    pub min_idle: Option<u32>,
+    pub connection_timeout_secs: u64,
+    pub idle_timeout_secs: Option<u64>,
+    pub enable_wal: bool,
+    pub enable_foreign_keys: bool,
+    pub busy_timeout_ms: Option<u64>,
+    pub synchronous: Option<Synchronous>,
+    pub cache_size: Option<i64>,
+    pub mmap_size: Option<u64>,
+    pub temp_store: Option<TempStore>,
+    pub query_only: bool,
+    pub slow_query_threshold_ms: Option<u64>,
+    pub unicode_functions: bool,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        let options = ConnectionOptions::default();
+        Self {
+            max_size: DEFAULT_MAX_SIZE,
+            min_idle: None,
+            connection_timeout_secs: DEFAULT_CONNECTION_TIMEOUT.as_secs(),
+            idle_timeout_secs: Some(DEFAULT_IDLE_TIMEOUT.as_secs()),
+            enable_wal: options.enable_wal,
+            enable_foreign_keys: options.enable_foreign_keys,
+            busy_timeout_ms: options
+                .busy_timeout
+                .map(|d| d.as_millis().try_into().unwrap_or(u64::MAX)),
+            synchronous: options.synchronous,
+            cache_size: options.cache_size,
+            mmap_size: options.mmap_size,
+            temp_store: options.temp_store,
+            query_only: options.query_only,
+            slow_query_threshold_ms: options
+                .slow_query_threshold
+                .map(|d| d.as_millis().try_into().unwrap_or(u64::MAX)),
+            unicode_functions: options.unicode_functions,
+        }
+    }
+}
+
+impl From<&DatabaseConfig> for DbPoolBuilder {
+    fn from(config: &DatabaseConfig) -> Self {
+        DbPoolBuilder::new()
+            .max_size(config.max_size)
+            .min_idle(config.min_idle)
+            .connection_timeout(Duration::from_secs(config.connection_timeout_secs))
+            .idle_timeout(config.idle_timeout_secs.map(Duration::from_secs))
+            .connection_options(ConnectionOptions {
+                enable_wal: config.enable_wal,
+                enable_foreign_keys: config.enable_foreign_keys,
+                busy_timeout: config.busy_timeout_ms.map(Duration::from_millis),
+                synchronous: config.synchronous,
+                cache_size: config.cache_size,
+                mmap_size: config.mmap_size,
+                temp_store: config.temp_store,
+                query_only: config.query_only,
+                slow_query_threshold: config.slow_query_threshold_ms.map(Duration::from_millis),
+                unicode_functions: config.unicode_functions,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::prelude::*;
+    use diesel::sql_types::BigInt;
+
+    #[derive(QueryableByName)]
+    struct Value {
+        #[diesel(sql_type = BigInt)]
+        value: i64,
+    }
+
+    fn pragma(pool: &DbPool, name: &str, column: &str) -> i64 {
+        let mut conn = pool.get().unwrap();
+        diesel::sql_query(format!("SELECT {column} AS value FROM pragma_{name}"))
+            .get_result::<Value>(&mut conn)
+            .unwrap()
+            .value
+    }
+
+    #[test]
+    fn builder_applies_pool_settings_and_pragmas() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let pool = DbPoolBuilder::new()
+            .max_size(3)
+            .min_idle(Some(1))
+            .synchronous(Some(Synchronous::Full))
+            .cache_size(Some(-4096))
+            .temp_store(Some(TempStore::Memory))
+            .busy_timeout(Some(Duration::from_millis(1500)))
+            .build(file.path().to_str().unwrap())
+            .unwrap();
+
+        assert_eq!(pool.max_size(), 3);
+        assert_eq!(pragma(&pool, "synchronous", "synchronous"), 2);
+        assert_eq!(pragma(&pool, "cache_size", "cache_size"), -4096);
+        assert_eq!(pragma(&pool, "temp_store", "temp_store"), 2);
+        assert_eq!(pragma(&pool, "busy_timeout", "timeout"), 1500);
+        assert_eq!(pragma(&pool, "foreign_keys", "foreign_keys"), 1);
+    }
+
+    #[test]
+    fn config_deserializes_with_defaults() {
+        let config: DatabaseConfig = serde_json::from_str(
+            r#"{"max_size": 4, "synchronous": "extra", "temp_store": "memory", "busy_timeout_ms": 250, "query_only": true}"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.max_size, 4);
+        assert_eq!(config.connection_timeout_secs, 30);
+        assert!(config.enable_wal);
+
+        let builder = DbPoolBuilder::from(&config);
+        let options = builder.options();
+        assert_eq!(options.synchronous, Some(Synchronous::Extra));
+        assert_eq!(options.temp_store, Some(TempStore::Memory));
+        assert_eq!(options.busy_timeout, Some(Duration::from_millis(250)));
+        assert!(options.query_only);
+    }
+
+    #[test]
+    fn synchronous_normal_only_applies_in_wal_mode() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let wal = DbPoolBuilder::new()
+            .build(file.path().to_str().unwrap())
+            .unwrap();
+        assert_eq!(pragma(&wal, "synchronous", "synchronous"), 1);
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let rollback = DbPoolBuilder::new()
+            .enable_wal(false)
+            .build(file.path().to_str().unwrap())
+            .unwrap();
+        assert_eq!(pragma(&rollback, "synchronous", "synchronous"), 2);
+    }
+}
@@ -0,0 +1,289 @@
+//! Separate writer and reader pools for SQLite in WAL mode.
+//!
+//! SQLite allows a single writer at a time, while WAL readers never block.
+//! [`SplitDbPool`] keeps exactly one writable connection, so writers queue on
+//! the pool instead of failing with `database is locked`, and a configurable
+//! number of connections opened with `PRAGMA query_only`.
+//!
+//! Handles are typed: write helpers such as
+//! [`write_transaction`](crate::db::transaction::write_transaction) and
+//! `outbox::enqueue` take a [`Writable`] handle. A [`WriteConnection`], the
+//! [`DbConnection`] of a plain [`DbPool`] and the [`WriteTx`] of an open write
+//! transaction are writable, while neither a [`ReadConnection`] nor the bare
+//! `SqliteConnection` it dereferences to can be passed to them. Functions that
+//! only read take `&mut SqliteConnection` and accept every handle.
+//!
+//! All handles still dereference to a mutable `SqliteConnection`, because
+//! Diesel needs one even to read, so a query written by hand can be run
+//! through a [`ReadConnection`]. Such writes are rejected at run time by
+//! `PRAGMA query_only`.
+//!
+//! ```ignore
+//! let db = SplitDbPool::build(DbPoolBuilder::new(), &database_url, 4)?;
+//!
+//! fn rename(conn: &mut impl Writable, id: i32, name: &str) -> RepositoryResult<()> { .. }
+//! fn find(conn: &mut SqliteConnection, id: i32) -> RepositoryResult<Customer> { .. }
+//!
+//! rename(&mut db.writer().get()?, id, "Анна")?;
+//! find(&mut db.reader().get()?, id)?;
+//! ```
+
+use std::ops::{Deref, DerefMut};
+
+use diesel::r2d2::PoolError;
+use diesel::sqlite::SqliteConnection;
+
+use crate::db::instrumentation::timed_get;
+use crate::db::{DbConnection, DbPool, DbPoolBuilder};
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// A connection that may be written to.
+///
+/// Implemented for [`WriteConnection`], [`DbConnection`] and [`WriteTx`], but
+/// not for [`ReadConnection`], so a reader cannot be passed to write helpers:
+///
+/// ```compile_fail
+/// # use pushkind_common::db::SplitDbPool;
+/// # use pushkind_common::db::transaction::{RetryPolicy, write_transaction};
+/// # fn rename(db: &SplitDbPool) {
+/// let mut reader = db.reader().get().unwrap();
+/// write_transaction(&mut reader, &RetryPolicy::default(), |_| Ok(())).unwrap();
+/// # }
+/// ```
+///
+/// Neither can the connection it dereferences to:
+///
+/// ```compile_fail
+/// # use pushkind_common::db::SplitDbPool;
+/// # use pushkind_common::db::transaction::{RetryPolicy, write_transaction};
+/// # fn rename(db: &SplitDbPool) {
+/// let mut reader = db.reader().get().unwrap();
+/// write_transaction(&mut *reader, &RetryPolicy::default(), |_| Ok(())).unwrap();
+/// # }
+/// ```
+///
+/// The trait is sealed and cannot be implemented outside this crate.
+pub trait Writable: sealed::Sealed {
+    /// The connection to write through.
+    fn writable(&mut self) -> &mut SqliteConnection;
+}
+
+impl sealed::Sealed for WriteConnection {}
+
+impl Writable for WriteConnection {
+    fn writable(&mut self) -> &mut SqliteConnection {
+        &mut self.0
+    }
+}
+
+impl sealed::Sealed for DbConnection {}
+
+impl Writable for DbConnection {
+    fn writable(&mut self) -> &mut SqliteConnection {
+        self
+    }
+}
+
+impl sealed::Sealed for WriteTx<'_> {}
+
+impl Writable for WriteTx<'_> {
+    fn writable(&mut self) -> &mut SqliteConnection {
+        self.0
+    }
+}
+
+/// Pool of read-only connections.
+#[derive(Clone)]
+pub struct ReadPool(DbPool);
+
+/// Pool holding the single writable connection.
+#[derive(Clone)]
+pub struct WritePool(DbPool);
+
+/// A pooled connection that rejects writes.
+///
+/// Writes fail with a `readonly database` error from SQLite; see the module
+/// documentation.
+pub struct ReadConnection(DbConnection);
+
+/// The pooled writable connection; held by one caller at a time.
+pub struct WriteConnection(DbConnection);
+
+/// The connection of an open write transaction.
+///
+/// Passed to the closures of
+/// [`write_transaction`](crate::db::transaction::write_transaction) and
+/// [`savepoint`](crate::db::transaction::savepoint), so write helpers can be
+/// called inside them.
+pub struct WriteTx<'a>(&'a mut SqliteConnection);
+
+impl<'a> WriteTx<'a> {
+    pub(crate) fn new(conn: &'a mut SqliteConnection) -> Self {
+        Self(conn)
+    }
+}
+
+/// A writer pool and a reader pool for the same database.
+#[derive(Clone)]
+pub struct SplitDbPool {
+    writer: WritePool,
+    reader: ReadPool,
+}
+
+impl SplitDbPool {
+    /// Build both pools for `database_url` from the same settings.
+    ///
+    /// The writer pool is limited to one connection and the reader pool to
+    /// `readers` connections with `query_only` enabled. The writer is opened
+    /// first so that pragmas like `journal_mode = WAL` take effect before any
+    /// reader connects.
+    pub fn build(
+        builder: DbPoolBuilder,
+        database_url: &str,
+        readers: u32,
+    ) -> Result<Self, PoolError> {
+        let writer = builder
+            .clone()
+            .max_size(1)
+            .min_idle(Some(1))
+            .query_only(false)
+            .build(database_url)?;
+        let reader = builder
+            .max_size(readers.max(1))
+            .query_only(true)
+            .build(database_url)?;
+
+        Ok(Self {
+            writer: WritePool(writer),
+            reader: ReadPool(reader),
+        })
+    }
+
+    /// Handle for obtaining the writable connection.
+    pub fn writer(&self) -> &WritePool {
+        &self.writer
+    }
+
+    /// Handle for obtaining read-only connections.
+    pub fn reader(&self) -> &ReadPool {
+        &self.reader
+    }
+}
+
+impl ReadPool {
+    /// Check out a read-only connection.
+    pub fn get(&self) -> Result<ReadConnection, PoolError> {
+        timed_get(&self.0).map(ReadConnection)
+    }
+
+    /// The underlying pool.
+    pub fn pool(&self) -> &DbPool {
+        &self.0
+    }
+}
+
+impl WritePool {
+    /// Check out the writable connection, waiting while another caller holds
+    /// it.
+    pub fn get(&self) -> Result<WriteConnection, PoolError> {
+        timed_get(&self.0).map(WriteConnection)
+    }
+
+    /// The underlying pool.
+    pub fn pool(&self) -> &DbPool {
+        &self.0
+    }
+}
+
+impl Deref for ReadConnection {
+    type Target = SqliteConnection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for ReadConnection {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl Deref for WriteConnection {
+    type Target = SqliteConnection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for WriteConnection {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl Deref for WriteTx<'_> {
+    type Target = SqliteConnection;
+
+    fn deref(&self) -> &Self::Target {
+        self.0
+    }
+}
+
+impl DerefMut for WriteTx<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::connection::SimpleConnection;
+    use std::time::Duration;
+
+    fn split() -> (tempfile::NamedTempFile, SplitDbPool) {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let db = SplitDbPool::build(
+            DbPoolBuilder::new().connection_timeout(Duration::from_millis(200)),
+            file.path().to_str().unwrap(),
+            2,
+        )
+        .unwrap();
+        (file, db)
+    }
+
+    #[test]
+    fn readers_see_writes_but_cannot_write() {
+        let (_file, db) = split();
+        let mut writer = db.writer().get().unwrap();
+        writer
+            .batch_execute(
+                "CREATE TABLE items (id INTEGER PRIMARY KEY); INSERT INTO items DEFAULT VALUES;",
+            )
+            .unwrap();
+
+        let mut reader = db.reader().get().unwrap();
+        reader.batch_execute("SELECT * FROM items").unwrap();
+        let error = reader
+            .batch_execute("INSERT INTO items DEFAULT VALUES")
+            .unwrap_err();
+        assert!(error.to_string().contains("readonly"));
+    }
+
+    #[test]
+    fn writer_is_exclusive() {
+        let (_file, db) = split();
+
+        let held = db.writer().get().unwrap();
+        assert!(db.writer().get().is_err());
+        drop(held);
+        assert!(db.writer().get().is_ok());
+        assert_eq!(db.writer().pool().max_size(), 1);
+        assert_eq!(db.reader().pool().max_size(), 2);
+    }
+}
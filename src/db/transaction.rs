@@ -0,0 +1,250 @@
+//! Transactions that cope with SQLite write contention.
+//!
+//! A deferred transaction that reads first and writes later has to upgrade
+//! its lock, and SQLite fails the upgrade with `database is locked` without
+//! consulting `busy_timeout`. [`write_transaction`] avoids the upgrade by
+//! starting with `BEGIN IMMEDIATE` and retries [`RepositoryError::Busy`]
+//! failures with jittered exponential backoff:
+//!
+//! ```ignore
+//! write_transaction(&mut conn, &RetryPolicy::default(), |conn| {
+//!     let order = insert_order(conn, &new_order)?;
+//!     savepoint(conn, |conn| reserve_stock(conn, &order))
+//!         .or_else(|_| mark_backordered(conn, &order))?;
+//!     Ok(order)
+//! })?;
+//! ```
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use diesel::connection::{AnsiTransactionManager, Connection, TransactionManager};
+use diesel::sqlite::SqliteConnection;
+
+use crate::db::{Writable, WriteTx};
+use crate::repository::errors::{RepositoryError, RepositoryResult};
+
+/// How often and how long [`write_transaction`] retries busy failures.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Total number of attempts including the first one.
+    pub max_attempts: u32,
+    /// Delay before the first retry; doubled for every further retry.
+    pub base_delay: Duration,
+    /// Upper bound for a single delay.
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(500),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Never retry.
+    pub fn no_retry() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Delay before retry number `retry` (starting at 1).
+    ///
+    /// The result is picked uniformly between half and the full exponential
+    /// delay so competing writers do not retry in lockstep.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let exponential = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(self.max_delay);
+        let half = exponential / 2;
+        let jitter = random_fraction();
+        half + half.mul_f64(jitter)
+    }
+}
+
+/// Run `f` in a `BEGIN IMMEDIATE` transaction, retrying busy failures.
+///
+/// `f` is re-run from scratch on every attempt, so it must not have side
+/// effects outside the database. When a transaction is already open on
+/// `conn`, `f` runs in a savepoint instead and is not retried, because the
+/// enclosing transaction has to be restarted as a whole.
+///
+/// `conn` must be [`Writable`], so a [`ReadConnection`](crate::db::ReadConnection)
+/// is rejected at compile time. `f` receives the transaction as a [`WriteTx`],
+/// which can be passed on to other write helpers.
+pub fn write_transaction<C, T, F>(
+    conn: &mut C,
+    policy: &RetryPolicy,
+    mut f: F,
+) -> RepositoryResult<T>
+where
+    C: Writable + ?Sized,
+    F: FnMut(&mut WriteTx<'_>) -> RepositoryResult<T>,
+{
+    let conn = conn.writable();
+    if in_transaction(conn)? {
+        return conn.transaction(|conn| f(&mut WriteTx::new(conn)));
+    }
+
+    let mut attempt = 1;
+    loop {
+        match conn.immediate_transaction(|conn| f(&mut WriteTx::new(conn))) {
+            Err(err) if err.is_busy() && attempt < policy.max_attempts => {
+                let delay = policy.delay(attempt);
+                log::warn!("Database busy (attempt {attempt}), retrying in {delay:?}: {err}");
+                std::thread::sleep(delay);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Run `f` in a deferred transaction, suitable for consistent reads.
+pub fn read_transaction<T, F>(conn: &mut SqliteConnection, f: F) -> RepositoryResult<T>
+where
+    F: FnOnce(&mut SqliteConnection) -> RepositoryResult<T>,
+{
+    conn.transaction(f)
+}
+
+/// Run `f` in a savepoint of the currently open transaction.
+///
+/// An error returned by `f` rolls back only the work done inside the
+/// savepoint; the enclosing transaction stays usable.
+pub fn savepoint<C, T, F>(conn: &mut C, f: F) -> RepositoryResult<T>
+where
+    C: Writable + ?Sized,
+    F: FnOnce(&mut WriteTx<'_>) -> RepositoryResult<T>,
+{
+    let conn = conn.writable();
+    if !in_transaction(conn)? {
+        return Err(RepositoryError::database(
+            "Savepoint requires an open transaction",
+        ));
+    }
+    conn.transaction(|conn| f(&mut WriteTx::new(conn)))
+}
+
+fn in_transaction(conn: &mut SqliteConnection) -> RepositoryResult<bool> {
+    Ok(AnsiTransactionManager::transaction_manager_status_mut(conn)
+        .transaction_depth()?
+        .is_some())
+}
+
+/// Random value in `[0, 1)` without pulling in a random number generator.
+fn random_fraction() -> f64 {
+    let bits = RandomState::new().build_hasher().finish() >> 11;
+    bits as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::DbPoolBuilder;
+    use crate::testing::{TestDb, count_rows};
+    use diesel::connection::SimpleConnection;
+    use diesel::prelude::*;
+
+    fn count(conn: &mut SqliteConnection) -> i64 {
+        count_rows(conn, "items")
+    }
+
+    fn database() -> TestDb {
+        TestDb::builder()
+            .pool_builder(DbPoolBuilder::new().busy_timeout(None))
+            .seed(|conn| {
+                conn.batch_execute(
+                    "CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT NOT NULL)",
+                )?;
+                Ok(())
+            })
+            .build()
+    }
+
+    fn insert(conn: &mut SqliteConnection, name: &str) -> RepositoryResult<()> {
+        diesel::sql_query("INSERT INTO items (name) VALUES (?)")
+            .bind::<diesel::sql_types::Text, _>(name)
+            .execute(conn)?;
+        Ok(())
+    }
+
+    #[test]
+    fn locked_database_is_classified_as_busy() {
+        let db = database();
+        let pool = db.pool();
+        let mut holder = pool.get().unwrap();
+        holder.batch_execute("BEGIN IMMEDIATE").unwrap();
+
+        let mut conn = db.conn();
+        let error = write_transaction(&mut conn, &RetryPolicy::no_retry(), |conn| {
+            insert(conn, "blocked")
+        })
+        .unwrap_err();
+
+        assert!(matches!(error, RepositoryError::Busy(..)));
+        holder.batch_execute("ROLLBACK").unwrap();
+    }
+
+    #[test]
+    fn retries_until_the_lock_is_released() {
+        let db = database();
+        let pool = db.pool();
+        let mut holder = pool.get().unwrap();
+        holder.batch_execute("BEGIN IMMEDIATE").unwrap();
+        let release = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            holder.batch_execute("COMMIT").unwrap();
+        });
+
+        let mut conn = db.conn();
+        let policy = RetryPolicy {
+            max_attempts: 50,
+            base_delay: Duration::from_millis(5),
+            max_delay: Duration::from_millis(20),
+        };
+        write_transaction(&mut conn, &policy, |conn| insert(conn, "eventually")).unwrap();
+        release.join().unwrap();
+
+        assert_eq!(count(&mut conn), 1);
+    }
+
+    #[test]
+    fn failed_savepoint_keeps_outer_transaction() {
+        let db = database();
+        let mut conn = db.conn();
+
+        write_transaction(&mut conn, &RetryPolicy::default(), |conn| {
+            insert(conn, "kept")?;
+            let inner: RepositoryResult<()> = savepoint(conn, |conn| {
+                insert(conn, "discarded")?;
+                Err(RepositoryError::validation("rejected"))
+            });
+            assert!(inner.is_err());
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(count(&mut conn), 1);
+        assert!(savepoint(&mut conn, |_| Ok(())).is_err());
+    }
+
+    #[test]
+    fn delay_is_bounded_and_jittered() {
+        let policy = RetryPolicy::default();
+
+        for retry in 1..10 {
+            let delay = policy.delay(retry);
+            assert!(delay <= policy.max_delay);
+        }
+        assert!(policy.delay(1) >= policy.base_delay / 2);
+    }
+}
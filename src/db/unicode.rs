@@ -0,0 +1,263 @@
+//! Unicode-aware SQL functions and collation for SQLite.
+//!
+//! SQLite's built-in `lower()`, `LIKE` and `NOCASE` only fold ASCII, so
+//! Cyrillic text is filtered and sorted by raw code points. With
+//! [`ConnectionOptions::unicode_functions`](crate::db::ConnectionOptions)
+//! enabled, every pooled connection gets:
+//!
+//! * `unicode_lower(text)` — full Unicode lowercase;
+//! * `unicode_casefold(text)` — lowercase that also folds `ß` and final `ς`,
+//!   for case-insensitive comparisons;
+//! * `normalize_text(text)` — casefold, `ё` → `е`, trimmed and with runs of
+//!   whitespace collapsed, for matching user input;
+//! * the `RUSSIAN` collation — case-insensitive alphabetical order with `ё`
+//!   right after `е`.
+//!
+//! The functions are deterministic and may be used in indexes. In Diesel
+//! queries use the typed wrappers:
+//!
+//! ```ignore
+//! customers::table
+//!     .filter(normalize_text(customers::name).eq(unicode::normalize(&query)))
+//!     .order(collate_russian(customers::name))
+//!     .load::<Customer>(conn)?;
+//! ```
+//!
+//! Functions on nullable columns return `NULL` for `NULL`; call them with
+//! `column.assume_not_null()` or through raw SQL.
+
+use std::cmp::Ordering;
+
+use diesel::expression::{
+    AppearsOnTable, AsExpression, Expression, SelectableExpression, ValidGrouping,
+};
+use diesel::prelude::*;
+use diesel::query_builder::{AstPass, QueryFragment, QueryId};
+use diesel::sql_types::{Nullable, Text};
+use diesel::sqlite::{Sqlite, SqliteConnection};
+
+/// Name of the Russian-aware collation.
+pub const RUSSIAN_COLLATION: &str = "RUSSIAN";
+
+diesel::define_sql_function! {
+    /// Unicode lowercase of `x`.
+    fn unicode_lower(x: Text) -> Text;
+}
+
+diesel::define_sql_function! {
+    /// Case folding of `x`, see [`casefold`].
+    fn unicode_casefold(x: Text) -> Text;
+}
+
+diesel::define_sql_function! {
+    /// Normalized form of `x`, see [`normalize`].
+    fn normalize_text(x: Text) -> Text;
+}
+
+// Registered under the same SQL names so that `NULL` arguments return `NULL`
+// instead of failing the query.
+diesel::define_sql_function! {
+    #[sql_name = "unicode_lower"]
+    fn unicode_lower_nullable(x: Nullable<Text>) -> Nullable<Text>;
+}
+
+diesel::define_sql_function! {
+    #[sql_name = "unicode_casefold"]
+    fn unicode_casefold_nullable(x: Nullable<Text>) -> Nullable<Text>;
+}
+
+diesel::define_sql_function! {
+    #[sql_name = "normalize_text"]
+    fn normalize_text_nullable(x: Nullable<Text>) -> Nullable<Text>;
+}
+
+/// Register the functions and the collation on `conn`.
+pub fn register(conn: &mut SqliteConnection) -> QueryResult<()> {
+    unicode_lower_nullable_utils::register_impl(conn, |x: Option<String>| {
+        x.map(|x| x.to_lowercase())
+    })?;
+    unicode_casefold_nullable_utils::register_impl(conn, |x: Option<String>| {
+        x.map(|x| casefold(&x))
+    })?;
+    normalize_text_nullable_utils::register_impl(conn, |x: Option<String>| {
+        x.map(|x| normalize(&x))
+    })?;
+    // Diesel hands the collation callback SQLite's operands in reverse order.
+    conn.register_collation(RUSSIAN_COLLATION, |rhs, lhs| russian_cmp(lhs, rhs))
+}
+
+/// Lowercase `text` and fold the characters whose lowercase form still
+/// differs between spellings of the same word.
+pub fn casefold(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            'ß' | 'ẞ' => folded.push_str("ss"),
+            'ς' => folded.push('σ'),
+            _ => folded.extend(c.to_lowercase()),
+        }
+    }
+    folded
+}
+
+/// Casefold `text`, replace `ё` with `е`, trim it and collapse runs of
+/// whitespace into a single space.
+pub fn normalize(text: &str) -> String {
+    casefold(text)
+        .replace('ё', "е")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Ordering used by the `RUSSIAN` collation.
+///
+/// Letters compare case-insensitively and `ё` sorts between `е` and `ж`.
+/// Strings that only differ in case are ordered by code point, so the
+/// ordering stays total.
+pub fn russian_cmp(left: &str, right: &str) -> Ordering {
+    let left_keys = left.chars().flat_map(char::to_lowercase).map(sort_key);
+    let right_keys = right.chars().flat_map(char::to_lowercase).map(sort_key);
+    left_keys.cmp(right_keys).then_with(|| left.cmp(right))
+}
+
+fn sort_key(c: char) -> (char, u8) {
+    match c {
+        'ё' => ('е', 1),
+        _ => (c, 0),
+    }
+}
+
+/// `expr COLLATE RUSSIAN`, see [`collate_russian`].
+#[derive(Debug, Clone, Copy, QueryId, ValidGrouping)]
+pub struct CollateRussian<E> {
+    expr: E,
+}
+
+/// Compare or order `expr` with the `RUSSIAN` collation.
+pub fn collate_russian<E>(expr: E) -> CollateRussian<E::Expression>
+where
+    E: AsExpression<Text>,
+{
+    CollateRussian {
+        expr: expr.as_expression(),
+    }
+}
+
+impl<E: Expression> Expression for CollateRussian<E> {
+    type SqlType = E::SqlType;
+}
+
+impl<E, QS> AppearsOnTable<QS> for CollateRussian<E>
+where
+    E: AppearsOnTable<QS>,
+    Self: Expression,
+{
+}
+
+impl<E, QS> SelectableExpression<QS> for CollateRussian<E>
+where
+    E: SelectableExpression<QS>,
+    Self: AppearsOnTable<QS>,
+{
+}
+
+impl<E: QueryFragment<Sqlite>> QueryFragment<Sqlite> for CollateRussian<E> {
+    fn walk_ast<'b>(&'b self, mut out: AstPass<'_, 'b, Sqlite>) -> QueryResult<()> {
+        self.expr.walk_ast(out.reborrow())?;
+        out.push_sql(" COLLATE ");
+        out.push_sql(RUSSIAN_COLLATION);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::connection::SimpleConnection;
+
+    diesel::table! {
+        names (id) {
+            id -> Integer,
+            name -> Text,
+        }
+    }
+
+    fn connection() -> SqliteConnection {
+        let mut conn = SqliteConnection::establish(":memory:").unwrap();
+        register(&mut conn).unwrap();
+        conn.batch_execute(
+            "CREATE TABLE names (id INTEGER PRIMARY KEY, name TEXT NOT NULL);
+             INSERT INTO names (name) VALUES ('Жанна'), ('ёлка'), ('Ель'), ('елка'), ('Ёж'), ('Яков');",
+        )
+        .unwrap();
+        conn
+    }
+
+    #[test]
+    fn russian_collation_puts_yo_after_ye() {
+        let mut conn = connection();
+
+        let sorted = names::table
+            .select(names::name)
+            .order(collate_russian(names::name))
+            .load::<String>(&mut conn)
+            .unwrap();
+
+        assert_eq!(sorted, ["елка", "Ель", "Ёж", "ёлка", "Жанна", "Яков"]);
+    }
+
+    #[test]
+    fn functions_fold_cyrillic_and_pass_null_through() {
+        let mut conn = connection();
+
+        let matched = names::table
+            .select(names::name)
+            .filter(normalize_text(names::name).eq(normalize("  ЁЛКА ")))
+            .order(names::id)
+            .load::<String>(&mut conn)
+            .unwrap();
+        assert_eq!(matched, ["ёлка", "елка"]);
+
+        let lower = diesel::select(unicode_lower("ЖАННА"))
+            .get_result::<String>(&mut conn)
+            .unwrap();
+        assert_eq!(lower, "жанна");
+
+        let null = diesel::select(unicode_casefold_nullable(None::<String>))
+            .get_result::<Option<String>>(&mut conn)
+            .unwrap();
+        assert_eq!(null, None);
+    }
+
+    #[test]
+    fn pool_registers_functions_when_enabled() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let url = file.path().to_str().unwrap();
+        let query = diesel::select(unicode_lower("Ё"));
+
+        let plain = crate::db::DbPoolBuilder::new().build(url).unwrap();
+        assert!(
+            query
+                .get_result::<String>(&mut plain.get().unwrap())
+                .is_err()
+        );
+
+        let pool = crate::db::DbPoolBuilder::new()
+            .unicode_functions(true)
+            .build(url)
+            .unwrap();
+        assert_eq!(
+            query
+                .get_result::<String>(&mut pool.get().unwrap())
+                .unwrap(),
+            "ё"
+        );
+    }
+
+    #[test]
+    fn casefold_and_normalize() {
+        assert_eq!(casefold("Straße ΣΟΦΟΣ"), "strasse σοφοσ");
+        assert_eq!(normalize("\tЁжик   в\nтумане "), "ежик в тумане");
+    }
+}
@@ -0,0 +1,234 @@
+//! Frontend builds compiled into the service binary.
+//!
+//! A service derives [`RustEmbed`] for its Vite output directory and serves it
+//! through [`EmbeddedFrontend`], which mirrors [`open_frontend_document`] and
+//! [`open_frontend_asset`] including ETags and precompressed variants:
+//!
+//! ```ignore
+//! use pushkind_common::frontend::embedded::{EmbeddedFrontend, RustEmbed};
+//!
+//! #[derive(RustEmbed)]
+//! #[folder = "assets/dist"]
+//! #[crate_path = "pushkind_common::frontend::embedded::rust_embed"]
+//! struct Dist;
+//!
+//! let frontend = EmbeddedFrontend::<Dist>::new().override_from_env("FRONTEND_DIST_DIR");
+//! let index = frontend.open_html("index.html").await?;
+//! ```
+//!
+//! Setting an override directory serves files from the filesystem instead,
+//! which keeps the Vite watch build usable during local development.
+//!
+//! [`open_frontend_document`]: super::open_frontend_document
+//! [`open_frontend_asset`]: super::open_frontend_asset
+
+use std::io;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+pub use rust_embed::{self, RustEmbed};
+
+use super::{
+    CachePolicy, FrontendAssetError, FrontendFile, PRECOMPRESSED, Representation, join_relative,
+    open_frontend_file, to_hex,
+};
+
+/// Serves a [`RustEmbed`] build directory with the same behaviour as the
+/// filesystem helpers in [`crate::frontend`].
+pub struct EmbeddedFrontend<E> {
+    override_dir: Option<PathBuf>,
+    _embed: PhantomData<fn() -> E>,
+}
+
+impl<E: RustEmbed> EmbeddedFrontend<E> {
+    /// Serve files embedded in `E`.
+    pub fn new() -> Self {
+        Self {
+            override_dir: None,
+            _embed: PhantomData,
+        }
+    }
+
+    /// Serve files from `dir` on disk instead of the embedded copy.
+    pub fn with_override_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.override_dir = Some(dir.into());
+        self
+    }
+
+    /// Use the directory named by the environment variable `var` as override
+    /// when it is set to a non-empty value.
+    pub fn override_from_env(self, var: &str) -> Self {
+        match std::env::var_os(var) {
+            Some(dir) if !dir.is_empty() => self.with_override_dir(dir),
+            _ => self,
+        }
+    }
+
+    /// Directory used instead of the embedded files, if any.
+    pub fn override_dir(&self) -> Option<&Path> {
+        self.override_dir.as_deref()
+    }
+
+    /// Open an HTML entry document, served with [`CachePolicy::NoCache`].
+    pub async fn open_html(&self, path: &str) -> Result<FrontendFile, FrontendAssetError> {
+        self.open_file(path, CachePolicy::NoCache).await
+    }
+
+    /// Open a content-hashed asset, served with [`CachePolicy::hashed_asset`].
+    pub async fn open_asset(&self, path: &str) -> Result<FrontendFile, FrontendAssetError> {
+        self.open_file(path, CachePolicy::hashed_asset()).await
+    }
+
+    /// Open any file of the build with an explicit cache policy.
+    ///
+    /// Missing files are reported as [`FrontendAssetError::Read`] with
+    /// [`io::ErrorKind::NotFound`], exactly like their filesystem equivalent.
+    pub async fn open_file(
+        &self,
+        path: &str,
+        cache_policy: CachePolicy,
+    ) -> Result<FrontendFile, FrontendAssetError> {
+        if let Some(dir) = &self.override_dir {
+            let path = join_relative(dir, path).ok_or_else(|| not_found(path))?;
+            return open_frontend_file(path, cache_policy).await;
+        }
+
+        let path = path.trim_start_matches('/');
+        let file = E::get(path).ok_or_else(|| not_found(path))?;
+        let content_type = actix_files::file_extension_to_mime(
+            Path::new(path)
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .unwrap_or_default(),
+        );
+
+        let precompressed = PRECOMPRESSED
+            .iter()
+            .filter_map(|(encoding, extension)| {
+                E::get(&format!("{path}.{extension}"))
+                    .map(|variant| (*encoding, Representation::Bytes(variant.data)))
+            })
+            .collect();
+
+        Ok(FrontendFile::from_parts(
+            Representation::Bytes(file.data),
+            content_type,
+            to_hex(&file.metadata.sha256_hash()),
+            precompressed,
+            cache_policy,
+        ))
+    }
+}
+
+impl<E: RustEmbed> Default for EmbeddedFrontend<E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E> Clone for EmbeddedFrontend<E> {
+    fn clone(&self) -> Self {
+        Self {
+            override_dir: self.override_dir.clone(),
+            _embed: PhantomData,
+        }
+    }
+}
+
+fn not_found(path: &str) -> FrontendAssetError {
+    FrontendAssetError::Read(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("embedded frontend file not found: {path}"),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::{StatusCode, header};
+    use actix_web::test::TestRequest;
+
+    #[derive(RustEmbed)]
+    #[folder = "tests/fixtures/frontend"]
+    struct Fixtures;
+
+    fn run<T>(fut: impl Future<Output = T>) -> T {
+        actix_web::rt::System::new().block_on(fut)
+    }
+
+    #[test]
+    fn serves_embedded_html_with_precompressed_variant() {
+        let frontend = EmbeddedFrontend::<Fixtures>::new();
+        let file = run(frontend.open_html("/index.html")).unwrap();
+
+        let req = TestRequest::default()
+            .insert_header((header::ACCEPT_ENCODING, "gzip, br"))
+            .to_http_request();
+        let resp = file.into_response(&req);
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get(header::CONTENT_ENCODING).unwrap(), "br");
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(
+            resp.headers().get(header::CACHE_CONTROL).unwrap(),
+            "no-cache"
+        );
+    }
+
+    #[test]
+    fn embedded_and_filesystem_etags_match() {
+        let embedded = EmbeddedFrontend::<Fixtures>::new();
+        let on_disk =
+            EmbeddedFrontend::<Fixtures>::new().with_override_dir("tests/fixtures/frontend");
+        let req = TestRequest::default().to_http_request();
+
+        let embedded = run(embedded.open_asset("assets/app-1a2b3c.js"))
+            .unwrap()
+            .into_response(&req);
+        let on_disk = run(on_disk.open_asset("assets/app-1a2b3c.js"))
+            .unwrap()
+            .into_response(&req);
+
+        assert_eq!(
+            embedded.headers().get(header::ETAG),
+            on_disk.headers().get(header::ETAG)
+        );
+        assert_eq!(
+            embedded.headers().get(header::CACHE_CONTROL).unwrap(),
+            "public, max-age=31536000, immutable"
+        );
+    }
+
+    #[test]
+    fn matching_if_none_match_returns_not_modified() {
+        let frontend = EmbeddedFrontend::<Fixtures>::new();
+        let req = TestRequest::default().to_http_request();
+        let first = run(frontend.open_html("index.html"))
+            .unwrap()
+            .into_response(&req);
+        let etag = first.headers().get(header::ETAG).unwrap().clone();
+
+        let req = TestRequest::default()
+            .insert_header((header::IF_NONE_MATCH, etag))
+            .to_http_request();
+        let second = run(frontend.open_html("index.html"))
+            .unwrap()
+            .into_response(&req);
+
+        assert_eq!(second.status(), StatusCode::NOT_MODIFIED);
+    }
+
+    #[test]
+    fn missing_embedded_file_returns_read_error() {
+        let frontend = EmbeddedFrontend::<Fixtures>::new();
+        let error = run(frontend.open_html("missing.html")).unwrap_err();
+
+        assert!(matches!(
+            error,
+            FrontendAssetError::Read(ref err) if err.kind() == io::ErrorKind::NotFound
+        ));
+    }
+}
@@ -0,0 +1,573 @@
+//! Helpers for serving compiled frontend HTML documents and assets.
+//!
+//! Files are served with a strong, content-derived `ETag` and an explicit
+//! `Cache-Control` policy. When the Vite build emitted precompressed `.br` or
+//! `.gz` siblings, the best one accepted by the client is sent instead of the
+//! original file.
+//!
+//! With the `embedded-frontend` feature the same files can be compiled into
+//! the binary and served from memory through the `embedded` module.
+
+#[cfg(feature = "embedded-frontend")]
+pub mod embedded;
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+
+use actix_files::NamedFile;
+use actix_web::body::BoxBody;
+use actix_web::http::StatusCode;
+use actix_web::http::header::{
+    self, AcceptEncoding, ContentEncoding, Encoding, EntityTag, Header, HeaderValue, IfNoneMatch,
+};
+use actix_web::mime::{self, Mime};
+use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+/// Lifetime used for content-hashed build assets.
+pub const HASHED_ASSET_MAX_AGE: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// Precompressed variants looked up next to a file, in server preference order.
+pub(crate) const PRECOMPRESSED: [(ContentEncoding, &str); 2] = [
+    (ContentEncoding::Brotli, "br"),
+    (ContentEncoding::Gzip, "gz"),
+];
+
+/// Errors raised while opening built frontend documents.
+#[derive(Debug, Error)]
+pub enum FrontendAssetError {
+    #[error("failed to open frontend document: {0}")]
+    Read(#[from] std::io::Error),
+}
+
+/// `Cache-Control` policy attached to a served frontend file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CachePolicy {
+    /// Browsers must revalidate on every use. Suitable for HTML entry documents.
+    NoCache,
+    /// Cache for the given duration, then revalidate.
+    MaxAge(Duration),
+    /// Cache for the given duration without ever revalidating. Suitable for
+    /// assets whose file name contains a content hash.
+    Immutable(Duration),
+}
+
+impl CachePolicy {
+    /// Policy used for content-hashed build assets.
+    pub fn hashed_asset() -> Self {
+        CachePolicy::Immutable(HASHED_ASSET_MAX_AGE)
+    }
+
+    /// Render the policy as a `Cache-Control` header value.
+    pub fn header_value(&self) -> String {
+        match self {
+            CachePolicy::NoCache => "no-cache".to_string(),
+            CachePolicy::MaxAge(age) => format!("public, max-age={}", age.as_secs()),
+            CachePolicy::Immutable(age) => {
+                format!("public, max-age={}, immutable", age.as_secs())
+            }
+        }
+    }
+}
+
+/// Bytes of a single representation of a frontend file.
+#[derive(Debug)]
+pub(crate) enum Representation {
+    /// Streamed from the filesystem.
+    File(Box<NamedFile>),
+    /// Held in memory, e.g. embedded into the binary.
+    #[cfg_attr(not(feature = "embedded-frontend"), allow(dead_code))]
+    Bytes(Cow<'static, [u8]>),
+}
+
+/// A frontend file ready to be sent, together with its precompressed variants.
+///
+/// The representation is chosen when the value is turned into a response, so
+/// handlers can return it directly.
+#[derive(Debug)]
+pub struct FrontendFile {
+    identity: Representation,
+    content_type: Mime,
+    digest: String,
+    precompressed: Vec<(ContentEncoding, Representation)>,
+    cache_policy: CachePolicy,
+}
+
+impl FrontendFile {
+    /// Assemble a file from its representations.
+    ///
+    /// `digest` is the hex-encoded SHA-256 of the identity representation and
+    /// is used to derive strong entity tags.
+    pub(crate) fn from_parts(
+        identity: Representation,
+        content_type: Mime,
+        digest: String,
+        precompressed: Vec<(ContentEncoding, Representation)>,
+        cache_policy: CachePolicy,
+    ) -> Self {
+        Self {
+            identity,
+            content_type,
+            digest,
+            precompressed,
+            cache_policy,
+        }
+    }
+
+    /// Replace the `Cache-Control` policy of this file.
+    pub fn with_cache_policy(mut self, policy: CachePolicy) -> Self {
+        self.cache_policy = policy;
+        self
+    }
+
+    /// The `Cache-Control` policy that will be sent with this file.
+    pub fn cache_policy(&self) -> CachePolicy {
+        self.cache_policy
+    }
+
+    /// Content encodings available as precompressed siblings.
+    pub fn precompressed_encodings(&self) -> Vec<ContentEncoding> {
+        self.precompressed.iter().map(|(enc, _)| *enc).collect()
+    }
+
+    /// Build the response for `req`, negotiating the content encoding and
+    /// answering conditional requests.
+    pub fn into_response(self, req: &HttpRequest) -> HttpResponse<BoxBody> {
+        let FrontendFile {
+            identity,
+            content_type,
+            digest,
+            mut precompressed,
+            cache_policy,
+        } = self;
+
+        let has_variants = !precompressed.is_empty();
+        let encoding = negotiate_encoding(
+            req,
+            precompressed
+                .iter()
+                .map(|(enc, _)| *enc)
+                .collect::<Vec<_>>(),
+        );
+        let etag = entity_tag(&digest, encoding);
+
+        let mut response = if is_not_modified(req, &etag) {
+            HttpResponse::NotModified().finish()
+        } else {
+            let selected = match precompressed.iter().position(|(enc, _)| *enc == encoding) {
+                Some(idx) => precompressed.swap_remove(idx).1,
+                None => identity,
+            };
+            match selected {
+                Representation::File(file) => {
+                    // A `.gz` or `.br` sibling would otherwise be sent as an
+                    // `application/gzip` attachment named after the sibling.
+                    let file = (*file)
+                        .set_content_type(content_type)
+                        .disable_content_disposition()
+                        .use_etag(false)
+                        .use_last_modified(true)
+                        .prefer_utf8(true);
+                    match encoding {
+                        ContentEncoding::Identity => file.into_response(req),
+                        encoding => file.set_content_encoding(encoding).into_response(req),
+                    }
+                }
+                Representation::Bytes(data) => {
+                    let mut builder = HttpResponse::Ok();
+                    builder.insert_header((header::CONTENT_TYPE, utf8_content_type(&content_type)));
+                    if encoding != ContentEncoding::Identity {
+                        builder.insert_header((header::CONTENT_ENCODING, encoding.as_str()));
+                    }
+                    match data {
+                        Cow::Borrowed(bytes) => builder.body(bytes),
+                        Cow::Owned(bytes) => builder.body(bytes),
+                    }
+                }
+            }
+        };
+
+        set_representation_headers(&mut response, &etag, cache_policy, has_variants);
+        response
+    }
+}
+
+impl Responder for FrontendFile {
+    type Body = BoxBody;
+
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse<Self::Body> {
+        self.into_response(req)
+    }
+}
+
+/// Open a Vite-built HTML document for a React-owned route.
+///
+/// The file is served as is, without precompressed variants or a content
+/// `ETag`; use [`open_frontend_document`] for those.
+pub async fn open_frontend_html(path: impl AsRef<Path>) -> Result<NamedFile, FrontendAssetError> {
+    let file = NamedFile::open_async(path).await?;
+    Ok(file.use_last_modified(true).prefer_utf8(true))
+}
+
+/// Open a Vite-built HTML document for a React-owned route, negotiating
+/// precompressed variants.
+///
+/// The document is served with [`CachePolicy::NoCache`] so browsers always
+/// pick up references to freshly built assets.
+pub async fn open_frontend_document(
+    path: impl AsRef<Path>,
+) -> Result<FrontendFile, FrontendAssetError> {
+    open_frontend_file(path, CachePolicy::NoCache).await
+}
+
+/// Open a content-hashed build asset located at `relative` below `root`.
+///
+/// `relative` usually comes from the request path, so anything that would
+/// escape `root` is rejected with [`io::ErrorKind::NotFound`]. The asset is
+/// served with [`CachePolicy::hashed_asset`].
+pub async fn open_frontend_asset(
+    root: impl AsRef<Path>,
+    relative: &str,
+) -> Result<FrontendFile, FrontendAssetError> {
+    let path = join_relative(root.as_ref(), relative)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "invalid asset path"))?;
+    open_frontend_file(path, CachePolicy::hashed_asset()).await
+}
+
+/// Open a frontend file with an explicit cache policy.
+pub async fn open_frontend_file(
+    path: impl AsRef<Path>,
+    cache_policy: CachePolicy,
+) -> Result<FrontendFile, FrontendAssetError> {
+    let path = path.as_ref();
+    let file = NamedFile::open_async(path).await?;
+    let content_type = file.content_type().clone();
+    let digest = file_digest(path, file.metadata()).await?;
+
+    let mut precompressed = Vec::new();
+    for (encoding, extension) in PRECOMPRESSED {
+        match NamedFile::open_async(sibling_path(path, extension)).await {
+            Ok(variant) => precompressed.push((encoding, Representation::File(Box::new(variant)))),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    Ok(FrontendFile::from_parts(
+        Representation::File(Box::new(file)),
+        content_type,
+        digest,
+        precompressed,
+        cache_policy,
+    ))
+}
+
+/// Pick the best available encoding for `req`, falling back to identity.
+pub(crate) fn negotiate_encoding(
+    req: &HttpRequest,
+    available: impl IntoIterator<Item = ContentEncoding>,
+) -> ContentEncoding {
+    let Ok(accept) = AcceptEncoding::parse(req) else {
+        return ContentEncoding::Identity;
+    };
+
+    let supported = std::iter::once(Encoding::identity())
+        .chain(available.into_iter().filter_map(|enc| match enc {
+            ContentEncoding::Brotli => Some(Encoding::brotli()),
+            ContentEncoding::Gzip => Some(Encoding::gzip()),
+            _ => None,
+        }))
+        .collect::<Vec<_>>();
+
+    match accept.negotiate(supported.iter()) {
+        Some(enc) if enc == Encoding::brotli() => ContentEncoding::Brotli,
+        Some(enc) if enc == Encoding::gzip() => ContentEncoding::Gzip,
+        _ => ContentEncoding::Identity,
+    }
+}
+
+/// Strong entity tag for the representation of `digest` in `encoding`.
+///
+/// Each encoding is a distinct representation and therefore gets its own tag.
+pub(crate) fn entity_tag(digest: &str, encoding: ContentEncoding) -> EntityTag {
+    match encoding {
+        ContentEncoding::Identity => EntityTag::new_strong(digest.to_string()),
+        other => EntityTag::new_strong(format!("{digest}-{}", other.as_str())),
+    }
+}
+
+/// Whether the request's `If-None-Match` header matches `etag`.
+pub(crate) fn is_not_modified(req: &HttpRequest, etag: &EntityTag) -> bool {
+    match IfNoneMatch::parse(req) {
+        Ok(IfNoneMatch::Any) => true,
+        Ok(IfNoneMatch::Items(items)) => items.iter().any(|item| item.weak_eq(etag)),
+        Err(_) => false,
+    }
+}
+
+/// `Content-Type` value for `content_type`, declaring UTF-8 for textual types.
+pub(crate) fn utf8_content_type(content_type: &Mime) -> String {
+    let textual = content_type.type_() == mime::TEXT
+        || (content_type.type_() == mime::APPLICATION
+            && (content_type.subtype() == mime::JAVASCRIPT
+                || content_type.subtype() == mime::JSON));
+    if textual && content_type.get_param(mime::CHARSET).is_none() {
+        format!("{content_type}; charset=utf-8")
+    } else {
+        content_type.to_string()
+    }
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Attach `ETag`, `Cache-Control` and, when variants exist, `Vary` headers.
+pub(crate) fn set_representation_headers(
+    response: &mut HttpResponse<BoxBody>,
+    etag: &EntityTag,
+    cache_policy: CachePolicy,
+    has_variants: bool,
+) {
+    let cacheable = response.status().is_success() || response.status() == StatusCode::NOT_MODIFIED;
+    let headers = response.headers_mut();
+    if cacheable {
+        if let Ok(value) = HeaderValue::from_str(&etag.to_string()) {
+            headers.insert(header::ETAG, value);
+        }
+        if let Ok(value) = HeaderValue::from_str(&cache_policy.header_value()) {
+            headers.insert(header::CACHE_CONTROL, value);
+        }
+    }
+    if has_variants {
+        headers.append(header::VARY, HeaderValue::from_static("accept-encoding"));
+    }
+}
+
+fn sibling_path(path: &Path, extension: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".");
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+pub(crate) fn join_relative(root: &Path, relative: &str) -> Option<PathBuf> {
+    let relative = Path::new(relative.trim_start_matches('/'));
+    let mut path = root.to_path_buf();
+    for component in relative.components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    (path != root).then_some(path)
+}
+
+/// Number of file digests kept in memory.
+const DIGEST_CACHE_CAPACITY: usize = 1024;
+
+struct CachedDigest {
+    len: u64,
+    modified: Option<SystemTime>,
+    digest: String,
+}
+
+/// Compute the SHA-256 digest of a file, reusing the previous result while the
+/// file size and modification time stay unchanged.
+///
+/// The file is hashed on the blocking thread pool. At most
+/// [`DIGEST_CACHE_CAPACITY`] digests are kept; a new path evicts an arbitrary
+/// entry once the cache is full.
+async fn file_digest(path: &Path, metadata: &std::fs::Metadata) -> io::Result<String> {
+    static CACHE: OnceLock<Mutex<HashMap<PathBuf, CachedDigest>>> = OnceLock::new();
+
+    let cache = CACHE.get_or_init(Default::default);
+    let modified = metadata.modified().ok();
+
+    if let Ok(cache) = cache.lock()
+        && let Some(entry) = cache.get(path)
+        && entry.len == metadata.len()
+        && entry.modified == modified
+    {
+        return Ok(entry.digest.clone());
+    }
+
+    let owned = path.to_path_buf();
+    let digest = web::block(move || {
+        let mut hasher = Sha256::new();
+        io::copy(&mut File::open(owned)?, &mut hasher)?;
+        io::Result::Ok(to_hex(&hasher.finalize()))
+    })
+    .await
+    .map_err(io::Error::other)??;
+
+    if let Ok(mut cache) = cache.lock() {
+        if cache.len() >= DIGEST_CACHE_CAPACITY
+            && !cache.contains_key(path)
+            && let Some(evicted) = cache.keys().next().cloned()
+        {
+            cache.remove(&evicted);
+        }
+        cache.insert(
+            path.to_path_buf(),
+            CachedDigest {
+                len: metadata.len(),
+                modified,
+                digest: digest.clone(),
+            },
+        );
+    }
+
+    Ok(digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn write_build(dir: &Path) {
+        std::fs::write(dir.join("index.html"), "<html>plain</html>").unwrap();
+        std::fs::write(dir.join("index.html.br"), "brotli-bytes").unwrap();
+        std::fs::write(dir.join("index.html.gz"), "gzip-bytes").unwrap();
+    }
+
+    fn open(path: &Path) -> FrontendFile {
+        actix_web::rt::System::new()
+            .block_on(open_frontend_document(path))
+            .unwrap()
+    }
+
+    #[test]
+    fn can_open_existing_file() {
+        let current_file_path: &'static str = file!();
+
+        let result = actix_web::rt::System::new().block_on(open_frontend_html(current_file_path));
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn missing_document_returns_read_error() {
+        let error = actix_web::rt::System::new()
+            .block_on(open_frontend_html("assets/dist/does-not-exist.html"))
+            .unwrap_err();
+
+        assert!(matches!(error, FrontendAssetError::Read(_)));
+    }
+
+    #[test]
+    fn serves_brotli_variant_when_accepted() {
+        let dir = tempfile::tempdir().unwrap();
+        write_build(dir.path());
+
+        let req = TestRequest::default()
+            .insert_header((header::ACCEPT_ENCODING, "gzip;q=0.8, br"))
+            .to_http_request();
+        let resp = open(&dir.path().join("index.html")).into_response(&req);
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get(header::CONTENT_ENCODING).unwrap(), "br");
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(resp.headers().get(header::VARY).unwrap(), "accept-encoding");
+        assert_eq!(
+            resp.headers().get(header::CACHE_CONTROL).unwrap(),
+            "no-cache"
+        );
+        assert!(resp.headers().get(header::CONTENT_DISPOSITION).is_none());
+        let etag = resp.headers().get(header::ETAG).unwrap().to_str().unwrap();
+        assert!(etag.starts_with('"') && etag.ends_with("-br\""));
+
+        let req = TestRequest::default()
+            .insert_header((header::ACCEPT_ENCODING, "gzip"))
+            .to_http_request();
+        let resp = open(&dir.path().join("index.html")).into_response(&req);
+        assert_eq!(
+            resp.headers().get(header::CONTENT_ENCODING).unwrap(),
+            "gzip"
+        );
+        assert!(resp.headers().get(header::CONTENT_DISPOSITION).is_none());
+    }
+
+    #[test]
+    fn falls_back_to_identity_without_accept_encoding() {
+        let dir = tempfile::tempdir().unwrap();
+        write_build(dir.path());
+
+        let req = TestRequest::default().to_http_request();
+        let resp = open(&dir.path().join("index.html")).into_response(&req);
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get(header::CONTENT_ENCODING).is_none());
+        let expected = entity_tag(
+            &to_hex(&Sha256::digest(b"<html>plain</html>")),
+            ContentEncoding::Identity,
+        );
+        assert_eq!(
+            resp.headers().get(header::ETAG).unwrap(),
+            expected.to_string().as_str()
+        );
+    }
+
+    #[test]
+    fn matching_if_none_match_returns_not_modified() {
+        let dir = tempfile::tempdir().unwrap();
+        write_build(dir.path());
+        let etag = entity_tag(
+            &to_hex(&Sha256::digest(b"<html>plain</html>")),
+            ContentEncoding::Gzip,
+        );
+
+        let req = TestRequest::default()
+            .insert_header((header::ACCEPT_ENCODING, "gzip"))
+            .insert_header((header::IF_NONE_MATCH, etag.to_string()))
+            .to_http_request();
+        let resp = open(&dir.path().join("index.html")).into_response(&req);
+
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(
+            resp.headers().get(header::ETAG).unwrap(),
+            etag.to_string().as_str()
+        );
+    }
+
+    #[test]
+    fn assets_are_immutable_and_cannot_escape_root() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("assets")).unwrap();
+        std::fs::write(dir.path().join("assets/app-1a2b.js"), "console.log(1)").unwrap();
+
+        let system = actix_web::rt::System::new();
+        let asset = system
+            .block_on(open_frontend_asset(dir.path(), "/assets/app-1a2b.js"))
+            .unwrap();
+        assert_eq!(asset.cache_policy(), CachePolicy::hashed_asset());
+        assert!(asset.precompressed_encodings().is_empty());
+
+        let escaped = system.block_on(open_frontend_asset(dir.path(), "../etc/passwd"));
+        assert!(matches!(escaped, Err(FrontendAssetError::Read(_))));
+    }
+
+    #[test]
+    fn cache_policy_header_values() {
+        assert_eq!(CachePolicy::NoCache.header_value(), "no-cache");
+        assert_eq!(
+            CachePolicy::MaxAge(Duration::from_secs(60)).header_value(),
+            "public, max-age=60"
+        );
+        assert_eq!(
+            CachePolicy::hashed_asset().header_value(),
+            "public, max-age=31536000, immutable"
+        );
+    }
+}
@@ -0,0 +1,924 @@
+//! Durable background jobs stored in SQLite.
+//!
+//! Jobs are JSON payloads in the `pushkind_jobs` table. A [`JobWorker`]
+//! claims ready jobs of one queue with a time-limited lease, runs the handler
+//! registered for their kind and records the outcome. Failed jobs are retried
+//! with exponential backoff; once `max_attempts` is exhausted, or the payload
+//! cannot be decoded, the job is moved to the dead-letter state where it waits
+//! for [`retry_dead`] or [`purge_finished`].
+//!
+//! ```ignore
+//! #[derive(Serialize, Deserialize)]
+//! struct SendEmail { recipient_id: i32 }
+//!
+//! impl Job for SendEmail {
+//!     const KIND: &'static str = "send_email";
+//! }
+//!
+//! let mut conn = get_connection(&pool)?;
+//! jobs::enqueue(&mut conn, &NewJob::new(&SendEmail { recipient_id: 7 })?.queue("emailer"))?;
+//!
+//! let worker = JobWorker::new(pool, WorkerOptions::new("emailer").concurrency(4))
+//!     .handle(move |job: SendEmail| {
+//!         let mailer = mailer.clone();
+//!         async move { mailer.send(job.recipient_id).await }
+//!     });
+//! actix_web::rt::spawn(worker.run());
+//! ```
+//!
+//! A job whose lease expires while it is still running is claimed again, so
+//! handlers must be idempotent and finish well within the lease.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use diesel::connection::SimpleConnection;
+use diesel::dsl;
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Integer, Text};
+use diesel::sqlite::SqliteConnection;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use tokio::sync::Semaphore;
+
+use crate::db::{DbPool, Writable, get_connection};
+use crate::repository::errors::{RepositoryError, RepositoryResult};
+
+/// Queue used when none is given.
+pub const DEFAULT_QUEUE: &str = "default";
+
+/// Attempts made before a job is dead-lettered, unless overridden.
+pub const DEFAULT_MAX_ATTEMPTS: i32 = 5;
+
+/// DDL for the jobs table, for inclusion in a service migration.
+pub const JOBS_TABLE_SQL: &str = "\
+CREATE TABLE IF NOT EXISTS pushkind_jobs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    queue TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    max_attempts INTEGER NOT NULL,
+    run_at BIGINT NOT NULL,
+    locked_by TEXT,
+    locked_until BIGINT,
+    last_error TEXT,
+    created_at BIGINT NOT NULL,
+    finished_at BIGINT
+);
+CREATE INDEX IF NOT EXISTS pushkind_jobs_ready
+    ON pushkind_jobs (queue, status, run_at);";
+
+diesel::table! {
+    pushkind_jobs (id) {
+        id -> Integer,
+        queue -> Text,
+        kind -> Text,
+        payload -> Text,
+        status -> Text,
+        attempts -> Integer,
+        max_attempts -> Integer,
+        run_at -> BigInt,
+        locked_by -> Nullable<Text>,
+        locked_until -> Nullable<BigInt>,
+        last_error -> Nullable<Text>,
+        created_at -> BigInt,
+        finished_at -> Nullable<BigInt>,
+    }
+}
+
+/// Create the jobs table if it does not exist yet.
+pub fn create_jobs_table(conn: &mut SqliteConnection) -> RepositoryResult<()> {
+    conn.batch_execute(JOBS_TABLE_SQL)?;
+    Ok(())
+}
+
+/// A typed job payload.
+pub trait Job: Serialize + DeserializeOwned + Send + 'static {
+    /// Name stored with the job and used to find its handler.
+    const KIND: &'static str;
+}
+
+/// Lifecycle state of a stored job.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JobStatus {
+    /// Waiting for `run_at`, possibly after failed attempts.
+    Pending,
+    /// Claimed by a worker whose lease has not been released.
+    Running,
+    /// Finished successfully.
+    Done,
+    /// Failed permanently.
+    Dead,
+}
+
+impl JobStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JobStatus::Pending => "pending",
+            JobStatus::Running => "running",
+            JobStatus::Done => "done",
+            JobStatus::Dead => "dead",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "pending" => Some(JobStatus::Pending),
+            "running" => Some(JobStatus::Running),
+            "done" => Some(JobStatus::Done),
+            "dead" => Some(JobStatus::Dead),
+            _ => None,
+        }
+    }
+}
+
+/// A job waiting to be enqueued.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NewJob {
+    pub queue: String,
+    pub kind: String,
+    pub payload: String,
+    pub run_at: SystemTime,
+    pub max_attempts: i32,
+}
+
+impl NewJob {
+    /// Job for `job` on the default queue, runnable immediately.
+    pub fn new<J: Job>(job: &J) -> RepositoryResult<Self> {
+        Ok(Self {
+            queue: DEFAULT_QUEUE.to_string(),
+            kind: J::KIND.to_string(),
+            payload: serde_json::to_string(job)
+                .map_err(|e| RepositoryError::ValidationError(e.to_string(), Some(e.into())))?,
+            run_at: SystemTime::now(),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+        })
+    }
+
+    pub fn queue(mut self, queue: impl Into<String>) -> Self {
+        self.queue = queue.into();
+        self
+    }
+
+    /// Do not run the job before `run_at`.
+    pub fn run_at(mut self, run_at: SystemTime) -> Self {
+        self.run_at = run_at;
+        self
+    }
+
+    /// Do not run the job before `delay` has passed.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.run_at = SystemTime::now() + delay;
+        self
+    }
+
+    pub fn max_attempts(mut self, max_attempts: i32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+}
+
+/// Store `job` and return its id.
+///
+/// Runs on the given connection, so a job enqueued inside a transaction only
+/// becomes visible to workers once the transaction commits.
+pub fn enqueue(conn: &mut (impl Writable + ?Sized), job: &NewJob) -> RepositoryResult<i32> {
+    let conn = conn.writable();
+    let id = diesel::insert_into(pushkind_jobs::table)
+        .values((
+            pushkind_jobs::queue.eq(&job.queue),
+            pushkind_jobs::kind.eq(&job.kind),
+            pushkind_jobs::payload.eq(&job.payload),
+            pushkind_jobs::status.eq(JobStatus::Pending.as_str()),
+            pushkind_jobs::max_attempts.eq(job.max_attempts),
+            pushkind_jobs::run_at.eq(unix_ms(job.run_at)),
+            pushkind_jobs::created_at.eq(now_ms()),
+        ))
+        .returning(pushkind_jobs::id)
+        .get_result(conn)?;
+    Ok(id)
+}
+
+/// Stored job as seen by inspection helpers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JobRecord {
+    pub id: i32,
+    pub queue: String,
+    pub kind: String,
+    pub payload: String,
+    pub status: JobStatus,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    pub last_error: Option<String>,
+}
+
+type JobRow = (
+    i32,
+    String,
+    String,
+    String,
+    String,
+    i32,
+    i32,
+    Option<String>,
+);
+
+impl TryFrom<JobRow> for JobRecord {
+    type Error = RepositoryError;
+
+    fn try_from(row: JobRow) -> Result<Self, Self::Error> {
+        let (id, queue, kind, payload, status, attempts, max_attempts, last_error) = row;
+        let status = JobStatus::parse(&status)
+            .ok_or_else(|| RepositoryError::Unexpected(format!("Unknown job status: {status}")))?;
+        Ok(Self {
+            id,
+            queue,
+            kind,
+            payload,
+            status,
+            attempts,
+            max_attempts,
+            last_error,
+        })
+    }
+}
+
+fn record_columns() -> (
+    pushkind_jobs::id,
+    pushkind_jobs::queue,
+    pushkind_jobs::kind,
+    pushkind_jobs::payload,
+    pushkind_jobs::status,
+    pushkind_jobs::attempts,
+    pushkind_jobs::max_attempts,
+    pushkind_jobs::last_error,
+) {
+    (
+        pushkind_jobs::id,
+        pushkind_jobs::queue,
+        pushkind_jobs::kind,
+        pushkind_jobs::payload,
+        pushkind_jobs::status,
+        pushkind_jobs::attempts,
+        pushkind_jobs::max_attempts,
+        pushkind_jobs::last_error,
+    )
+}
+
+/// Look up a job by id.
+pub fn get_job(conn: &mut SqliteConnection, id: i32) -> RepositoryResult<JobRecord> {
+    pushkind_jobs::table
+        .find(id)
+        .select(record_columns())
+        .first::<JobRow>(conn)?
+        .try_into()
+}
+
+/// Dead-lettered jobs of `queue`, oldest first.
+pub fn dead_jobs(conn: &mut SqliteConnection, queue: &str) -> RepositoryResult<Vec<JobRecord>> {
+    pushkind_jobs::table
+        .filter(pushkind_jobs::queue.eq(queue))
+        .filter(pushkind_jobs::status.eq(JobStatus::Dead.as_str()))
+        .order(pushkind_jobs::id)
+        .select(record_columns())
+        .load::<JobRow>(conn)?
+        .into_iter()
+        .map(JobRecord::try_from)
+        .collect()
+}
+
+/// Move a dead-lettered job back to the queue with a fresh attempt budget.
+pub fn retry_dead(conn: &mut SqliteConnection, id: i32) -> RepositoryResult<()> {
+    let updated = diesel::update(
+        pushkind_jobs::table
+            .find(id)
+            .filter(pushkind_jobs::status.eq(JobStatus::Dead.as_str())),
+    )
+    .set((
+        pushkind_jobs::status.eq(JobStatus::Pending.as_str()),
+        pushkind_jobs::attempts.eq(0),
+        pushkind_jobs::run_at.eq(now_ms()),
+        pushkind_jobs::finished_at.eq(None::<i64>),
+    ))
+    .execute(conn)?;
+    if updated == 0 {
+        return Err(RepositoryError::NotFound);
+    }
+    Ok(())
+}
+
+/// Delete done and dead jobs that finished more than `older_than` ago and
+/// return how many were removed.
+pub fn purge_finished(
+    conn: &mut SqliteConnection,
+    older_than: Duration,
+) -> RepositoryResult<usize> {
+    let cutoff = now_ms().saturating_sub(millis(older_than));
+    Ok(diesel::delete(
+        pushkind_jobs::table
+            .filter(
+                pushkind_jobs::status.eq_any([JobStatus::Done.as_str(), JobStatus::Dead.as_str()]),
+            )
+            .filter(pushkind_jobs::finished_at.le(cutoff)),
+    )
+    .execute(conn)?)
+}
+
+/// A job claimed by a worker.
+#[derive(Clone, Debug, PartialEq, Eq, QueryableByName)]
+pub struct ClaimedJob {
+    #[diesel(sql_type = Integer)]
+    pub id: i32,
+    #[diesel(sql_type = Text)]
+    pub kind: String,
+    #[diesel(sql_type = Text)]
+    pub payload: String,
+    /// Attempts including the current one.
+    #[diesel(sql_type = Integer)]
+    pub attempts: i32,
+    #[diesel(sql_type = Integer)]
+    pub max_attempts: i32,
+}
+
+/// Lease up to `limit` ready jobs of `queue` to `worker_id`.
+///
+/// Pending jobs whose `run_at` has passed and running jobs whose lease
+/// expired are eligible. The claim is a single `UPDATE`, so concurrent
+/// workers never receive the same job.
+///
+/// A job whose lease expired after its last allowed attempt crashed or hung
+/// every worker that ran it; it is dead-lettered instead of being reclaimed.
+pub fn claim(
+    conn: &mut SqliteConnection,
+    queue: &str,
+    worker_id: &str,
+    lease: Duration,
+    limit: i64,
+) -> RepositoryResult<Vec<ClaimedJob>> {
+    let now = now_ms();
+    diesel::sql_query(
+        "UPDATE pushkind_jobs
+         SET status = 'dead', locked_by = NULL, locked_until = NULL, finished_at = ?,
+             last_error = 'Lease expired on the last attempt'
+         WHERE queue = ? AND status = 'running' AND locked_until <= ?
+           AND attempts >= max_attempts",
+    )
+    .bind::<BigInt, _>(now)
+    .bind::<Text, _>(queue)
+    .bind::<BigInt, _>(now)
+    .execute(conn)?;
+
+    let jobs = diesel::sql_query(
+        "UPDATE pushkind_jobs
+         SET status = 'running', locked_by = ?, locked_until = ?, attempts = attempts + 1
+         WHERE id IN (
+             SELECT id FROM pushkind_jobs
+             WHERE queue = ?
+               AND ((status = 'pending' AND run_at <= ?)
+                    OR (status = 'running' AND locked_until <= ?
+                        AND attempts < max_attempts))
+             ORDER BY run_at, id
+             LIMIT ?)
+         RETURNING id, kind, payload, attempts, max_attempts",
+    )
+    .bind::<Text, _>(worker_id)
+    .bind::<BigInt, _>(now.saturating_add(millis(lease)))
+    .bind::<Text, _>(queue)
+    .bind::<BigInt, _>(now)
+    .bind::<BigInt, _>(now)
+    .bind::<BigInt, _>(limit)
+    .load(conn)?;
+    Ok(jobs)
+}
+
+/// Mark a claimed job as done. Returns `false` when the lease was lost to
+/// another worker.
+pub fn complete(conn: &mut SqliteConnection, id: i32, worker_id: &str) -> RepositoryResult<bool> {
+    let updated = diesel::update(owned_by(id, worker_id))
+        .set((
+            pushkind_jobs::status.eq(JobStatus::Done.as_str()),
+            pushkind_jobs::locked_by.eq(None::<String>),
+            pushkind_jobs::locked_until.eq(None::<i64>),
+            pushkind_jobs::finished_at.eq(now_ms()),
+        ))
+        .execute(conn)?;
+    Ok(updated > 0)
+}
+
+/// Record a failed attempt of a claimed job.
+///
+/// The job is dead-lettered when `retry_in` is `None`, otherwise it becomes
+/// pending again after `retry_in`. Returns `false` when the lease was lost
+/// to another worker.
+pub fn fail(
+    conn: &mut SqliteConnection,
+    id: i32,
+    worker_id: &str,
+    error: &str,
+    retry_in: Option<Duration>,
+) -> RepositoryResult<bool> {
+    let (status, run_at, finished_at) = match retry_in {
+        Some(delay) => (
+            JobStatus::Pending,
+            now_ms().saturating_add(millis(delay)),
+            None,
+        ),
+        None => (JobStatus::Dead, now_ms(), Some(now_ms())),
+    };
+    let updated = diesel::update(owned_by(id, worker_id))
+        .set((
+            pushkind_jobs::status.eq(status.as_str()),
+            pushkind_jobs::run_at.eq(run_at),
+            pushkind_jobs::last_error.eq(error),
+            pushkind_jobs::locked_by.eq(None::<String>),
+            pushkind_jobs::locked_until.eq(None::<i64>),
+            pushkind_jobs::finished_at.eq(finished_at),
+        ))
+        .execute(conn)?;
+    Ok(updated > 0)
+}
+
+type OwnedJob<'a> = dsl::Filter<
+    dsl::Filter<dsl::Find<pushkind_jobs::table, i32>, dsl::Eq<pushkind_jobs::status, &'static str>>,
+    dsl::Eq<pushkind_jobs::locked_by, &'a str>,
+>;
+
+/// The job `id` if it is still leased to `worker_id`.
+fn owned_by(id: i32, worker_id: &str) -> OwnedJob<'_> {
+    pushkind_jobs::table
+        .find(id)
+        .filter(pushkind_jobs::status.eq(JobStatus::Running.as_str()))
+        .filter(pushkind_jobs::locked_by.eq(worker_id))
+}
+
+/// Tunables for [`JobWorker`].
+#[derive(Clone, Debug)]
+pub struct WorkerOptions {
+    /// Queue the worker takes jobs from.
+    pub queue: String,
+    /// Maximum number of jobs running at once.
+    pub concurrency: usize,
+    /// How long a claimed job is reserved for this worker.
+    pub lease: Duration,
+    /// Pause between polls when nothing is ready.
+    pub poll_interval: Duration,
+    /// Delay before the first retry; doubled for every further attempt.
+    pub base_backoff: Duration,
+    /// Upper bound for the retry delay.
+    pub max_backoff: Duration,
+}
+
+impl WorkerOptions {
+    /// Defaults for a worker on `queue`.
+    pub fn new(queue: impl Into<String>) -> Self {
+        Self {
+            queue: queue.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    pub fn lease(mut self, lease: Duration) -> Self {
+        self.lease = lease;
+        self
+    }
+
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    pub fn backoff(mut self, base_backoff: Duration, max_backoff: Duration) -> Self {
+        self.base_backoff = base_backoff;
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    fn retry_delay(&self, attempts: i32) -> Duration {
+        let exponent = u32::try_from(attempts.saturating_sub(1))
+            .unwrap_or(0)
+            .min(20);
+        self.base_backoff
+            .saturating_mul(2u32.saturating_pow(exponent))
+            .min(self.max_backoff)
+    }
+}
+
+impl Default for WorkerOptions {
+    fn default() -> Self {
+        Self {
+            queue: DEFAULT_QUEUE.to_string(),
+            concurrency: 1,
+            lease: Duration::from_secs(300),
+            poll_interval: Duration::from_secs(1),
+            base_backoff: Duration::from_secs(5),
+            max_backoff: Duration::from_secs(3600),
+        }
+    }
+}
+
+type HandlerFuture = Pin<Box<dyn Future<Output = Result<(), HandlerError>> + Send>>;
+type Handler = Arc<dyn Fn(&str) -> HandlerFuture + Send + Sync>;
+
+enum HandlerError {
+    /// The payload does not match the handler; retrying cannot help.
+    Payload(String),
+    Failed(String),
+}
+
+static WORKER_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Runs jobs of one queue with bounded concurrency.
+pub struct JobWorker {
+    pool: DbPool,
+    options: WorkerOptions,
+    worker_id: String,
+    handlers: HashMap<&'static str, Handler>,
+    permits: Arc<Semaphore>,
+}
+
+impl JobWorker {
+    pub fn new(pool: DbPool, options: WorkerOptions) -> Self {
+        let worker_id = format!(
+            "{}-{}-{}",
+            options.queue,
+            std::process::id(),
+            WORKER_COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        let permits = Arc::new(Semaphore::new(options.concurrency.max(1)));
+        Self {
+            pool,
+            options,
+            worker_id,
+            handlers: HashMap::new(),
+            permits,
+        }
+    }
+
+    /// Identifier recorded in `locked_by` for jobs claimed by this worker.
+    pub fn worker_id(&self) -> &str {
+        &self.worker_id
+    }
+
+    /// Run `handler` for jobs of kind `J::KIND`.
+    ///
+    /// Jobs of kinds without a handler fail like any other job and end up in
+    /// the dead-letter state.
+    pub fn handle<J, F, Fut, E>(mut self, handler: F) -> Self
+    where
+        J: Job,
+        F: Fn(J) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: Display,
+    {
+        let handler: Handler = Arc::new(move |payload: &str| -> HandlerFuture {
+            match serde_json::from_str::<J>(payload) {
+                Ok(job) => {
+                    let future = handler(job);
+                    Box::pin(async move {
+                        future
+                            .await
+                            .map_err(|e| HandlerError::Failed(e.to_string()))
+                    })
+                }
+                Err(err) => Box::pin(std::future::ready(Err(HandlerError::Payload(format!(
+                    "Invalid {} payload: {err}",
+                    J::KIND
+                ))))),
+            }
+        });
+        self.handlers.insert(J::KIND, handler);
+        self
+    }
+
+    /// Claim the jobs that are ready, up to the concurrency limit, run them
+    /// and return how many were claimed.
+    pub async fn run_once(self: &Arc<Self>) -> RepositoryResult<usize> {
+        let available = self.permits.available_permits();
+        if available == 0 {
+            return Ok(0);
+        }
+        let jobs = self.claim(available).await?;
+        let claimed = jobs.len();
+
+        let mut tasks = Vec::with_capacity(claimed);
+        for job in jobs {
+            tasks.push(self.spawn(job).await?);
+        }
+        for task in tasks {
+            let _ = task.await;
+        }
+        Ok(claimed)
+    }
+
+    /// Process jobs until the task is dropped or aborted.
+    ///
+    /// New jobs are claimed as soon as a slot frees up, without waiting for
+    /// the rest of the batch.
+    pub async fn run(self) {
+        let worker = Arc::new(self);
+        loop {
+            let Ok(permit) = Arc::clone(&worker.permits).acquire_owned().await else {
+                return;
+            };
+            let available = worker.permits.available_permits() + 1;
+            drop(permit);
+
+            match worker.claim(available).await {
+                Ok(jobs) if jobs.is_empty() => {
+                    tokio::time::sleep(worker.options.poll_interval).await;
+                }
+                Ok(jobs) => {
+                    for job in jobs {
+                        if let Err(err) = worker.spawn(job).await {
+                            log::error!("Job worker {} failed: {err}", worker.worker_id);
+                        }
+                    }
+                }
+                Err(err) => {
+                    log::error!(
+                        "Job worker {} failed to claim jobs: {err}",
+                        worker.worker_id
+                    );
+                    tokio::time::sleep(worker.options.poll_interval).await;
+                }
+            }
+        }
+    }
+
+    async fn claim(&self, limit: usize) -> RepositoryResult<Vec<ClaimedJob>> {
+        let queue = self.options.queue.clone();
+        let worker_id = self.worker_id.clone();
+        let lease = self.options.lease;
+        let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+        self.with_conn(move |conn| claim(conn, &queue, &worker_id, lease, limit))
+            .await
+    }
+
+    async fn spawn(
+        self: &Arc<Self>,
+        job: ClaimedJob,
+    ) -> RepositoryResult<tokio::task::JoinHandle<()>> {
+        let permit = Arc::clone(&self.permits)
+            .acquire_owned()
+            .await
+            .map_err(|e| RepositoryError::Unexpected(e.to_string()))?;
+        let worker = Arc::clone(self);
+        Ok(tokio::spawn(async move {
+            let _permit = permit;
+            if let Err(err) = worker.execute(job).await {
+                log::error!("Job worker {} failed: {err}", worker.worker_id);
+            }
+        }))
+    }
+
+    async fn execute(&self, job: ClaimedJob) -> RepositoryResult<()> {
+        let result = match self.handlers.get(job.kind.as_str()) {
+            Some(handler) => handler(&job.payload).await,
+            None => Err(HandlerError::Failed(format!(
+                "No handler for job kind {}",
+                job.kind
+            ))),
+        };
+
+        let id = job.id;
+        let worker_id = self.worker_id.clone();
+        let kept_lease = match result {
+            Ok(()) => {
+                self.with_conn(move |conn| complete(conn, id, &worker_id))
+                    .await?
+            }
+            Err(err) => {
+                let (error, retry_in) = match err {
+                    HandlerError::Payload(error) => (error, None),
+                    HandlerError::Failed(error) if job.attempts >= job.max_attempts => {
+                        (error, None)
+                    }
+                    HandlerError::Failed(error) => {
+                        (error, Some(self.options.retry_delay(job.attempts)))
+                    }
+                };
+                match retry_in {
+                    Some(delay) => log::warn!(
+                        "Job {id} ({}) failed on attempt {}/{}, retrying in {delay:?}: {error}",
+                        job.kind,
+                        job.attempts,
+                        job.max_attempts
+                    ),
+                    None => log::error!(
+                        "Job {id} ({}) moved to dead letter after {} attempts: {error}",
+                        job.kind,
+                        job.attempts
+                    ),
+                }
+                self.with_conn(move |conn| fail(conn, id, &worker_id, &error, retry_in))
+                    .await?
+            }
+        };
+
+        if !kept_lease {
+            log::warn!(
+                "Job {id} ({}) outlived its lease and was claimed by another worker",
+                job.kind
+            );
+        }
+        Ok(())
+    }
+
+    async fn with_conn<T, F>(&self, f: F) -> RepositoryResult<T>
+    where
+        F: FnOnce(&mut SqliteConnection) -> RepositoryResult<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let pool = self.pool.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = get_connection(&pool)?;
+            f(&mut conn)
+        })
+        .await
+        .map_err(|e| RepositoryError::Unexpected(format!("Job task failed: {e}")))?
+    }
+}
+
+fn now_ms() -> i64 {
+    unix_ms(SystemTime::now())
+}
+
+fn unix_ms(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(millis)
+        .unwrap_or_default()
+}
+
+fn millis(duration: Duration) -> i64 {
+    i64::try_from(duration.as_millis()).unwrap_or(i64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestDb;
+    use serde::Deserialize;
+    use std::sync::Mutex;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Greet {
+        name: String,
+    }
+
+    impl Job for Greet {
+        const KIND: &'static str = "greet";
+    }
+
+    fn database() -> TestDb {
+        TestDb::builder().seed(create_jobs_table).build()
+    }
+
+    fn greet(name: &str) -> NewJob {
+        NewJob::new(&Greet {
+            name: name.to_string(),
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn runs_ready_jobs_and_skips_scheduled_ones() {
+        let db = database();
+        let pool = db.pool();
+        let mut conn = db.conn();
+        let now = enqueue(&mut conn, &greet("now")).unwrap();
+        let later = enqueue(&mut conn, &greet("later").delay(Duration::from_secs(60))).unwrap();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&seen);
+        let worker = Arc::new(
+            JobWorker::new(pool.clone(), WorkerOptions::default()).handle(move |job: Greet| {
+                recorded.lock().unwrap().push(job.name);
+                async { Ok::<_, String>(()) }
+            }),
+        );
+
+        assert_eq!(worker.run_once().await.unwrap(), 1);
+
+        assert_eq!(*seen.lock().unwrap(), vec!["now".to_string()]);
+        assert_eq!(get_job(&mut conn, now).unwrap().status, JobStatus::Done);
+        assert_eq!(
+            get_job(&mut conn, later).unwrap().status,
+            JobStatus::Pending
+        );
+    }
+
+    #[tokio::test]
+    async fn failing_job_is_retried_then_dead_lettered() {
+        let db = database();
+        let pool = db.pool();
+        let mut conn = db.conn();
+        let id = enqueue(&mut conn, &greet("x").max_attempts(2)).unwrap();
+        let options = WorkerOptions::default().backoff(Duration::ZERO, Duration::ZERO);
+        let worker = Arc::new(
+            JobWorker::new(pool.clone(), options)
+                .handle(|_: Greet| async { Err::<(), _>("smtp unavailable") }),
+        );
+
+        worker.run_once().await.unwrap();
+        let job = get_job(&mut conn, id).unwrap();
+        assert_eq!((job.status, job.attempts), (JobStatus::Pending, 1));
+
+        worker.run_once().await.unwrap();
+        let dead = dead_jobs(&mut conn, DEFAULT_QUEUE).unwrap();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].attempts, 2);
+        assert_eq!(dead[0].last_error.as_deref(), Some("smtp unavailable"));
+
+        retry_dead(&mut conn, id).unwrap();
+        let job = get_job(&mut conn, id).unwrap();
+        assert_eq!((job.status, job.attempts), (JobStatus::Pending, 0));
+    }
+
+    #[tokio::test]
+    async fn invalid_payload_is_dead_lettered_immediately() {
+        let db = database();
+        let pool = db.pool();
+        let mut conn = db.conn();
+        let mut job = greet("x");
+        job.payload = "{}".to_string();
+        let id = enqueue(&mut conn, &job).unwrap();
+        let worker = Arc::new(
+            JobWorker::new(pool.clone(), WorkerOptions::default())
+                .handle(|_: Greet| async { Ok::<_, String>(()) }),
+        );
+
+        worker.run_once().await.unwrap();
+
+        let job = get_job(&mut conn, id).unwrap();
+        assert_eq!((job.status, job.attempts), (JobStatus::Dead, 1));
+    }
+
+    #[test]
+    fn claims_are_exclusive_until_the_lease_expires() {
+        let db = database();
+        let mut conn = db.conn();
+        for name in ["a", "b", "c"] {
+            enqueue(&mut conn, &greet(name)).unwrap();
+        }
+
+        let first = claim(&mut conn, DEFAULT_QUEUE, "w1", Duration::ZERO, 2).unwrap();
+        let second = claim(&mut conn, DEFAULT_QUEUE, "w2", Duration::from_secs(60), 5).unwrap();
+
+        assert_eq!(first.iter().map(|j| j.id).collect::<Vec<_>>(), vec![1, 2]);
+        // The expired leases of the first claim are reclaimed together with
+        // the remaining pending job.
+        assert_eq!(second.len(), 3);
+        assert!(!complete(&mut conn, 1, "w1").unwrap());
+        assert!(complete(&mut conn, 1, "w2").unwrap());
+        assert!(
+            claim(&mut conn, DEFAULT_QUEUE, "w3", Duration::ZERO, 5)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn expired_lease_on_the_last_attempt_is_dead_lettered() {
+        let db = database();
+        let mut conn = db.conn();
+        let id = enqueue(&mut conn, &greet("poison").max_attempts(2)).unwrap();
+
+        // Two workers crash while holding the job.
+        assert_eq!(
+            claim(&mut conn, DEFAULT_QUEUE, "w1", Duration::ZERO, 1)
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            claim(&mut conn, DEFAULT_QUEUE, "w2", Duration::ZERO, 1)
+                .unwrap()
+                .len(),
+            1
+        );
+
+        assert!(
+            claim(&mut conn, DEFAULT_QUEUE, "w3", Duration::ZERO, 1)
+                .unwrap()
+                .is_empty()
+        );
+        let job = get_job(&mut conn, id).unwrap();
+        assert_eq!((job.status, job.attempts), (JobStatus::Dead, 2));
+        assert_eq!(
+            job.last_error.as_deref(),
+            Some("Lease expired on the last attempt")
+        );
+        assert_eq!(dead_jobs(&mut conn, DEFAULT_QUEUE).unwrap().len(), 1);
+    }
+}
@@ -2,11 +2,13 @@
 //!
 //! The crate exposes Actix Web middleware, reusable models, pagination
 //! and route helpers. When compiled with the `db` feature it also
-//! includes Diesel-based database helpers.
+//! includes Diesel-based database helpers. The `jobs` feature adds a
+//! SQLite-backed background job queue and the `testing` feature adds test
+//! database fixtures and an in-memory repository for services.
 
 #[cfg(feature = "actix")]
 pub mod middleware;
-#[cfg(feature = "actix")]
+#[cfg(any(feature = "actix", feature = "db"))]
 pub mod pagination;
 #[cfg(feature = "actix")]
 pub mod routes;
@@ -19,6 +21,13 @@ pub mod db;
 #[cfg(feature = "db")]
 pub mod repository;
 
+#[cfg(feature = "jobs")]
+pub mod jobs;
+#[cfg(all(feature = "db", feature = "zeromq"))]
+pub mod outbox;
+#[cfg(any(feature = "testing", all(test, feature = "db")))]
+pub mod testing;
+
 #[cfg(feature = "zeromq")]
 pub mod zmq;
 
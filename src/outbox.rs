@@ -0,0 +1,546 @@
+//! Transactional outbox for ZeroMQ events.
+//!
+//! Publishing an event right after committing a database change loses the
+//! event when the process stops in between. Instead, the event is written to
+//! the `pushkind_outbox` table inside the same Diesel transaction as the
+//! business change, and an [`OutboxRelay`] publishes it afterwards through any
+//! [`ZmqSenderTrait`] implementation:
+//!
+//! ```ignore
+//! write_transaction(&mut conn, &RetryPolicy::default(), |conn| {
+//!     let order = insert_order(conn, &new_order)?;
+//!     outbox::enqueue(conn, &OutboxMessage::json("order", order.id, Some("orders.created"), &order)?)?;
+//!     Ok(order)
+//! })?;
+//!
+//! let relay = OutboxRelay::new(pool.clone(), sender, RelayOptions::default());
+//! actix_web::rt::spawn(async move { relay.run().await });
+//! ```
+//!
+//! Delivery is at least once. A message is marked delivered once the socket
+//! accepted it, see [`ZmqSenderTrait::send_bytes_acked`]; failed sends are
+//! retried with exponential backoff. Messages
+//! of one aggregate are published strictly in insertion order: a message is
+//! only sent when every earlier message of the same aggregate was delivered.
+//! Run a single relay per database.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Integer, Nullable, Text};
+use diesel::sqlite::SqliteConnection;
+use serde::Serialize;
+
+use crate::db::{DbPool, Writable, get_connection};
+use crate::repository::errors::{RepositoryError, RepositoryResult};
+use crate::zmq::ZmqSenderTrait;
+
+/// DDL for the outbox table, for inclusion in a service migration.
+pub const OUTBOX_TABLE_SQL: &str = "\
+CREATE TABLE IF NOT EXISTS pushkind_outbox (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    aggregate_type TEXT NOT NULL,
+    aggregate_id TEXT NOT NULL,
+    topic TEXT,
+    payload BLOB NOT NULL,
+    created_at BIGINT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at BIGINT NOT NULL,
+    last_error TEXT,
+    delivered_at BIGINT
+);
+CREATE INDEX IF NOT EXISTS pushkind_outbox_pending
+    ON pushkind_outbox (aggregate_type, aggregate_id, id)
+    WHERE delivered_at IS NULL;";
+
+diesel::table! {
+    pushkind_outbox (id) {
+        id -> Integer,
+        aggregate_type -> Text,
+        aggregate_id -> Text,
+        topic -> Nullable<Text>,
+        payload -> Binary,
+        created_at -> BigInt,
+        attempts -> Integer,
+        next_attempt_at -> BigInt,
+        last_error -> Nullable<Text>,
+        delivered_at -> Nullable<BigInt>,
+    }
+}
+
+/// Create the outbox table if it does not exist yet.
+pub fn create_outbox_table(conn: &mut SqliteConnection) -> RepositoryResult<()> {
+    conn.batch_execute(OUTBOX_TABLE_SQL)?;
+    Ok(())
+}
+
+/// An event waiting to be written to the outbox.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutboxMessage {
+    /// Kind of entity the event belongs to, e.g. `order`.
+    pub aggregate_type: String,
+    /// Identifier of the entity; ordering is guaranteed per type and id.
+    pub aggregate_id: String,
+    /// Sent as the first frame when present, otherwise the payload is sent as
+    /// a single frame.
+    pub topic: Option<String>,
+    pub payload: Vec<u8>,
+}
+
+impl OutboxMessage {
+    /// Message with `payload` serialized as JSON.
+    pub fn json<T: Serialize>(
+        aggregate_type: impl Into<String>,
+        aggregate_id: impl ToString,
+        topic: Option<&str>,
+        payload: &T,
+    ) -> RepositoryResult<Self> {
+        Ok(Self {
+            aggregate_type: aggregate_type.into(),
+            aggregate_id: aggregate_id.to_string(),
+            topic: topic.map(str::to_string),
+            payload: serde_json::to_vec(payload)
+                .map_err(|e| RepositoryError::ValidationError(e.to_string(), Some(e.into())))?,
+        })
+    }
+}
+
+/// Write `message` to the outbox and return its id.
+///
+/// Call it with the connection of the transaction that performs the business
+/// change, so both are committed or rolled back together.
+pub fn enqueue(
+    conn: &mut (impl Writable + ?Sized),
+    message: &OutboxMessage,
+) -> RepositoryResult<i32> {
+    let conn = conn.writable();
+    let now = now_ms();
+    let id = diesel::insert_into(pushkind_outbox::table)
+        .values((
+            pushkind_outbox::aggregate_type.eq(&message.aggregate_type),
+            pushkind_outbox::aggregate_id.eq(&message.aggregate_id),
+            pushkind_outbox::topic.eq(&message.topic),
+            pushkind_outbox::payload.eq(&message.payload),
+            pushkind_outbox::created_at.eq(now),
+            pushkind_outbox::next_attempt_at.eq(now),
+        ))
+        .returning(pushkind_outbox::id)
+        .get_result(conn)?;
+    Ok(id)
+}
+
+/// A stored outbox row that is ready to be published.
+#[derive(Clone, Debug, PartialEq, Eq, QueryableByName)]
+pub struct PendingMessage {
+    #[diesel(sql_type = Integer)]
+    pub id: i32,
+    #[diesel(sql_type = Text)]
+    pub aggregate_type: String,
+    #[diesel(sql_type = Text)]
+    pub aggregate_id: String,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub topic: Option<String>,
+    #[diesel(sql_type = diesel::sql_types::Binary)]
+    pub payload: Vec<u8>,
+    #[diesel(sql_type = Integer)]
+    pub attempts: i32,
+}
+
+/// Oldest undelivered message of every aggregate whose retry time has come.
+pub fn ready_messages(
+    conn: &mut SqliteConnection,
+    limit: i64,
+) -> RepositoryResult<Vec<PendingMessage>> {
+    let messages = diesel::sql_query(
+        "SELECT o.id, o.aggregate_type, o.aggregate_id, o.topic, o.payload, o.attempts
+         FROM pushkind_outbox o
+         WHERE o.delivered_at IS NULL
+           AND o.next_attempt_at <= ?
+           AND o.id = (
+               SELECT MIN(h.id) FROM pushkind_outbox h
+               WHERE h.delivered_at IS NULL
+                 AND h.aggregate_type = o.aggregate_type
+                 AND h.aggregate_id = o.aggregate_id)
+         ORDER BY o.id
+         LIMIT ?",
+    )
+    .bind::<BigInt, _>(now_ms())
+    .bind::<BigInt, _>(limit)
+    .load(conn)?;
+    Ok(messages)
+}
+
+/// Number of messages that were not delivered yet.
+pub fn pending_count(conn: &mut SqliteConnection) -> RepositoryResult<i64> {
+    Ok(pushkind_outbox::table
+        .filter(pushkind_outbox::delivered_at.is_null())
+        .count()
+        .get_result(conn)?)
+}
+
+/// Delete messages delivered more than `older_than` ago and return how many
+/// were removed.
+pub fn purge_delivered(
+    conn: &mut SqliteConnection,
+    older_than: Duration,
+) -> RepositoryResult<usize> {
+    let cutoff = now_ms().saturating_sub(millis(older_than));
+    Ok(
+        diesel::delete(pushkind_outbox::table.filter(pushkind_outbox::delivered_at.le(cutoff)))
+            .execute(conn)?,
+    )
+}
+
+fn mark_delivered(conn: &mut SqliteConnection, id: i32) -> RepositoryResult<()> {
+    diesel::update(pushkind_outbox::table.find(id))
+        .set(pushkind_outbox::delivered_at.eq(now_ms()))
+        .execute(conn)?;
+    Ok(())
+}
+
+fn mark_failed(
+    conn: &mut SqliteConnection,
+    id: i32,
+    error: &str,
+    retry_in: Duration,
+) -> RepositoryResult<()> {
+    diesel::update(pushkind_outbox::table.find(id))
+        .set((
+            pushkind_outbox::attempts.eq(pushkind_outbox::attempts + 1),
+            pushkind_outbox::last_error.eq(error),
+            pushkind_outbox::next_attempt_at.eq(now_ms().saturating_add(millis(retry_in))),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Tunables for [`OutboxRelay`].
+#[derive(Clone, Debug)]
+pub struct RelayOptions {
+    /// Maximum number of messages fetched per query.
+    pub batch_size: i64,
+    /// Pause between polls when nothing is ready.
+    pub poll_interval: Duration,
+    /// Delay before the first retry; doubled for every further attempt.
+    pub base_backoff: Duration,
+    /// Upper bound for the retry delay.
+    pub max_backoff: Duration,
+}
+
+impl Default for RelayOptions {
+    fn default() -> Self {
+        Self {
+            batch_size: 100,
+            poll_interval: Duration::from_millis(500),
+            base_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(300),
+        }
+    }
+}
+
+impl RelayOptions {
+    fn backoff(&self, attempts: i32) -> Duration {
+        let exponent = u32::try_from(attempts).unwrap_or(0).min(20);
+        self.base_backoff
+            .saturating_mul(2u32.saturating_pow(exponent))
+            .min(self.max_backoff)
+    }
+}
+
+/// Publishes outbox messages through a ZeroMQ sender.
+pub struct OutboxRelay<S> {
+    pool: DbPool,
+    sender: S,
+    options: RelayOptions,
+}
+
+impl<S: ZmqSenderTrait> OutboxRelay<S> {
+    pub fn new(pool: DbPool, sender: S, options: RelayOptions) -> Self {
+        Self {
+            pool,
+            sender,
+            options,
+        }
+    }
+
+    /// Publish every message that is ready and return how many were
+    /// delivered.
+    pub async fn relay_once(&self) -> RepositoryResult<usize> {
+        let mut delivered = 0;
+        loop {
+            let batch_size = self.options.batch_size;
+            let batch = self
+                .with_conn(move |conn| ready_messages(conn, batch_size))
+                .await?;
+            if batch.is_empty() {
+                return Ok(delivered);
+            }
+
+            let mut progressed = false;
+            for message in batch {
+                let id = message.id;
+                let sent = match message.topic {
+                    Some(topic) => {
+                        self.sender
+                            .send_multipart_acked(vec![topic.into_bytes(), message.payload])
+                            .await
+                    }
+                    None => self.sender.send_bytes_acked(message.payload).await,
+                };
+
+                match sent {
+                    Ok(()) => {
+                        self.with_conn(move |conn| mark_delivered(conn, id)).await?;
+                        delivered += 1;
+                        progressed = true;
+                    }
+                    Err(err) => {
+                        let retry_in = self.options.backoff(message.attempts);
+                        let error = err.to_string();
+                        log::warn!(
+                            "Outbox message {id} for {}/{} failed (attempt {}), retrying in {retry_in:?}: {error}",
+                            message.aggregate_type,
+                            message.aggregate_id,
+                            message.attempts + 1
+                        );
+                        self.with_conn(move |conn| mark_failed(conn, id, &error, retry_in))
+                            .await?;
+                    }
+                }
+            }
+
+            if !progressed {
+                return Ok(delivered);
+            }
+        }
+    }
+
+    /// Relay messages until the task is dropped or aborted.
+    pub async fn run(&self) {
+        loop {
+            match self.relay_once().await {
+                Ok(0) => tokio::time::sleep(self.options.poll_interval).await,
+                Ok(_) => {}
+                Err(err) => {
+                    log::error!("Outbox relay failed: {err}");
+                    tokio::time::sleep(self.options.poll_interval).await;
+                }
+            }
+        }
+    }
+
+    async fn with_conn<T, F>(&self, f: F) -> RepositoryResult<T>
+    where
+        F: FnOnce(&mut SqliteConnection) -> RepositoryResult<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let pool = self.pool.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = get_connection(&pool)?;
+            f(&mut conn)
+        })
+        .await
+        .map_err(|e| RepositoryError::Unexpected(format!("Outbox task failed: {e}")))?
+    }
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(millis)
+        .unwrap_or_default()
+}
+
+fn millis(duration: Duration) -> i64 {
+    i64::try_from(duration.as_millis()).unwrap_or(i64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::transaction::{RetryPolicy, write_transaction};
+    use crate::testing::TestDb;
+    use crate::zmq::{SendFuture, ZmqSenderError};
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct RecordingSender {
+        failures_left: AtomicUsize,
+        sent: Mutex<Vec<Vec<Vec<u8>>>>,
+    }
+
+    impl RecordingSender {
+        fn record(&self, frames: Vec<Vec<u8>>) -> Result<(), ZmqSenderError> {
+            if self
+                .failures_left
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_ok()
+            {
+                return Err(ZmqSenderError::QueueFull);
+            }
+            self.sent.lock().unwrap().push(frames);
+            Ok(())
+        }
+
+        fn payloads(&self) -> Vec<String> {
+            self.sent
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|frames| String::from_utf8(frames.last().unwrap().clone()).unwrap())
+                .collect()
+        }
+    }
+
+    impl ZmqSenderTrait for RecordingSender {
+        fn send_bytes<'a>(&'a self, bytes: Vec<u8>) -> SendFuture<'a> {
+            Box::pin(async move { self.record(vec![bytes]) })
+        }
+
+        fn try_send_bytes(&self, bytes: Vec<u8>) -> Result<(), ZmqSenderError> {
+            self.record(vec![bytes])
+        }
+
+        fn send_multipart<'a>(&'a self, frames: Vec<Vec<u8>>) -> SendFuture<'a> {
+            Box::pin(async move { self.record(frames) })
+        }
+    }
+
+    fn database() -> TestDb {
+        TestDb::builder().seed(create_outbox_table).build()
+    }
+
+    fn enqueue_all(pool: &DbPool, messages: &[(&str, &str)]) {
+        let mut conn = pool.get().unwrap();
+        for (aggregate_id, payload) in messages {
+            let message = OutboxMessage::json("order", aggregate_id, None, payload).unwrap();
+            enqueue(&mut conn, &message).unwrap();
+        }
+    }
+
+    fn relay(pool: &DbPool, failures: usize) -> OutboxRelay<RecordingSender> {
+        let sender = RecordingSender {
+            failures_left: AtomicUsize::new(failures),
+            ..Default::default()
+        };
+        OutboxRelay::new(
+            pool.clone(),
+            sender,
+            RelayOptions {
+                base_backoff: Duration::ZERO,
+                ..Default::default()
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn delivers_in_order_and_marks_rows() {
+        let db = database();
+        let pool = db.pool();
+        enqueue_all(&pool, &[("1", "a1"), ("2", "b1"), ("1", "a2"), ("1", "a3")]);
+        let relay = relay(&pool, 0);
+
+        assert_eq!(relay.relay_once().await.unwrap(), 4);
+
+        assert_eq!(
+            relay.sender.payloads(),
+            vec!["\"a1\"", "\"b1\"", "\"a2\"", "\"a3\""]
+        );
+        assert_eq!(pending_count(&mut pool.get().unwrap()).unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn failed_message_blocks_its_aggregate_until_retried() {
+        let db = database();
+        let pool = db.pool();
+        enqueue_all(&pool, &[("1", "a1"), ("1", "a2"), ("2", "b1")]);
+        let relay = relay(&pool, 1);
+
+        relay.relay_once().await.unwrap();
+        relay.relay_once().await.unwrap();
+
+        let payloads = relay.sender.payloads();
+        assert_eq!(payloads.len(), 3);
+        let a1 = payloads.iter().position(|p| p == "\"a1\"").unwrap();
+        let a2 = payloads.iter().position(|p| p == "\"a2\"").unwrap();
+        assert!(a1 < a2);
+
+        let attempts: i32 = pushkind_outbox::table
+            .select(pushkind_outbox::attempts)
+            .order(pushkind_outbox::id)
+            .first(&mut pool.get().unwrap())
+            .unwrap();
+        assert_eq!(attempts, 1);
+    }
+
+    /// Queues every message but reports a socket failure on the ack.
+    struct QueueingSender;
+
+    impl ZmqSenderTrait for QueueingSender {
+        fn send_bytes<'a>(&'a self, _bytes: Vec<u8>) -> SendFuture<'a> {
+            Box::pin(async { Ok(()) })
+        }
+
+        fn try_send_bytes(&self, _bytes: Vec<u8>) -> Result<(), ZmqSenderError> {
+            Ok(())
+        }
+
+        fn send_multipart<'a>(&'a self, _frames: Vec<Vec<u8>>) -> SendFuture<'a> {
+            Box::pin(async { Ok(()) })
+        }
+
+        fn send_bytes_acked<'a>(&'a self, _bytes: Vec<u8>) -> SendFuture<'a> {
+            Box::pin(async { Err(ZmqSenderError::Send(zmq::Error::EHOSTUNREACH)) })
+        }
+
+        fn send_multipart_acked<'a>(&'a self, _frames: Vec<Vec<u8>>) -> SendFuture<'a> {
+            Box::pin(async { Err(ZmqSenderError::Send(zmq::Error::EHOSTUNREACH)) })
+        }
+    }
+
+    #[tokio::test]
+    async fn queued_but_unsent_message_stays_pending() {
+        let db = database();
+        let pool = db.pool();
+        enqueue_all(&pool, &[("1", "a1")]);
+        let relay = OutboxRelay::new(pool.clone(), QueueingSender, RelayOptions::default());
+
+        assert_eq!(relay.relay_once().await.unwrap(), 0);
+
+        assert_eq!(pending_count(&mut pool.get().unwrap()).unwrap(), 1);
+        let attempts: i32 = pushkind_outbox::table
+            .select(pushkind_outbox::attempts)
+            .first(&mut pool.get().unwrap())
+            .unwrap();
+        assert_eq!(attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn rolled_back_transaction_leaves_no_message() {
+        let db = database();
+        let mut conn = db.conn();
+
+        let result: RepositoryResult<()> =
+            write_transaction(&mut conn, &RetryPolicy::no_retry(), |conn| {
+                enqueue(
+                    conn,
+                    &OutboxMessage::json("order", 1, Some("orders"), &"lost").unwrap(),
+                )?;
+                Err(RepositoryError::validation("rejected"))
+            });
+
+        assert!(result.is_err());
+        assert_eq!(pending_count(&mut conn).unwrap(), 0);
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        let options = RelayOptions::default();
+
+        assert_eq!(options.backoff(0), Duration::from_secs(1));
+        assert_eq!(options.backoff(3), Duration::from_secs(8));
+        assert_eq!(options.backoff(100), options.max_backoff);
+    }
+}
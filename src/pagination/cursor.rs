@@ -0,0 +1,548 @@
+//! Keyset (cursor) pagination.
+//!
+//! Instead of skipping `OFFSET` rows, keyset pagination remembers the sort key
+//! and id of the last row shown and continues strictly after it. Pages stay
+//! fast on large tables and rows inserted while browsing do not shift the
+//! following pages.
+//!
+//! Positions travel to clients as opaque tokens produced by [`CursorCodec`].
+//! Tokens are signed with HMAC-SHA256, so a modified token is rejected instead
+//! of silently jumping to an arbitrary position.
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use hmac::{Hmac, Mac};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use thiserror::Error;
+
+use crate::pagination::DEFAULT_ITEMS_PER_PAGE;
+
+#[cfg(feature = "db")]
+pub use self::diesel_ext::{Keyset, KeysetPaginateDsl, KeysetQuery};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Number of signature bytes kept in a token.
+const SIGNATURE_LEN: usize = 16;
+
+/// Errors produced while encoding or decoding cursor tokens.
+#[derive(Debug, Error)]
+pub enum CursorError {
+    #[error("malformed cursor")]
+    Malformed,
+
+    #[error("cursor signature mismatch")]
+    InvalidSignature,
+
+    #[error("failed to serialize cursor: {0}")]
+    Serialize(#[from] serde_json::Error),
+}
+
+/// Which side of the remembered position a page lies on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CursorDirection {
+    /// Rows following the position in sort order.
+    #[serde(rename = "a")]
+    After,
+    /// Rows preceding the position in sort order.
+    #[serde(rename = "b")]
+    Before,
+}
+
+/// A position in a keyset-ordered result set.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Cursor<K, I> {
+    /// Value of the sort column at the position.
+    #[serde(rename = "k")]
+    pub key: K,
+    /// Id of the row at the position, used to break ties in `key`.
+    #[serde(rename = "i")]
+    pub id: I,
+    #[serde(rename = "d")]
+    pub direction: CursorDirection,
+}
+
+/// A request for one page of keyset-paginated rows.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeysetRequest<K, I> {
+    /// Position to continue from, `None` for the first page.
+    pub cursor: Option<Cursor<K, I>>,
+    /// Maximum number of rows in the page.
+    pub limit: usize,
+}
+
+impl<K, I> KeysetRequest<K, I> {
+    /// Request the first page.
+    pub fn first(limit: usize) -> Self {
+        Self {
+            cursor: None,
+            limit,
+        }
+    }
+}
+
+impl<K, I> Default for KeysetRequest<K, I> {
+    fn default() -> Self {
+        Self::first(DEFAULT_ITEMS_PER_PAGE)
+    }
+}
+
+/// One page of keyset-paginated items with tokens for the neighbouring pages.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CursorPage<T> {
+    pub items: Vec<T>,
+    /// Token of the following page, `None` on the last page.
+    pub next: Option<String>,
+    /// Token of the preceding page, `None` on the first page.
+    pub prev: Option<String>,
+}
+
+impl<T> CursorPage<T> {
+    /// Convert every item while keeping the cursors.
+    pub fn map<U, F>(self, f: F) -> CursorPage<U>
+    where
+        F: FnMut(T) -> U,
+    {
+        CursorPage {
+            items: self.items.into_iter().map(f).collect(),
+            next: self.next,
+            prev: self.prev,
+        }
+    }
+}
+
+/// Signs and verifies cursor tokens.
+#[derive(Clone)]
+pub struct CursorCodec {
+    secret: Vec<u8>,
+}
+
+impl std::fmt::Debug for CursorCodec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CursorCodec").finish_non_exhaustive()
+    }
+}
+
+impl CursorCodec {
+    /// Create a codec signing tokens with `secret`.
+    pub fn new(secret: impl AsRef<[u8]>) -> Self {
+        Self {
+            secret: secret.as_ref().to_vec(),
+        }
+    }
+
+    /// Encode `cursor` into an opaque URL-safe token.
+    pub fn encode<K, I>(&self, cursor: &Cursor<K, I>) -> Result<String, CursorError>
+    where
+        K: Serialize,
+        I: Serialize,
+    {
+        let payload = serde_json::to_vec(cursor)?;
+        let signature = self.sign(&payload);
+        Ok(format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(&payload),
+            URL_SAFE_NO_PAD.encode(&signature[..SIGNATURE_LEN])
+        ))
+    }
+
+    /// Decode and verify a token produced by [`CursorCodec::encode`].
+    pub fn decode<K, I>(&self, token: &str) -> Result<Cursor<K, I>, CursorError>
+    where
+        K: DeserializeOwned,
+        I: DeserializeOwned,
+    {
+        let (payload, signature) = token.split_once('.').ok_or(CursorError::Malformed)?;
+        let payload = URL_SAFE_NO_PAD
+            .decode(payload)
+            .map_err(|_| CursorError::Malformed)?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| CursorError::Malformed)?;
+
+        if signature.len() != SIGNATURE_LEN {
+            return Err(CursorError::InvalidSignature);
+        }
+        let mut mac = self.mac();
+        mac.update(&payload);
+        mac.verify_truncated_left(&signature)
+            .map_err(|_| CursorError::InvalidSignature)?;
+
+        serde_json::from_slice(&payload).map_err(|_| CursorError::Malformed)
+    }
+
+    /// Build a [`KeysetRequest`] from an optional token taken from the request.
+    ///
+    /// Empty tokens are treated as a request for the first page.
+    pub fn request<K, I>(
+        &self,
+        token: Option<&str>,
+        limit: usize,
+    ) -> Result<KeysetRequest<K, I>, CursorError>
+    where
+        K: DeserializeOwned,
+        I: DeserializeOwned,
+    {
+        let cursor = match token.map(str::trim) {
+            None | Some("") => None,
+            Some(token) => Some(self.decode(token)?),
+        };
+        Ok(KeysetRequest { cursor, limit })
+    }
+
+    /// Assemble a [`CursorPage`] from rows fetched for `request`.
+    ///
+    /// `rows` must be in display order and may contain one row more than
+    /// `request.limit`; that extra row only signals that another page exists
+    /// in the direction of travel. `position` extracts the sort key and id of
+    /// a row.
+    pub fn page<T, K, I, F>(
+        &self,
+        request: &KeysetRequest<K, I>,
+        mut rows: Vec<T>,
+        position: F,
+    ) -> Result<CursorPage<T>, CursorError>
+    where
+        K: Serialize,
+        I: Serialize,
+        F: Fn(&T) -> (K, I),
+    {
+        let direction = request
+            .cursor
+            .as_ref()
+            .map_or(CursorDirection::After, |cursor| cursor.direction);
+        let has_more = rows.len() > request.limit;
+        if has_more {
+            match direction {
+                CursorDirection::After => {
+                    rows.truncate(request.limit);
+                }
+                CursorDirection::Before => {
+                    rows.drain(..rows.len() - request.limit);
+                }
+            }
+        }
+
+        let (more_after, more_before) = match direction {
+            CursorDirection::After => (has_more, request.cursor.is_some()),
+            CursorDirection::Before => (true, has_more),
+        };
+
+        let token = |row: Option<&T>, direction| -> Result<Option<String>, CursorError> {
+            row.map(|row| {
+                let (key, id) = position(row);
+                self.encode(&Cursor { key, id, direction })
+            })
+            .transpose()
+        };
+
+        let next = if more_after {
+            token(rows.last(), CursorDirection::After)?
+        } else {
+            None
+        };
+        let prev = if more_before {
+            token(rows.first(), CursorDirection::Before)?
+        } else {
+            None
+        };
+
+        Ok(CursorPage {
+            items: rows,
+            next,
+            prev,
+        })
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts keys of any length")
+    }
+
+    fn sign(&self, payload: &[u8]) -> Vec<u8> {
+        let mut mac = self.mac();
+        mac.update(payload);
+        mac.finalize().into_bytes().to_vec()
+    }
+}
+
+#[cfg(feature = "db")]
+impl From<CursorError> for crate::repository::errors::RepositoryError {
+    fn from(err: CursorError) -> Self {
+        crate::repository::errors::RepositoryError::ValidationError(
+            err.to_string(),
+            Some(err.into()),
+        )
+    }
+}
+
+#[cfg(feature = "db")]
+mod diesel_ext {
+    use diesel::dsl;
+    use diesel::expression::{AsExpression, TypedExpressionType};
+    use diesel::prelude::*;
+    use diesel::query_dsl::LoadQuery;
+    use diesel::query_dsl::methods::{FilterDsl, LimitDsl, OrderDsl};
+    use diesel::sql_types::{Bool, SqlType};
+    use diesel::sqlite::SqliteConnection;
+    use serde::Serialize;
+
+    use super::{CursorCodec, CursorDirection, CursorPage, KeysetRequest};
+    use crate::pagination::SortDirection;
+    use crate::repository::errors::RepositoryResult;
+
+    /// Sort column and tie-breaking id column of a keyset-paginated query.
+    ///
+    /// Both columns must be `NOT NULL` and `(key, id)` must be unique.
+    #[derive(Clone, Copy, Debug)]
+    pub struct Keyset<K, I> {
+        pub key: K,
+        pub id: I,
+        pub order: SortDirection,
+    }
+
+    impl<K, I> Keyset<K, I> {
+        pub fn new(key: K, id: I, order: SortDirection) -> Self {
+            Self { key, id, order }
+        }
+    }
+
+    /// A query restricted to a single keyset page, ready to be loaded.
+    #[must_use = "call `load_page` to run the query"]
+    pub struct KeysetQuery<Q, KV, IV> {
+        query: Q,
+        request: KeysetRequest<KV, IV>,
+        reversed: bool,
+    }
+
+    type After<K, I, KV, IV> = dsl::Or<dsl::Gt<K, KV>, dsl::And<dsl::Eq<K, KV>, dsl::Gt<I, IV>>>;
+    type Before<K, I, KV, IV> = dsl::Or<dsl::Lt<K, KV>, dsl::And<dsl::Eq<K, KV>, dsl::Lt<I, IV>>>;
+
+    /// Adds [`keyset_paginate`](KeysetPaginateDsl::keyset_paginate) to boxed
+    /// Diesel select statements.
+    pub trait KeysetPaginateDsl: Sized {
+        /// Restrict the query to the page described by `request`, ordered by
+        /// `keyset`.
+        fn keyset_paginate<K, I, KV, IV>(
+            self,
+            keyset: Keyset<K, I>,
+            request: KeysetRequest<KV, IV>,
+        ) -> KeysetQuery<Self, KV, IV>
+        where
+            K: ExpressionMethods + Copy,
+            I: ExpressionMethods + Copy,
+            K::SqlType: SqlType + TypedExpressionType,
+            I::SqlType: SqlType + TypedExpressionType,
+            KV: AsExpression<K::SqlType> + Clone,
+            IV: AsExpression<I::SqlType> + Clone,
+            dsl::Gt<K, KV>: Expression<SqlType = Bool>,
+            dsl::Lt<K, KV>: Expression<SqlType = Bool>,
+            dsl::Eq<K, KV>: Expression<SqlType = Bool>,
+            dsl::Gt<I, IV>: Expression<SqlType = Bool>,
+            dsl::Lt<I, IV>: Expression<SqlType = Bool>,
+            Self: FilterDsl<After<K, I, KV, IV>, Output = Self>
+                + FilterDsl<Before<K, I, KV, IV>, Output = Self>
+                + OrderDsl<(dsl::Asc<K>, dsl::Asc<I>), Output = Self>
+                + OrderDsl<(dsl::Desc<K>, dsl::Desc<I>), Output = Self>
+                + LimitDsl<Output = Self>,
+        {
+            let Keyset { key, id, order } = keyset;
+            let direction = request
+                .cursor
+                .as_ref()
+                .map_or(CursorDirection::After, |cursor| cursor.direction);
+            let forward = scans_forward(order, direction);
+
+            let mut query = self;
+            if let Some(cursor) = &request.cursor {
+                let (kv, iv) = (cursor.key.clone(), cursor.id.clone());
+                query = if forward {
+                    FilterDsl::filter(query, key.gt(kv.clone()).or(key.eq(kv).and(id.gt(iv))))
+                } else {
+                    FilterDsl::filter(query, key.lt(kv.clone()).or(key.eq(kv).and(id.lt(iv))))
+                };
+            }
+
+            query = if forward {
+                OrderDsl::order(query, (key.asc(), id.asc()))
+            } else {
+                OrderDsl::order(query, (key.desc(), id.desc()))
+            };
+            let limit = i64::try_from(request.limit.saturating_add(1)).unwrap_or(i64::MAX);
+            query = LimitDsl::limit(query, limit);
+
+            KeysetQuery {
+                query,
+                reversed: forward != (order == SortDirection::Asc),
+                request,
+            }
+        }
+    }
+
+    impl<T> KeysetPaginateDsl for T {}
+
+    /// Whether rows must be compared with `>` for the requested page, given the
+    /// overall sort order.
+    fn scans_forward(order: SortDirection, direction: CursorDirection) -> bool {
+        matches!(
+            (order, direction),
+            (SortDirection::Asc, CursorDirection::After)
+                | (SortDirection::Desc, CursorDirection::Before)
+        )
+    }
+
+    impl<Q, KV, IV> KeysetQuery<Q, KV, IV>
+    where
+        KV: Serialize,
+        IV: Serialize,
+    {
+        /// Run the query and build a [`CursorPage`].
+        ///
+        /// `position` returns the sort key and id of a loaded row; they must
+        /// match the columns passed to [`Keyset::new`].
+        pub fn load_page<'q, U, F>(
+            self,
+            conn: &mut SqliteConnection,
+            codec: &CursorCodec,
+            position: F,
+        ) -> RepositoryResult<CursorPage<U>>
+        where
+            Q: RunQueryDsl<SqliteConnection> + LoadQuery<'q, SqliteConnection, U>,
+            F: Fn(&U) -> (KV, IV),
+        {
+            let mut rows = self.query.load::<U>(conn)?;
+            if self.reversed {
+                rows.reverse();
+            }
+            Ok(codec.page(&self.request, rows, position)?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codec() -> CursorCodec {
+        CursorCodec::new("secret")
+    }
+
+    #[test]
+    fn token_round_trip() {
+        let cursor = Cursor {
+            key: "2024-01-01T00:00:00".to_string(),
+            id: 42,
+            direction: CursorDirection::After,
+        };
+        let token = codec().encode(&cursor).unwrap();
+
+        assert!(!token.contains("2024"));
+        assert_eq!(codec().decode::<String, i32>(&token).unwrap(), cursor);
+    }
+
+    #[test]
+    fn tampered_token_is_rejected() {
+        let cursor = Cursor {
+            key: 10,
+            id: 1,
+            direction: CursorDirection::After,
+        };
+        let token = codec().encode(&cursor).unwrap();
+        let (_, signature) = token.split_once('.').unwrap();
+        let forged = Cursor {
+            key: 11,
+            id: 1,
+            direction: CursorDirection::After,
+        };
+        let forged_payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&forged).unwrap());
+
+        assert!(matches!(
+            codec().decode::<i32, i32>(&format!("{forged_payload}.{signature}")),
+            Err(CursorError::InvalidSignature)
+        ));
+        assert!(matches!(
+            CursorCodec::new("other").decode::<i32, i32>(&token),
+            Err(CursorError::InvalidSignature)
+        ));
+        assert!(matches!(
+            codec().decode::<i32, i32>("garbage"),
+            Err(CursorError::Malformed)
+        ));
+    }
+
+    #[test]
+    fn empty_token_requests_first_page() {
+        let request = codec().request::<i32, i32>(Some(""), 10).unwrap();
+        assert_eq!(request, KeysetRequest::first(10));
+    }
+
+    #[cfg(feature = "db")]
+    mod diesel_tests {
+        use super::*;
+        use crate::pagination::SortDirection;
+        use diesel::connection::SimpleConnection;
+        use diesel::prelude::*;
+
+        diesel::table! {
+            messages (id) {
+                id -> Integer,
+                sent_at -> Integer,
+            }
+        }
+
+        fn connection() -> SqliteConnection {
+            let mut conn = SqliteConnection::establish(":memory:").unwrap();
+            conn.batch_execute(
+                "CREATE TABLE messages (id INTEGER PRIMARY KEY, sent_at INTEGER NOT NULL);
+                 INSERT INTO messages (id, sent_at) VALUES
+                    (1, 10), (2, 20), (3, 20), (4, 30), (5, 40);",
+            )
+            .unwrap();
+            conn
+        }
+
+        fn load(
+            conn: &mut SqliteConnection,
+            request: KeysetRequest<i32, i32>,
+        ) -> CursorPage<(i32, i32)> {
+            messages::table
+                .select((messages::id, messages::sent_at))
+                .into_boxed()
+                .keyset_paginate(
+                    Keyset::new(messages::sent_at, messages::id, SortDirection::Desc),
+                    request,
+                )
+                .load_page(conn, &codec(), |row: &(i32, i32)| (row.1, row.0))
+                .unwrap()
+        }
+
+        fn ids(page: &CursorPage<(i32, i32)>) -> Vec<i32> {
+            page.items.iter().map(|row| row.0).collect()
+        }
+
+        #[test]
+        fn walks_forward_and_back() {
+            let mut conn = connection();
+
+            let first = load(&mut conn, KeysetRequest::first(2));
+            assert_eq!(ids(&first), vec![5, 4]);
+            assert!(first.prev.is_none());
+
+            let request = codec().request(first.next.as_deref(), 2).unwrap();
+            let second = load(&mut conn, request);
+            assert_eq!(ids(&second), vec![3, 2]);
+
+            let request = codec().request(second.next.as_deref(), 2).unwrap();
+            let third = load(&mut conn, request);
+            assert_eq!(ids(&third), vec![1]);
+            assert!(third.next.is_none());
+
+            let request = codec().request(third.prev.as_deref(), 2).unwrap();
+            let back = load(&mut conn, request);
+            assert_eq!(ids(&back), vec![3, 2]);
+            assert!(back.prev.is_some());
+            assert!(back.next.is_some());
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Navigation links for paginated pages.
+//!
+//! [`PageLinks`] combines a [`Paginated`] value with the current request so
+//! templates get ready-made hrefs for every page, while JSON endpoints can
+//! send the same links as an RFC 8288 `Link` header. Every href keeps the rest
+//! of the query string, so active filters and sorting survive navigation.
+
+use actix_web::HttpRequest;
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use serde::Serialize;
+use url::form_urlencoded;
+
+use crate::pagination::Paginated;
+use crate::pagination::query::PAGE_PARAM;
+
+/// One entry of the page list; `page` and `href` are `None` for an ellipsis.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct PageLink {
+    pub page: Option<usize>,
+    pub href: Option<String>,
+    pub current: bool,
+}
+
+/// Hrefs for the page list and the first, previous, next and last pages.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct PageLinks {
+    pub pages: Vec<PageLink>,
+    pub first: Option<String>,
+    pub prev: Option<String>,
+    pub next: Option<String>,
+    pub last: Option<String>,
+}
+
+impl PageLinks {
+    /// Build links for `paginated` relative to `path` and the raw `query`
+    /// string (without the leading `?`).
+    pub fn new<T>(paginated: &Paginated<T>, path: &str, query: &str) -> Self {
+        let href = |page: usize| page_href(path, query, page);
+        let total_pages = paginated.total_pages;
+        let has_pages = total_pages > 0;
+
+        Self {
+            pages: paginated
+                .pages
+                .iter()
+                .map(|page| PageLink {
+                    page: *page,
+                    href: page.map(href),
+                    current: *page == Some(paginated.page),
+                })
+                .collect(),
+            first: has_pages.then(|| href(1)),
+            prev: (has_pages && paginated.has_previous)
+                .then(|| href((paginated.page - 1).min(total_pages))),
+            next: paginated.has_next.then(|| href(paginated.page + 1)),
+            last: has_pages.then(|| href(total_pages)),
+        }
+    }
+
+    /// Build links for `paginated` using the path and query of `req`.
+    pub fn from_request<T>(paginated: &Paginated<T>, req: &HttpRequest) -> Self {
+        Self::new(paginated, req.path(), req.query_string())
+    }
+
+    /// `Link` header value with `first`, `prev`, `next` and `last` relations.
+    ///
+    /// Returns `None` when there is nothing to link to. The result can be
+    /// passed straight to `HttpResponseBuilder::insert_header`.
+    pub fn link_header(&self) -> Option<(HeaderName, HeaderValue)> {
+        let value = [
+            ("first", &self.first),
+            ("prev", &self.prev),
+            ("next", &self.next),
+            ("last", &self.last),
+        ]
+        .into_iter()
+        .filter_map(|(rel, href)| href.as_ref().map(|href| format!("<{href}>; rel=\"{rel}\"")))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+        if value.is_empty() {
+            return None;
+        }
+        HeaderValue::from_str(&value)
+            .ok()
+            .map(|value| (header::LINK, value))
+    }
+}
+
+impl<T> Paginated<T> {
+    /// Navigation links for this page relative to the current request.
+    pub fn links(&self, req: &HttpRequest) -> PageLinks {
+        PageLinks::from_request(self, req)
+    }
+}
+
+/// `path` with `query`, where the `page` parameter is replaced by `page`.
+///
+/// The position of an existing `page` parameter is kept; otherwise it is
+/// appended at the end.
+fn page_href(path: &str, query: &str, page: usize) -> String {
+    let page = page.to_string();
+    let mut serializer = form_urlencoded::Serializer::new(String::new());
+    let mut replaced = false;
+
+    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+        if key == PAGE_PARAM {
+            if !replaced {
+                serializer.append_pair(PAGE_PARAM, &page);
+                replaced = true;
+            }
+        } else {
+            serializer.append_pair(&key, &value);
+        }
+    }
+    if !replaced {
+        serializer.append_pair(PAGE_PARAM, &page);
+    }
+
+    format!("{path}?{}", serializer.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pagination::Pagination;
+    use actix_web::test::TestRequest;
+
+    fn page(page: usize, total_items: usize) -> Paginated<i32> {
+        Paginated::from_total(vec![], &Pagination { page, per_page: 10 }, total_items)
+    }
+
+    #[test]
+    fn hrefs_keep_filters_and_replace_page() {
+        let req =
+            TestRequest::with_uri("/customers?search=%D0%98%D0%B2%D0%B0%D0%BD&page=2&sort=-name")
+                .to_http_request();
+
+        let links = page(2, 35).links(&req);
+
+        assert_eq!(
+            links.prev.as_deref(),
+            Some("/customers?search=%D0%98%D0%B2%D0%B0%D0%BD&page=1&sort=-name")
+        );
+        assert_eq!(
+            links.next.as_deref(),
+            Some("/customers?search=%D0%98%D0%B2%D0%B0%D0%BD&page=3&sort=-name")
+        );
+        assert_eq!(
+            links.last.as_deref(),
+            Some("/customers?search=%D0%98%D0%B2%D0%B0%D0%BD&page=4&sort=-name")
+        );
+        assert_eq!(links.pages.len(), 4);
+        assert!(links.pages[1].current);
+        assert!(!links.pages[0].current);
+    }
+
+    #[test]
+    fn page_is_appended_when_missing_and_ellipses_have_no_href() {
+        let links = PageLinks::new(&page(1, 1000), "/orders", "status=open");
+
+        assert_eq!(links.first.as_deref(), Some("/orders?status=open&page=1"));
+        assert!(links.prev.is_none());
+        assert_eq!(links.next.as_deref(), Some("/orders?status=open&page=2"));
+        let ellipsis = links.pages.iter().find(|link| link.page.is_none()).unwrap();
+        assert!(ellipsis.href.is_none());
+    }
+
+    #[test]
+    fn link_header_lists_available_relations() {
+        let links = PageLinks::new(&page(1, 20), "/orders", "");
+
+        let (name, value) = links.link_header().unwrap();
+
+        assert_eq!(name, header::LINK);
+        assert_eq!(
+            value,
+            r#"</orders?page=1>; rel="first", </orders?page=2>; rel="next", </orders?page=2>; rel="last""#
+        );
+    }
+
+    #[test]
+    fn empty_result_has_no_links() {
+        let links = PageLinks::new(&page(1, 0), "/orders", "");
+
+        assert!(links.pages.is_empty());
+        assert!(links.link_header().is_none());
+    }
+}
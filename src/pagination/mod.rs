@@ -0,0 +1,434 @@
+//! Helpers for building page navigation widgets.
+//!
+//! The pagination algorithm computes a list of page numbers with optional
+//! ellipses so templates can render compact navigation controls. A few pages
+//! from the beginning and end are always shown while the pages around the
+//! current one remain visible as well.
+//!
+//! The `query` module provides the `ListQuery` extractor that reads
+//! pagination, sorting and filters from the query string. Large tables can use
+//! keyset pagination from [`cursor`] instead of page numbers. With the `db`
+//! feature, `paginate` loads a [`Paginated`] page straight from a Diesel query.
+//! The `links` module turns a page into navigation hrefs and `Link` headers.
+
+pub mod cursor;
+#[cfg(feature = "actix")]
+pub mod links;
+#[cfg(feature = "db")]
+pub mod paginate;
+#[cfg(feature = "actix")]
+pub mod query;
+
+use serde::{Deserialize, Serialize};
+
+/// Default number of list items shown when a page size is not specified.
+/// This constant is used by pagination helpers throughout the crate.
+pub const DEFAULT_ITEMS_PER_PAGE: usize = 20;
+
+/// Calculate a list of page numbers for a pagination bar.
+///
+/// `total_pages` is the total number of pages. `current_page` denotes the
+/// page currently displayed. `left_edge` and `right_edge` specify how many
+/// pages are always shown at the start and end of the pagination sequence.
+/// `left_current` and `right_current` control how many pages are visible on
+/// each side of the current page. `None` values in the resulting vector
+/// represent collapsed ranges (ellipses).
+fn get_pages(
+    total_pages: usize,
+    current_page: usize,
+    left_edge: usize,
+    left_current: usize,
+    right_current: usize,
+    right_edge: usize,
+) -> Vec<Option<usize>> {
+    let last_page = total_pages;
+
+    if last_page == 0 {
+        return vec![];
+    }
+
+    let mut pages = Vec::new();
+
+    let left_end = (1 + left_edge).min(last_page + 1);
+    pages.extend((1..left_end).map(Some));
+
+    let mid_start = left_end.max(current_page.saturating_sub(left_current));
+    let mid_end = (current_page + right_current + 1).min(last_page + 1);
+
+    if mid_start > left_end {
+        pages.push(None);
+    }
+    pages.extend((mid_start..mid_end).map(Some));
+
+    let right_start = mid_end.max(last_page.saturating_sub(right_edge) + 1);
+
+    if right_start > mid_end {
+        pages.push(None);
+    }
+    pages.extend((right_start..=last_page).map(Some));
+
+    pages
+}
+
+/// Shape of the page number sequence produced for navigation controls.
+///
+/// The defaults show two pages at each edge, two pages before the current one
+/// and four after it. Setters can be chained to build a custom window:
+///
+/// ```
+/// use pushkind_common::pagination::{PageWindow, Paginated, Pagination};
+///
+/// let window = PageWindow::default().left_current(1).right_current(1);
+/// let page = Paginated::with_window(vec![1], &Pagination::default(), 1, window);
+/// assert_eq!(page.pages, vec![Some(1)]);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageWindow {
+    pub left_edge: usize,
+    pub left_current: usize,
+    pub right_current: usize,
+    pub right_edge: usize,
+}
+
+impl Default for PageWindow {
+    fn default() -> Self {
+        Self {
+            left_edge: 2,
+            left_current: 2,
+            right_current: 4,
+            right_edge: 2,
+        }
+    }
+}
+
+impl PageWindow {
+    /// Number of pages always shown at the start.
+    pub fn left_edge(mut self, pages: usize) -> Self {
+        self.left_edge = pages;
+        self
+    }
+
+    /// Number of pages shown before the current one.
+    pub fn left_current(mut self, pages: usize) -> Self {
+        self.left_current = pages;
+        self
+    }
+
+    /// Number of pages shown after the current one.
+    pub fn right_current(mut self, pages: usize) -> Self {
+        self.right_current = pages;
+        self
+    }
+
+    /// Number of pages always shown at the end.
+    pub fn right_edge(mut self, pages: usize) -> Self {
+        self.right_edge = pages;
+        self
+    }
+
+    /// Page numbers for `current_page` out of `total_pages`, with `None`
+    /// marking collapsed ranges.
+    pub fn pages(&self, total_pages: usize, current_page: usize) -> Vec<Option<usize>> {
+        get_pages(
+            total_pages,
+            current_page,
+            self.left_edge,
+            self.left_current,
+            self.right_current,
+            self.right_edge,
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+/// Items of a single page together with pagination info.
+///
+/// The sequence of page numbers is stored in `pages` and is suitable for
+/// building navigation controls. `page` keeps the normalized current page
+/// number. The remaining fields describe the whole result set so JSON clients
+/// can render counters and next/previous controls. `per_page` and
+/// `total_items` are `None`, and left out of the JSON, when a page was built
+/// from a page count alone. Fields are serialized in camelCase to match the
+/// `FrontendPaginated` TypeScript type.
+pub struct Paginated<T> {
+    pub items: Vec<T>,
+    pub pages: Vec<Option<usize>>,
+    pub page: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub per_page: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_items: Option<usize>,
+    pub total_pages: usize,
+    pub has_next: bool,
+    pub has_previous: bool,
+}
+
+/// Direction of a single sort key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone)]
+/// A helper struct to provide pagination information
+pub struct Pagination {
+    pub page: usize,
+    pub per_page: usize,
+}
+
+impl Default for Pagination {
+    fn default() -> Self {
+        Self {
+            page: 1,
+            per_page: DEFAULT_ITEMS_PER_PAGE,
+        }
+    }
+}
+
+impl Pagination {
+    /// Number of pages needed to show `total_items` with this page size.
+    pub fn total_pages(&self, total_items: usize) -> usize {
+        total_items.div_ceil(self.per_page.max(1))
+    }
+}
+
+impl<T> Paginated<T> {
+    /// Create a [`Paginated`] value from a list of items.
+    ///
+    /// A `current_page` of zero is interpreted as page one. Only the page
+    /// count is known here, so `per_page` and `total_items` are `None`; use
+    /// [`Paginated::from_total`] to fill them in.
+    pub fn new(items: Vec<T>, current_page: usize, total_pages: usize) -> Self {
+        // One item per page makes the page count the item count.
+        let pagination = Pagination {
+            page: current_page,
+            per_page: 1,
+        };
+        Self {
+            per_page: None,
+            total_items: None,
+            ..Self::from_total(items, &pagination, total_pages)
+        }
+    }
+
+    /// Create a [`Paginated`] value from the items of the requested page.
+    ///
+    /// `total_items` is the size of the whole result set. A page number of
+    /// zero is interpreted as page one. The page list uses the default
+    /// [`PageWindow`].
+    pub fn from_total(items: Vec<T>, pagination: &Pagination, total_items: usize) -> Self {
+        Self::with_window(items, pagination, total_items, PageWindow::default())
+    }
+
+    /// Same as [`Paginated::from_total`] but with a custom page list window.
+    pub fn with_window(
+        items: Vec<T>,
+        pagination: &Pagination,
+        total_items: usize,
+        window: PageWindow,
+    ) -> Self {
+        let current_page = if pagination.page == 0 {
+            1
+        } else {
+            pagination.page
+        };
+        let total_pages = pagination.total_pages(total_items);
+
+        let pages = window.pages(total_pages, current_page);
+
+        Self {
+            items,
+            pages,
+            page: current_page,
+            per_page: Some(pagination.per_page),
+            total_items: Some(total_items),
+            total_pages,
+            has_next: current_page < total_pages,
+            has_previous: current_page > 1,
+        }
+    }
+
+    /// Convert every item while keeping the pagination metadata.
+    pub fn map<U, F>(self, f: F) -> Paginated<U>
+    where
+        F: FnMut(T) -> U,
+    {
+        Paginated {
+            items: self.items.into_iter().map(f).collect(),
+            pages: self.pages,
+            page: self.page,
+            per_page: self.per_page,
+            total_items: self.total_items,
+            total_pages: self.total_pages,
+            has_next: self.has_next,
+            has_previous: self.has_previous,
+        }
+    }
+
+    /// Fallible version of [`Paginated::map`] returning the first error.
+    pub fn try_map<U, E, F>(self, f: F) -> Result<Paginated<U>, E>
+    where
+        F: FnMut(T) -> Result<U, E>,
+    {
+        Ok(Paginated {
+            items: self.items.into_iter().map(f).collect::<Result<_, _>>()?,
+            pages: self.pages,
+            page: self.page,
+            per_page: self.per_page,
+            total_items: self.total_items,
+            total_pages: self.total_pages,
+            has_next: self.has_next,
+            has_previous: self.has_previous,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages_without_ellipses() {
+        let pages = get_pages(10, 5, 2, 2, 4, 2);
+        let expected = (1..=10).map(Some).collect::<Vec<_>>();
+        assert_eq!(pages, expected);
+    }
+
+    #[test]
+    fn pages_with_ellipses() {
+        let pages = get_pages(100, 1, 2, 2, 4, 2);
+        assert_eq!(
+            pages,
+            vec![
+                Some(1),
+                Some(2),
+                Some(3),
+                Some(4),
+                Some(5),
+                None,
+                Some(99),
+                Some(100),
+            ]
+        );
+    }
+
+    #[test]
+    fn paginated_sets_page_to_one_when_zero() {
+        let paginated: Paginated<i32> = Paginated::new(vec![1, 2, 3], 0, 3);
+        assert_eq!(paginated.page, 1);
+        assert_eq!(paginated.pages, vec![Some(1), Some(2), Some(3)]);
+    }
+
+    #[test]
+    fn from_total_sets_page_to_one_when_zero() {
+        let pagination = Pagination {
+            page: 0,
+            per_page: 1,
+        };
+        let paginated: Paginated<i32> = Paginated::from_total(vec![1, 2, 3], &pagination, 3);
+        assert_eq!(paginated.page, 1);
+        assert_eq!(paginated.pages, vec![Some(1), Some(2), Some(3)]);
+        assert_eq!(paginated.total_items, Some(3));
+    }
+
+    #[test]
+    fn paginated_reports_totals_and_neighbours() {
+        let pagination = Pagination {
+            page: 2,
+            per_page: 20,
+        };
+        let paginated = Paginated::from_total(vec![1, 2, 3], &pagination, 45);
+
+        assert_eq!(paginated.per_page, Some(20));
+        assert_eq!(paginated.total_items, Some(45));
+        assert_eq!(paginated.total_pages, 3);
+        assert!(paginated.has_next);
+        assert!(paginated.has_previous);
+
+        let last = Paginated::from_total(
+            vec![1],
+            &Pagination {
+                page: 3,
+                ..pagination
+            },
+            45,
+        );
+        assert!(!last.has_next);
+    }
+
+    #[test]
+    fn map_and_try_map_keep_metadata() {
+        let paginated = Paginated::from_total(vec![1, 2], &Pagination::default(), 2);
+
+        let mapped = paginated.clone().map(|n| n.to_string());
+        assert_eq!(mapped.items, vec!["1".to_string(), "2".to_string()]);
+        assert_eq!(mapped.total_items, Some(2));
+
+        let failed: Result<Paginated<i32>, &str> =
+            paginated.try_map(|n| if n == 2 { Err("boom") } else { Ok(n) });
+        assert_eq!(failed.unwrap_err(), "boom");
+    }
+
+    #[test]
+    fn paginated_round_trips_through_json() {
+        let paginated = Paginated::from_total(vec!["a".to_string()], &Pagination::default(), 1);
+        let json = serde_json::to_string(&paginated).unwrap();
+        let decoded: Paginated<String> = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, paginated);
+    }
+
+    #[test]
+    fn paginated_serializes_camel_case() {
+        let paginated = Paginated::from_total(vec![1], &Pagination::default(), 1);
+        let json = serde_json::to_value(&paginated).unwrap();
+        assert_eq!(json["perPage"], 20);
+        assert_eq!(json["totalItems"], 1);
+        assert_eq!(json["totalPages"], 1);
+        assert_eq!(json["hasNext"], false);
+        assert_eq!(json["hasPrevious"], false);
+    }
+
+    #[test]
+    fn new_takes_page_count() {
+        let paginated = Paginated::new(vec![1, 2, 3], 2, 3);
+        assert_eq!(paginated.total_pages, 3);
+        assert!(paginated.has_next);
+        assert!(paginated.has_previous);
+
+        let json = serde_json::to_value(&paginated).unwrap();
+        assert!(json.get("perPage").is_none());
+        assert!(json.get("totalItems").is_none());
+        assert_eq!(json["totalPages"], 3);
+    }
+
+    #[test]
+    fn custom_window_narrows_page_list() {
+        let window = PageWindow::default()
+            .left_edge(1)
+            .left_current(1)
+            .right_current(1)
+            .right_edge(1);
+        let paginated = Paginated::with_window(
+            vec![1],
+            &Pagination {
+                page: 50,
+                per_page: 1,
+            },
+            100,
+            window,
+        );
+
+        assert_eq!(
+            paginated.pages,
+            vec![Some(1), None, Some(49), Some(50), Some(51), None, Some(100)]
+        );
+    }
+
+    #[test]
+    fn pages_empty_when_no_pages() {
+        let pages = get_pages(0, 1, 2, 2, 4, 2);
+        assert!(pages.is_empty());
+    }
+}
@@ -0,0 +1,248 @@
+//! Page-number pagination for Diesel queries.
+//!
+//! [`PaginateDsl::paginate`] wraps a select statement so that the requested
+//! page and the total number of rows are fetched in one round trip using the
+//! `COUNT(*) OVER ()` window function. Rows keep the order of the statement's
+//! own `ORDER BY`:
+//!
+//! ```ignore
+//! let page: Paginated<Customer> = customers::table
+//!     .filter(customers::hub_id.eq(hub_id))
+//!     .order(customers::name.asc())
+//!     .paginate(&pagination)
+//!     .load_paginated(&mut conn)?;
+//! ```
+
+use diesel::prelude::*;
+use diesel::query_builder::{AstPass, Query, QueryFragment, QueryId};
+use diesel::query_dsl::LoadQuery;
+use diesel::sql_types::BigInt;
+use diesel::sqlite::{Sqlite, SqliteConnection};
+
+use crate::pagination::{Paginated, Pagination};
+
+/// Adds [`paginate`](PaginateDsl::paginate) to Diesel queries.
+pub trait PaginateDsl: Sized {
+    /// Restrict the query to the page described by `pagination`.
+    fn paginate(self, pagination: &Pagination) -> PaginatedQuery<Self> {
+        PaginatedQuery {
+            query: self,
+            pagination: pagination.clone(),
+        }
+    }
+}
+
+impl<T: Query> PaginateDsl for T {}
+
+/// A query paired with the page to load from it.
+#[derive(Debug, Clone)]
+#[must_use = "call `load_paginated` to run the query"]
+pub struct PaginatedQuery<T> {
+    query: T,
+    pagination: Pagination,
+}
+
+impl<T> PaginatedQuery<T> {
+    /// Load the requested page together with the total row count.
+    ///
+    /// The query must not have a `LIMIT` of its own. When the page lies past
+    /// the end of the result set the window function sees no rows, so the
+    /// total is fetched with a `COUNT(*)` query in the same transaction to
+    /// keep the metadata of the returned [`Paginated`] accurate.
+    pub fn load_paginated<U>(self, conn: &mut SqliteConnection) -> QueryResult<Paginated<U>>
+    where
+        for<'q> PageSlice<'q, T>: LoadQuery<'q, SqliteConnection, (U, i64)>,
+        for<'q> CountRows<'q, T>: LoadQuery<'q, SqliteConnection, i64>,
+    {
+        let page = self.pagination.page.max(1);
+        let per_page = self.pagination.per_page.max(1);
+        let limit = to_i64(per_page);
+        let offset = to_i64(page - 1).saturating_mul(limit);
+
+        let (rows, total) = conn.transaction(|conn| {
+            let rows = PageSlice {
+                query: &self.query,
+                limit,
+                offset,
+            }
+            .load::<(U, i64)>(conn)?;
+
+            let total = match rows.first() {
+                Some((_, total)) => *total,
+                None if offset > 0 => CountRows { query: &self.query }.get_result::<i64>(conn)?,
+                None => 0,
+            };
+            QueryResult::Ok((rows, total))
+        })?;
+        let items = rows.into_iter().map(|(item, _)| item).collect();
+
+        Ok(Paginated::from_total(
+            items,
+            &Pagination { page, per_page },
+            usize::try_from(total).unwrap_or_default(),
+        ))
+    }
+}
+
+fn to_i64(value: usize) -> i64 {
+    i64::try_from(value).unwrap_or(i64::MAX)
+}
+
+/// `SELECT *, COUNT(*) OVER () FROM (query) LIMIT ? OFFSET ?`
+#[doc(hidden)]
+pub struct PageSlice<'q, T> {
+    query: &'q T,
+    limit: i64,
+    offset: i64,
+}
+
+impl<T: Query> Query for PageSlice<'_, T> {
+    type SqlType = (T::SqlType, BigInt);
+}
+
+impl<T> QueryId for PageSlice<'_, T> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<T> RunQueryDsl<SqliteConnection> for PageSlice<'_, T> {}
+
+impl<T: QueryFragment<Sqlite>> QueryFragment<Sqlite> for PageSlice<'_, T> {
+    fn walk_ast<'b>(&'b self, mut out: AstPass<'_, 'b, Sqlite>) -> QueryResult<()> {
+        out.push_sql("SELECT *, COUNT(*) OVER () FROM (");
+        self.query.walk_ast(out.reborrow())?;
+        out.push_sql(") LIMIT ");
+        out.push_bind_param::<BigInt, _>(&self.limit)?;
+        out.push_sql(" OFFSET ");
+        out.push_bind_param::<BigInt, _>(&self.offset)?;
+        Ok(())
+    }
+}
+
+/// `SELECT COUNT(*) FROM (query)`
+#[doc(hidden)]
+pub struct CountRows<'q, T> {
+    query: &'q T,
+}
+
+impl<T> Query for CountRows<'_, T> {
+    type SqlType = BigInt;
+}
+
+impl<T> QueryId for CountRows<'_, T> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<T> RunQueryDsl<SqliteConnection> for CountRows<'_, T> {}
+
+impl<T: QueryFragment<Sqlite>> QueryFragment<Sqlite> for CountRows<'_, T> {
+    fn walk_ast<'b>(&'b self, mut out: AstPass<'_, 'b, Sqlite>) -> QueryResult<()> {
+        out.push_sql("SELECT COUNT(*) FROM (");
+        self.query.walk_ast(out.reborrow())?;
+        out.push_sql(")");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::connection::SimpleConnection;
+
+    diesel::table! {
+        customers (id) {
+            id -> Integer,
+            name -> Text,
+            hub_id -> Integer,
+        }
+    }
+
+    fn connection() -> SqliteConnection {
+        let mut conn = SqliteConnection::establish(":memory:").unwrap();
+        conn.batch_execute(
+            "CREATE TABLE customers (id INTEGER PRIMARY KEY, name TEXT NOT NULL, hub_id INTEGER NOT NULL);
+             INSERT INTO customers (id, name, hub_id) VALUES
+                (1, 'Анна', 1), (2, 'Борис', 1), (3, 'Вера', 1),
+                (4, 'Глеб', 1), (5, 'Дина', 1), (6, 'Егор', 2);",
+        )
+        .unwrap();
+        conn
+    }
+
+    fn load(conn: &mut SqliteConnection, page: usize) -> Paginated<(i32, String)> {
+        customers::table
+            .filter(customers::hub_id.eq(1))
+            .select((customers::id, customers::name))
+            .order(customers::id.asc())
+            .into_boxed()
+            .paginate(&Pagination { page, per_page: 2 })
+            .load_paginated(conn)
+            .unwrap()
+    }
+
+    #[test]
+    fn loads_page_with_totals() {
+        let mut conn = connection();
+
+        let page = load(&mut conn, 2);
+
+        assert_eq!(
+            page.items,
+            vec![(3, "Вера".to_string()), (4, "Глеб".to_string())]
+        );
+        assert_eq!(page.total_items, Some(5));
+        assert_eq!(page.total_pages, 3);
+        assert!(page.has_next);
+        assert!(page.has_previous);
+    }
+
+    #[test]
+    fn pages_follow_the_query_order() {
+        let mut conn = connection();
+
+        let page: Paginated<String> = customers::table
+            .filter(customers::hub_id.eq(1))
+            .select(customers::name)
+            .order(customers::name.desc())
+            .paginate(&Pagination {
+                page: 1,
+                per_page: 2,
+            })
+            .load_paginated(&mut conn)
+            .unwrap();
+
+        assert_eq!(page.items, vec!["Дина".to_string(), "Глеб".to_string()]);
+        assert_eq!(page.total_items, Some(5));
+    }
+
+    #[test]
+    fn page_past_the_end_keeps_totals() {
+        let mut conn = connection();
+
+        let page = load(&mut conn, 10);
+
+        assert!(page.items.is_empty());
+        assert_eq!(page.total_items, Some(5));
+        assert_eq!(page.total_pages, 3);
+        assert!(!page.has_next);
+    }
+
+    #[test]
+    fn empty_result_has_no_pages() {
+        let mut conn = connection();
+
+        let page: Paginated<i32> = customers::table
+            .filter(customers::hub_id.eq(42))
+            .select(customers::id)
+            .paginate(&Pagination::default())
+            .load_paginated(&mut conn)
+            .unwrap();
+
+        assert!(page.items.is_empty());
+        assert_eq!(page.total_items, Some(0));
+        assert!(page.pages.is_empty());
+    }
+}
@@ -0,0 +1,599 @@
+//! Query string extractor for list endpoints.
+//!
+//! [`ListQuery`] reads `page`, `per_page` and `sort` from the query string and
+//! deserializes every other parameter into a typed filter struct. Invalid
+//! input is rejected with `400 Bad Request` and an [`ApiMutationErrorDto`]
+//! body, so React list pages can show the error next to the offending control.
+
+use std::cell::Cell;
+use std::fmt;
+use std::future::{Ready, ready};
+
+use actix_web::http::StatusCode;
+use actix_web::{FromRequest, HttpRequest, HttpResponse, ResponseError, dev::Payload};
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, Deserializer, EnumAccess, IntoDeserializer, MapAccess,
+    SeqAccess, Visitor,
+};
+use thiserror::Error;
+use url::form_urlencoded;
+
+use crate::dto::mutation::{ApiFieldErrorDto, ApiMutationErrorDto};
+use crate::pagination::{DEFAULT_ITEMS_PER_PAGE, Pagination, SortDirection};
+
+/// Default upper bound for the `per_page` parameter.
+pub const MAX_ITEMS_PER_PAGE: usize = 100;
+
+pub(crate) const PAGE_PARAM: &str = "page";
+const PER_PAGE_PARAM: &str = "per_page";
+const SORT_PARAM: &str = "sort";
+
+/// Filters accepted by a list endpoint together with its sorting rules.
+///
+/// Every query parameter other than `page`, `per_page` and `sort` is
+/// deserialized into the implementing type.
+pub trait ListFilters: DeserializeOwned {
+    /// Columns that may appear in the `sort` parameter.
+    const SORTABLE: &'static [&'static str];
+
+    /// Largest accepted page size; bigger values are clamped to it.
+    const MAX_PER_PAGE: usize = MAX_ITEMS_PER_PAGE;
+
+    /// Page size used when `per_page` is absent.
+    const DEFAULT_PER_PAGE: usize = DEFAULT_ITEMS_PER_PAGE;
+}
+
+/// A whitelisted column to order by.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SortField {
+    pub column: String,
+    pub direction: SortDirection,
+}
+
+/// Validated pagination, sorting and filters of a list request.
+#[derive(Clone, Debug)]
+pub struct ListQuery<F> {
+    pub pagination: Pagination,
+    pub sort: Vec<SortField>,
+    pub filters: F,
+}
+
+/// Errors produced while parsing a [`ListQuery`].
+#[derive(Debug, Error)]
+pub enum ListQueryError {
+    #[error("invalid page: {0}")]
+    InvalidPage(String),
+
+    #[error("invalid per_page: {0}")]
+    InvalidPerPage(String),
+
+    #[error("unknown sort column: {0}")]
+    UnknownSortColumn(String),
+
+    /// `field` is `None` when the filters as a whole were rejected.
+    #[error(
+        "invalid filter{}: {message}",
+        field.as_deref().map(|f| format!(" `{f}`")).unwrap_or_default()
+    )]
+    InvalidFilter {
+        field: Option<String>,
+        message: String,
+    },
+}
+
+impl ListQueryError {
+    /// Name of the query parameter that caused the error, if any.
+    pub fn field(&self) -> Option<&str> {
+        match self {
+            ListQueryError::InvalidPage(_) => Some(PAGE_PARAM),
+            ListQueryError::InvalidPerPage(_) => Some(PER_PAGE_PARAM),
+            ListQueryError::UnknownSortColumn(_) => Some(SORT_PARAM),
+            ListQueryError::InvalidFilter { field, .. } => field.as_deref(),
+        }
+    }
+
+    /// Convert the error into the JSON body returned to the client.
+    pub fn to_dto(&self) -> ApiMutationErrorDto {
+        let message = match self {
+            ListQueryError::InvalidPage(_) => {
+                "Номер страницы должен быть целым числом не меньше 1.".to_string()
+            }
+            ListQueryError::InvalidPerPage(_) => {
+                "Размер страницы должен быть целым числом больше 0.".to_string()
+            }
+            ListQueryError::UnknownSortColumn(column) => {
+                format!("Сортировка по полю «{column}» недоступна.")
+            }
+            ListQueryError::InvalidFilter { .. } => "Некорректное значение фильтра.".to_string(),
+        };
+
+        match self.field() {
+            Some(field) => ApiMutationErrorDto {
+                message: "Некорректные параметры запроса.".to_string(),
+                field_errors: vec![ApiFieldErrorDto {
+                    field: field.to_string(),
+                    message,
+                }],
+            },
+            None => ApiMutationErrorDto {
+                message,
+                field_errors: Vec::new(),
+            },
+        }
+    }
+}
+
+impl ResponseError for ListQueryError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::BadRequest().json(self.to_dto())
+    }
+}
+
+impl<F: ListFilters> ListQuery<F> {
+    /// Parse a raw query string (without the leading `?`).
+    pub fn from_query(query: &str) -> Result<Self, ListQueryError> {
+        let mut page = None;
+        let mut per_page = None;
+        let mut sort = None;
+        let mut filters = form_urlencoded::Serializer::new(String::new());
+
+        for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+            match key.as_ref() {
+                PAGE_PARAM => page = Some(value.into_owned()),
+                PER_PAGE_PARAM => per_page = Some(value.into_owned()),
+                SORT_PARAM => sort = Some(value.into_owned()),
+                _ => {
+                    filters.append_pair(&key, &value);
+                }
+            }
+        }
+
+        let page = match page.as_deref().map(str::trim) {
+            None | Some("") => 1,
+            Some(raw) => match raw.parse::<usize>() {
+                Ok(page) if page >= 1 => page,
+                _ => return Err(ListQueryError::InvalidPage(raw.to_string())),
+            },
+        };
+
+        let per_page = match per_page.as_deref().map(str::trim) {
+            None | Some("") => F::DEFAULT_PER_PAGE,
+            Some(raw) => match raw.parse::<usize>() {
+                Ok(per_page) if per_page >= 1 => per_page,
+                _ => return Err(ListQueryError::InvalidPerPage(raw.to_string())),
+            },
+        }
+        .min(F::MAX_PER_PAGE);
+
+        let sort = parse_sort(sort.as_deref().unwrap_or_default(), F::SORTABLE)?;
+
+        let encoded = filters.finish();
+        let named_field = Cell::new(None);
+        let deserializer = FilterDeserializer {
+            inner: serde_urlencoded::Deserializer::new(form_urlencoded::parse(encoded.as_bytes())),
+            field: &named_field,
+        };
+        let filters = serde_path_to_error::deserialize(deserializer).map_err(|err| {
+            let path = err.path().to_string();
+            let message = err.into_inner().to_string();
+            // Missing and unknown fields are reported on the struct itself.
+            let field = if path == "." {
+                named_field.take()
+            } else {
+                Some(path)
+            };
+            ListQueryError::InvalidFilter { field, message }
+        })?;
+
+        Ok(Self {
+            pagination: Pagination { page, per_page },
+            sort,
+            filters,
+        })
+    }
+}
+
+/// Filter deserializer that stores the field of a "missing field" or
+/// "unknown field" error in `field`.
+///
+/// serde reports both errors on the struct rather than at the field's path,
+/// so the field is taken from the [`de::Error::missing_field`] and
+/// [`de::Error::unknown_field`] calls made by the filter type.
+struct FilterDeserializer<'a, 'de> {
+    inner: serde_urlencoded::Deserializer<'de>,
+    field: &'a Cell<Option<String>>,
+}
+
+impl<'de> Deserializer<'de> for FilterDeserializer<'_, 'de> {
+    type Error = serde_urlencoded::de::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.inner.deserialize_any(FieldVisitor {
+            visitor,
+            field: self.field,
+        })
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.inner.deserialize_unit(visitor)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.inner.deserialize_seq(visitor)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit_struct newtype_struct tuple tuple_struct
+        map struct enum identifier ignored_any
+    }
+}
+
+/// Visitor that hooks [`FieldMap`] into maps and hands everything else to
+/// the filter's own visitor unchanged.
+struct FieldVisitor<'a, V> {
+    visitor: V,
+    field: &'a Cell<Option<String>>,
+}
+
+macro_rules! forward_visit {
+    ($($method:ident($ty:ty)),* $(,)?) => {
+        $(
+            fn $method<E: de::Error>(self, value: $ty) -> Result<Self::Value, E> {
+                self.visitor.$method(value)
+            }
+        )*
+    };
+}
+
+impl<'de, V: Visitor<'de>> Visitor<'de> for FieldVisitor<'_, V> {
+    type Value = V::Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.visitor.expecting(f)
+    }
+
+    forward_visit! {
+        visit_bool(bool),
+        visit_i8(i8),
+        visit_i16(i16),
+        visit_i32(i32),
+        visit_i64(i64),
+        visit_i128(i128),
+        visit_u8(u8),
+        visit_u16(u16),
+        visit_u32(u32),
+        visit_u64(u64),
+        visit_u128(u128),
+        visit_f32(f32),
+        visit_f64(f64),
+        visit_char(char),
+        visit_str(&str),
+        visit_borrowed_str(&'de str),
+        visit_string(String),
+        visit_bytes(&[u8]),
+        visit_borrowed_bytes(&'de [u8]),
+        visit_byte_buf(Vec<u8>),
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+        self.visitor.visit_none()
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        self.visitor.visit_unit()
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        self.visitor.visit_some(deserializer)
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+        self.visitor.visit_newtype_struct(deserializer)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+        self.visitor.visit_seq(seq)
+    }
+
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Self::Value, A::Error> {
+        self.visitor.visit_enum(data)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        self.visitor
+            .visit_map(FieldMap { map })
+            .map_err(|err: FilterError| {
+                self.field.set(err.field);
+                de::Error::custom(err.message)
+            })
+    }
+}
+
+/// Map access whose errors remember the field they name.
+struct FieldMap<A> {
+    map: A,
+}
+
+impl<'de, A: MapAccess<'de>> MapAccess<'de> for FieldMap<A> {
+    type Error = FilterError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        let key: Option<String> = self.map.next_key().map_err(FilterError::from_inner)?;
+        key.map(|key| seed.deserialize(key.into_deserializer()))
+            .transpose()
+    }
+
+    fn next_value_seed<S: DeserializeSeed<'de>>(
+        &mut self,
+        seed: S,
+    ) -> Result<S::Value, Self::Error> {
+        self.map
+            .next_value_seed(seed)
+            .map_err(FilterError::from_inner)
+    }
+}
+
+#[derive(Debug)]
+struct FilterError {
+    field: Option<String>,
+    message: String,
+}
+
+impl FilterError {
+    fn from_inner(err: impl fmt::Display) -> Self {
+        de::Error::custom(err)
+    }
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for FilterError {}
+
+impl de::Error for FilterError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self {
+            field: None,
+            message: msg.to_string(),
+        }
+    }
+
+    fn missing_field(field: &'static str) -> Self {
+        Self {
+            field: Some(field.to_string()),
+            message: format!("missing field `{field}`"),
+        }
+    }
+
+    fn unknown_field(field: &str, expected: &'static [&'static str]) -> Self {
+        Self {
+            field: Some(field.to_string()),
+            message: format!("unknown field `{field}`, expected one of {expected:?}"),
+        }
+    }
+}
+
+impl<F: ListFilters> FromRequest for ListQuery<F> {
+    type Error = ListQueryError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Self::from_query(req.query_string()))
+    }
+}
+
+/// Parse `-created_at,name` style sort specifications.
+///
+/// A leading `-` selects descending order. Empty entries are skipped and every
+/// column must be listed in `allowed`.
+pub fn parse_sort(raw: &str, allowed: &[&str]) -> Result<Vec<SortField>, ListQueryError> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            let (column, direction) = match item.strip_prefix('-') {
+                Some(column) => (column, SortDirection::Desc),
+                None => (item.strip_prefix('+').unwrap_or(item), SortDirection::Asc),
+            };
+            if allowed.contains(&column) {
+                Ok(SortField {
+                    column: column.to_string(),
+                    direction,
+                })
+            } else {
+                Err(ListQueryError::UnknownSortColumn(column.to_string()))
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct CustomerFilters {
+        #[serde(default, deserialize_with = "crate::routes::empty_string_as_none")]
+        search: Option<String>,
+        #[serde(default)]
+        hub_id: Option<i32>,
+    }
+
+    impl ListFilters for CustomerFilters {
+        const SORTABLE: &'static [&'static str] = &["created_at", "name"];
+        const MAX_PER_PAGE: usize = 50;
+    }
+
+    #[test]
+    fn defaults_when_parameters_are_missing() {
+        let query = ListQuery::<CustomerFilters>::from_query("").unwrap();
+
+        assert_eq!(query.pagination.page, 1);
+        assert_eq!(query.pagination.per_page, DEFAULT_ITEMS_PER_PAGE);
+        assert!(query.sort.is_empty());
+        assert_eq!(
+            query.filters,
+            CustomerFilters {
+                search: None,
+                hub_id: None
+            }
+        );
+    }
+
+    #[test]
+    fn parses_sort_and_filters_and_clamps_per_page() {
+        let query = ListQuery::<CustomerFilters>::from_query(
+            "page=3&per_page=500&sort=-created_at,name&search=%D0%98%D0%B2%D0%B0%D0%BD&hub_id=7",
+        )
+        .unwrap();
+
+        assert_eq!(query.pagination.page, 3);
+        assert_eq!(query.pagination.per_page, 50);
+        assert_eq!(
+            query.sort,
+            vec![
+                SortField {
+                    column: "created_at".into(),
+                    direction: SortDirection::Desc
+                },
+                SortField {
+                    column: "name".into(),
+                    direction: SortDirection::Asc
+                },
+            ]
+        );
+        assert_eq!(query.filters.search.as_deref(), Some("Иван"));
+        assert_eq!(query.filters.hub_id, Some(7));
+    }
+
+    #[test]
+    fn rejects_invalid_page_and_per_page() {
+        assert!(matches!(
+            ListQuery::<CustomerFilters>::from_query("page=0"),
+            Err(ListQueryError::InvalidPage(_))
+        ));
+        assert!(matches!(
+            ListQuery::<CustomerFilters>::from_query("page=abc"),
+            Err(ListQueryError::InvalidPage(_))
+        ));
+        assert!(matches!(
+            ListQuery::<CustomerFilters>::from_query("per_page=0"),
+            Err(ListQueryError::InvalidPerPage(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_unknown_sort_column() {
+        let error = ListQuery::<CustomerFilters>::from_query("sort=password").unwrap_err();
+
+        assert!(matches!(error, ListQueryError::UnknownSortColumn(ref c) if c == "password"));
+        assert_eq!(error.to_dto().field_errors[0].field, "sort");
+    }
+
+    #[test]
+    fn invalid_filter_reports_field() {
+        let error = ListQuery::<CustomerFilters>::from_query("hub_id=abc").unwrap_err();
+
+        assert_eq!(error.field(), Some("hub_id"));
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct HubFilters {
+        #[serde(default)]
+        search: Option<String>,
+        #[serde(rename = "hub")]
+        hub_id: i32,
+    }
+
+    impl ListFilters for HubFilters {
+        const SORTABLE: &'static [&'static str] = &[];
+    }
+
+    #[test]
+    fn struct_level_filter_errors_name_the_field() {
+        let query = ListQuery::<HubFilters>::from_query("hub=3").unwrap();
+        assert_eq!(query.filters.hub_id, 3);
+        assert_eq!(query.filters.search, None);
+
+        let missing = ListQuery::<HubFilters>::from_query("search=anna").unwrap_err();
+        assert_eq!(missing.field(), Some("hub"));
+        assert_eq!(missing.to_dto().field_errors[0].field, "hub");
+
+        let unknown = ListQuery::<HubFilters>::from_query("hub=1&color=red").unwrap_err();
+        assert_eq!(unknown.field(), Some("color"));
+        assert!(unknown.to_string().contains("unknown field `color`"));
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct HubScope {
+        hub: String,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct FlattenedFilters {
+        #[serde(flatten)]
+        scope: HubScope,
+        #[serde(default)]
+        search: Option<String>,
+    }
+
+    impl ListFilters for FlattenedFilters {
+        const SORTABLE: &'static [&'static str] = &[];
+    }
+
+    #[test]
+    fn flattened_filter_errors_name_the_field() {
+        let query = ListQuery::<FlattenedFilters>::from_query("hub=3&search=anna").unwrap();
+        assert_eq!(query.filters.scope.hub, "3");
+        assert_eq!(query.filters.search.as_deref(), Some("anna"));
+
+        let missing = ListQuery::<FlattenedFilters>::from_query("search=anna").unwrap_err();
+        assert_eq!(missing.field(), Some("hub"));
+    }
+
+    #[test]
+    fn filter_errors_without_a_field_have_no_field_entry() {
+        let error = ListQueryError::InvalidFilter {
+            field: None,
+            message: "invalid type: map, expected i32".to_string(),
+        };
+        let dto = error.to_dto();
+        assert!(dto.field_errors.is_empty());
+        assert_eq!(dto.message, "Некорректное значение фильтра.");
+        assert_eq!(
+            error.to_string(),
+            "invalid filter: invalid type: map, expected i32"
+        );
+    }
+
+    #[actix_web::test]
+    async fn extractor_returns_bad_request_with_error_dto() {
+        let req = TestRequest::with_uri("/customers?page=-1").to_http_request();
+        let error = ListQuery::<CustomerFilters>::extract(&req)
+            .await
+            .unwrap_err();
+
+        let resp = error.error_response();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains(r#""field":"page""#));
+    }
+}
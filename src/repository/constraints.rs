@@ -0,0 +1,310 @@
+//! Mapping of constraint violations to form field errors.
+//!
+//! Repositories usually rely on the database to reject duplicates instead of
+//! checking first. [`ConstraintRegistry`] turns the resulting
+//! [`RepositoryError::ConstraintViolation`] into the field errors of
+//! [`ApiMutationErrorDto`], so the message lands next to the right input:
+//!
+//! ```ignore
+//! let registry = ConstraintRegistry::new()
+//!     .unique("customers", &["hub_id", "email"], "email", "Клиент с таким email уже есть.");
+//!
+//! match repo.create_customer(&new_customer) {
+//!     Err(err) => match registry.to_dto(&err) {
+//!         Some(dto) => HttpResponse::UnprocessableEntity().json(dto),
+//!         None => HttpResponse::InternalServerError().finish(),
+//!     },
+//!     Ok(customer) => ...,
+//! }
+//! ```
+//!
+//! Services use [`ConstraintRegistry::service_error`] instead:
+//!
+//! ```ignore
+//! repo.create_customer(&new_customer)
+//!     .map_err(|err| registry.service_error(err))?;
+//! ```
+//!
+//! Violations without a registered rule are reported on their last column,
+//! which for composite keys such as `(hub_id, email)` is the field the user
+//! typed.
+
+use crate::dto::mutation::{ApiFieldErrorDto, ApiMutationErrorDto};
+use crate::repository::errors::{ConstraintKind, ConstraintViolation, RepositoryError};
+use crate::services::errors::ServiceError;
+
+#[derive(Clone, Debug)]
+struct Rule {
+    kind: ConstraintKind,
+    table: Option<String>,
+    columns: Vec<String>,
+    name: Option<String>,
+    field: String,
+    message: String,
+}
+
+impl Rule {
+    fn matches(&self, violation: &ConstraintViolation) -> bool {
+        if self.kind != violation.kind {
+            return false;
+        }
+        if let Some(name) = &self.name {
+            return violation.name.as_ref() == Some(name);
+        }
+        let mut expected = self.columns.iter().collect::<Vec<_>>();
+        let mut actual = violation.columns.iter().collect::<Vec<_>>();
+        expected.sort();
+        actual.sort();
+        self.table == violation.table && expected == actual
+    }
+}
+
+/// Rules turning constraint violations into field errors.
+#[derive(Clone, Debug, Default)]
+pub struct ConstraintRegistry {
+    rules: Vec<Rule>,
+}
+
+impl ConstraintRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Report duplicates of `columns` in `table` on `field`.
+    pub fn unique(self, table: &str, columns: &[&str], field: &str, message: &str) -> Self {
+        self.rule(ConstraintKind::Unique, table, columns, field, message)
+    }
+
+    /// Report a missing `column` of `table` on `field`.
+    pub fn not_null(self, table: &str, column: &str, field: &str, message: &str) -> Self {
+        self.rule(ConstraintKind::NotNull, table, &[column], field, message)
+    }
+
+    /// Report violations of the `CHECK` constraint or expression index
+    /// `name` on `field`.
+    pub fn named(mut self, kind: ConstraintKind, name: &str, field: &str, message: &str) -> Self {
+        self.rules.push(Rule {
+            kind,
+            table: None,
+            columns: Vec::new(),
+            name: Some(name.to_string()),
+            field: field.to_string(),
+            message: message.to_string(),
+        });
+        self
+    }
+
+    fn rule(
+        mut self,
+        kind: ConstraintKind,
+        table: &str,
+        columns: &[&str],
+        field: &str,
+        message: &str,
+    ) -> Self {
+        self.rules.push(Rule {
+            kind,
+            table: Some(table.to_string()),
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+            name: None,
+            field: field.to_string(),
+            message: message.to_string(),
+        });
+        self
+    }
+
+    /// Field errors for `violation`.
+    ///
+    /// Without a matching rule, unique and not-null violations are reported
+    /// on their last column with a generic message; other violations have no
+    /// field to point at and produce no entries.
+    pub fn field_errors(&self, violation: &ConstraintViolation) -> Vec<ApiFieldErrorDto> {
+        if let Some(rule) = self.rules.iter().find(|rule| rule.matches(violation)) {
+            return vec![ApiFieldErrorDto {
+                field: rule.field.clone(),
+                message: rule.message.clone(),
+            }];
+        }
+        let message = match violation.kind {
+            ConstraintKind::Unique => "Такое значение уже существует.",
+            ConstraintKind::NotNull => "Обязательное поле.",
+            ConstraintKind::ForeignKey | ConstraintKind::Check => return Vec::new(),
+        };
+        violation
+            .columns
+            .last()
+            .map(|column| ApiFieldErrorDto {
+                field: column.clone(),
+                message: message.to_string(),
+            })
+            .into_iter()
+            .collect()
+    }
+
+    /// Response body for `error`, or `None` when it is not a constraint
+    /// violation.
+    pub fn to_dto(&self, error: &RepositoryError) -> Option<ApiMutationErrorDto> {
+        let RepositoryError::ConstraintViolation(violation) = error else {
+            return None;
+        };
+        let message = match violation.kind {
+            ConstraintKind::Unique => "Запись с такими данными уже существует.",
+            ConstraintKind::ForeignKey => "Связанная запись не найдена или ещё используется.",
+            ConstraintKind::NotNull => "Заполнены не все обязательные поля.",
+            ConstraintKind::Check => "Некорректные данные.",
+        };
+        Some(ApiMutationErrorDto {
+            message: message.to_string(),
+            field_errors: self.field_errors(violation),
+        })
+    }
+
+    /// Convert `error` for the service layer: constraint violations become
+    /// [`ServiceError::Validation`], everything else goes through
+    /// `From<RepositoryError>`.
+    pub fn service_error(&self, error: RepositoryError) -> ServiceError {
+        match self.to_dto(&error) {
+            Some(dto) => ServiceError::Validation {
+                message: dto.message,
+                field_errors: dto.field_errors,
+            },
+            None => error.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::connection::SimpleConnection;
+    use diesel::prelude::*;
+
+    fn violation(conn: &mut SqliteConnection, sql: &str) -> ConstraintViolation {
+        let err = diesel::sql_query(sql).execute(conn).unwrap_err();
+        match RepositoryError::from(err) {
+            RepositoryError::ConstraintViolation(violation) => violation,
+            other => panic!("expected a constraint violation, got {other:?}"),
+        }
+    }
+
+    fn connection() -> SqliteConnection {
+        let mut conn = SqliteConnection::establish(":memory:").unwrap();
+        conn.batch_execute(
+            "PRAGMA foreign_keys = ON;
+             CREATE TABLE hubs (id INTEGER PRIMARY KEY);
+             CREATE TABLE customers (
+                 id INTEGER PRIMARY KEY,
+                 hub_id INTEGER NOT NULL REFERENCES hubs(id),
+                 email TEXT NOT NULL,
+                 phone TEXT,
+                 discount INTEGER NOT NULL DEFAULT 0
+                     CONSTRAINT discount_range CHECK (discount BETWEEN 0 AND 100),
+                 UNIQUE (hub_id, email)
+             );
+             CREATE UNIQUE INDEX customers_phone ON customers (hub_id, lower(phone));
+             INSERT INTO hubs (id) VALUES (1);
+             INSERT INTO customers (hub_id, email, phone) VALUES (1, 'a@example.com', '+7 900');",
+        )
+        .unwrap();
+        conn
+    }
+
+    #[test]
+    fn parses_sqlite_constraint_messages() {
+        let mut conn = connection();
+
+        let unique = violation(
+            &mut conn,
+            "INSERT INTO customers (hub_id, email) VALUES (1, 'a@example.com')",
+        );
+        assert_eq!(unique.kind, ConstraintKind::Unique);
+        assert_eq!(unique.table.as_deref(), Some("customers"));
+        assert_eq!(unique.columns, ["hub_id", "email"]);
+
+        let not_null = violation(&mut conn, "INSERT INTO customers (hub_id) VALUES (1)");
+        assert_eq!(not_null.kind, ConstraintKind::NotNull);
+        assert_eq!(not_null.columns, ["email"]);
+
+        let check = violation(
+            &mut conn,
+            "INSERT INTO customers (hub_id, email, discount) VALUES (1, 'b@example.com', 120)",
+        );
+        assert_eq!(check.kind, ConstraintKind::Check);
+        assert_eq!(check.name.as_deref(), Some("discount_range"));
+
+        let index = violation(
+            &mut conn,
+            "INSERT INTO customers (hub_id, email, phone) VALUES (1, 'c@example.com', '+7 900')",
+        );
+        assert_eq!(index.kind, ConstraintKind::Unique);
+        assert_eq!(index.name.as_deref(), Some("customers_phone"));
+
+        let foreign = violation(
+            &mut conn,
+            "INSERT INTO customers (hub_id, email) VALUES (2, 'd@example.com')",
+        );
+        assert_eq!(foreign.kind, ConstraintKind::ForeignKey);
+        assert!(foreign.columns.is_empty());
+        assert_eq!(
+            RepositoryError::ConstraintViolation(foreign).to_string(),
+            "Constraint violation: Foreign key constraint violation: FOREIGN KEY constraint failed"
+        );
+    }
+
+    #[test]
+    fn registry_maps_violations_to_fields() {
+        let mut conn = connection();
+        let registry = ConstraintRegistry::new()
+            .unique(
+                "customers",
+                &["email", "hub_id"],
+                "email",
+                "Клиент с таким email уже есть.",
+            )
+            .named(
+                ConstraintKind::Unique,
+                "customers_phone",
+                "phone",
+                "Клиент с таким телефоном уже есть.",
+            );
+
+        let duplicate = RepositoryError::from(
+            diesel::sql_query("INSERT INTO customers (hub_id, email) VALUES (1, 'a@example.com')")
+                .execute(&mut conn)
+                .unwrap_err(),
+        );
+        let dto = registry.to_dto(&duplicate).unwrap();
+        assert_eq!(
+            dto.field_errors,
+            [ApiFieldErrorDto {
+                field: "email".into(),
+                message: "Клиент с таким email уже есть.".into(),
+            }]
+        );
+
+        let phone = violation(
+            &mut conn,
+            "INSERT INTO customers (hub_id, email, phone) VALUES (1, 'c@example.com', '+7 900')",
+        );
+        assert_eq!(registry.field_errors(&phone)[0].field, "phone");
+
+        let missing = violation(&mut conn, "INSERT INTO customers (hub_id) VALUES (1)");
+        assert_eq!(
+            registry.field_errors(&missing),
+            [ApiFieldErrorDto {
+                field: "email".into(),
+                message: "Обязательное поле.".into(),
+            }]
+        );
+
+        assert!(registry.to_dto(&RepositoryError::NotFound).is_none());
+        assert!(matches!(
+            registry.service_error(duplicate),
+            ServiceError::Validation { field_errors, .. } if field_errors[0].field == "email"
+        ));
+        assert!(matches!(
+            registry.service_error(RepositoryError::NotFound),
+            ServiceError::NotFound
+        ));
+    }
+}
@@ -2,29 +2,45 @@
 //!
 //! These errors provide a consistent abstraction over Diesel and
 //! connection-pool errors so callers can react without depending on the
-//! underlying database layer.
+//! underlying database layer. The original Diesel or pool error stays
+//! reachable through [`std::error::Error::source`] for logging, and
+//! [`RepositoryError::code`] gives clients a stable identifier to switch on.
+
+use std::fmt;
 
 use diesel::r2d2::{Error as R2D2Error, PoolError};
-use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use diesel::result::{DatabaseErrorInformation, DatabaseErrorKind, Error as DieselError};
 use thiserror::Error;
 
+/// Underlying cause of a [`RepositoryError`].
+pub type ErrorSource = Box<dyn std::error::Error + Send + Sync + 'static>;
+
 /// Errors that can occur while interacting with the persistence layer.
+///
+/// Variants converted from Diesel or pool errors keep the original error as
+/// their source. Errors raised by repository code itself have none and are
+/// built with the constructors, e.g. [`RepositoryError::validation`].
 #[derive(Debug, Error)]
 pub enum RepositoryError {
     #[error("Entity not found")]
     NotFound,
 
     #[error("Database error: {0}")]
-    DatabaseError(String),
+    DatabaseError(String, #[source] Option<ErrorSource>),
+
+    /// The database stayed locked by another writer (`SQLITE_BUSY` or
+    /// `SQLITE_LOCKED`); the operation may succeed when retried.
+    #[error("Database busy: {0}")]
+    Busy(String, #[source] Option<ErrorSource>),
 
     #[error("Validation error: {0}")]
-    ValidationError(String),
+    ValidationError(String, #[source] Option<ErrorSource>),
 
     #[error("Connection error: {0}")]
-    ConnectionError(String),
+    ConnectionError(String, #[source] Option<ErrorSource>),
 
     #[error("Constraint violation: {0}")]
-    ConstraintViolation(String),
+    ConstraintViolation(ConstraintViolation),
 
     #[error("Unexpected error: {0}")]
     Unexpected(String),
@@ -33,75 +49,266 @@ pub enum RepositoryError {
 /// Convenient alias for results returned by repository functions.
 pub type RepositoryResult<T> = Result<T, RepositoryError>;
 
+/// Kind of a violated database constraint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ConstraintKind {
+    Unique,
+    ForeignKey,
+    NotNull,
+    Check,
+}
+
+impl fmt::Display for ConstraintKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ConstraintKind::Unique => "Unique",
+            ConstraintKind::ForeignKey => "Foreign key",
+            ConstraintKind::NotNull => "Not null",
+            ConstraintKind::Check => "Check",
+        })
+    }
+}
+
+/// A violated constraint, as far as the database reported it.
+///
+/// SQLite names the table and columns of `UNIQUE` and `NOT NULL` failures,
+/// the constraint (or its expression) of `CHECK` failures, the index of
+/// failures on expression indexes and nothing at all for foreign keys.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConstraintViolation {
+    pub kind: ConstraintKind,
+    pub table: Option<String>,
+    pub columns: Vec<String>,
+    /// Name of the constraint or index.
+    pub name: Option<String>,
+    /// Message reported by the database.
+    pub message: String,
+}
+
+impl ConstraintViolation {
+    /// Parse a SQLite constraint failure message such as
+    /// `UNIQUE constraint failed: customers.hub_id, customers.email`.
+    pub fn parse(kind: ConstraintKind, message: &str) -> Self {
+        let mut violation = Self {
+            kind,
+            table: None,
+            columns: Vec::new(),
+            name: None,
+            message: message.to_string(),
+        };
+        let Some((_, detail)) = message.split_once("constraint failed: ") else {
+            return violation;
+        };
+        let detail = detail.trim();
+
+        if let Some(index) = detail.strip_prefix("index ") {
+            violation.name = Some(index.trim_matches('\'').to_string());
+        } else if kind == ConstraintKind::Check {
+            violation.name = Some(detail.to_string());
+        } else {
+            for column in detail.split(", ") {
+                match column.split_once('.') {
+                    Some((table, column)) => {
+                        violation.table.get_or_insert_with(|| table.to_string());
+                        violation.columns.push(column.to_string());
+                    }
+                    None => violation.columns.push(column.to_string()),
+                }
+            }
+        }
+        violation
+    }
+
+    fn from_diesel(kind: ConstraintKind, info: &dyn DatabaseErrorInformation) -> Self {
+        let mut violation = Self::parse(kind, info.message());
+        if violation.table.is_none() {
+            violation.table = info.table_name().map(str::to_string);
+        }
+        if violation.columns.is_empty()
+            && let Some(column) = info.column_name()
+        {
+            violation.columns.push(column.to_string());
+        }
+        if violation.name.is_none() {
+            violation.name = info.constraint_name().map(str::to_string);
+        }
+        violation
+    }
+}
+
+impl fmt::Display for ConstraintViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} constraint violation: {}", self.kind, self.message)
+    }
+}
+
 impl From<DieselError> for RepositoryError {
     fn from(err: DieselError) -> Self {
         match err {
             DieselError::NotFound => RepositoryError::NotFound,
 
             DieselError::DatabaseError(kind, info) => {
+                let constraint = match kind {
+                    DatabaseErrorKind::UniqueViolation => Some(ConstraintKind::Unique),
+                    DatabaseErrorKind::ForeignKeyViolation => Some(ConstraintKind::ForeignKey),
+                    DatabaseErrorKind::NotNullViolation => Some(ConstraintKind::NotNull),
+                    DatabaseErrorKind::CheckViolation => Some(ConstraintKind::Check),
+                    _ => None,
+                };
+                if let Some(constraint) = constraint {
+                    let violation = ConstraintViolation::from_diesel(constraint, info.as_ref());
+                    return RepositoryError::ConstraintViolation(violation);
+                }
                 let message = info.message().to_string();
-                match kind {
-                    DatabaseErrorKind::UniqueViolation => RepositoryError::ConstraintViolation(
-                        format!("Unique constraint violation: {message}"),
-                    ),
-                    DatabaseErrorKind::ForeignKeyViolation => RepositoryError::ConstraintViolation(
-                        format!("Foreign key constraint violation: {message}"),
-                    ),
-                    DatabaseErrorKind::NotNullViolation => RepositoryError::ConstraintViolation(
-                        format!("Not null constraint violation: {message}"),
-                    ),
-                    DatabaseErrorKind::CheckViolation => RepositoryError::ConstraintViolation(
-                        format!("Check constraint violation: {message}"),
-                    ),
-                    _ => RepositoryError::DatabaseError(message),
+                let source = Some(DieselError::DatabaseError(kind, info).into());
+                if is_busy_message(&message) {
+                    return RepositoryError::Busy(message, source);
                 }
+                RepositoryError::DatabaseError(message, source)
             }
 
-            DieselError::InvalidCString(_) => {
-                RepositoryError::ValidationError("Invalid C string".to_string())
+            err @ DieselError::InvalidCString(_) => {
+                RepositoryError::ValidationError("Invalid C string".to_string(), Some(err.into()))
             }
 
             DieselError::SerializationError(e) => {
-                RepositoryError::ValidationError(format!("Serialization error: {e}"))
+                RepositoryError::ValidationError(format!("Serialization error: {e}"), Some(e))
             }
 
             DieselError::DeserializationError(e) => {
-                RepositoryError::ValidationError(format!("Deserialization error: {e}"))
+                RepositoryError::ValidationError(format!("Deserialization error: {e}"), Some(e))
             }
 
             DieselError::QueryBuilderError(e) => {
-                RepositoryError::ValidationError(format!("Query builder error: {e}"))
+                RepositoryError::ValidationError(format!("Query builder error: {e}"), Some(e))
             }
 
-            DieselError::RollbackTransaction => {
-                RepositoryError::DatabaseError("Transaction rollback".to_string())
+            err @ DieselError::RollbackTransaction => {
+                RepositoryError::DatabaseError("Transaction rollback".to_string(), Some(err.into()))
             }
 
-            DieselError::AlreadyInTransaction => {
-                RepositoryError::DatabaseError("Already in transaction".to_string())
-            }
+            err @ DieselError::AlreadyInTransaction => RepositoryError::DatabaseError(
+                "Already in transaction".to_string(),
+                Some(err.into()),
+            ),
 
-            DieselError::NotInTransaction => {
-                RepositoryError::DatabaseError("Not in transaction".to_string())
+            err @ DieselError::NotInTransaction => {
+                RepositoryError::DatabaseError("Not in transaction".to_string(), Some(err.into()))
             }
 
-            DieselError::BrokenTransactionManager => {
-                RepositoryError::DatabaseError("Broken transaction manager".to_string())
-            }
+            err @ DieselError::BrokenTransactionManager => RepositoryError::DatabaseError(
+                "Broken transaction manager".to_string(),
+                Some(err.into()),
+            ),
+
+            err => RepositoryError::DatabaseError(err.to_string(), Some(err.into())),
+        }
+    }
+}
+
+impl RepositoryError {
+    /// A [`DatabaseError`](Self::DatabaseError) without a source.
+    pub fn database(message: impl Into<String>) -> Self {
+        RepositoryError::DatabaseError(message.into(), None)
+    }
+
+    /// A [`Busy`](Self::Busy) error without a source.
+    pub fn busy(message: impl Into<String>) -> Self {
+        RepositoryError::Busy(message.into(), None)
+    }
+
+    /// A [`ValidationError`](Self::ValidationError) without a source.
+    pub fn validation(message: impl Into<String>) -> Self {
+        RepositoryError::ValidationError(message.into(), None)
+    }
+
+    /// A [`ConnectionError`](Self::ConnectionError) without a source.
+    pub fn connection(message: impl Into<String>) -> Self {
+        RepositoryError::ConnectionError(message.into(), None)
+    }
 
-            _ => RepositoryError::Unexpected(format!("Unexpected diesel error: {err}")),
+    /// Whether the error is transient lock contention worth retrying.
+    pub fn is_busy(&self) -> bool {
+        matches!(self, RepositoryError::Busy(..))
+    }
+
+    /// Stable identifier of the error kind, such as `repository.not_found`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            RepositoryError::NotFound => "repository.not_found",
+            RepositoryError::DatabaseError(..) => "repository.database",
+            RepositoryError::Busy(..) => "repository.busy",
+            RepositoryError::ValidationError(..) => "repository.validation",
+            RepositoryError::ConnectionError(..) => "repository.connection",
+            RepositoryError::ConstraintViolation(violation) => match violation.kind {
+                ConstraintKind::Unique => "repository.unique_violation",
+                ConstraintKind::ForeignKey => "repository.foreign_key_violation",
+                ConstraintKind::NotNull => "repository.not_null_violation",
+                ConstraintKind::Check => "repository.check_violation",
+            },
+            RepositoryError::Unexpected(_) => "repository.unexpected",
         }
     }
 }
 
+/// SQLite reports `SQLITE_BUSY` and `SQLITE_LOCKED` only through the message.
+fn is_busy_message(message: &str) -> bool {
+    message.contains("database is locked")
+        || message.contains("database table is locked")
+        || message.contains("database is busy")
+}
+
 impl From<R2D2Error> for RepositoryError {
     fn from(err: R2D2Error) -> Self {
-        RepositoryError::ConnectionError(format!("Connection error: {err}"))
+        RepositoryError::ConnectionError(err.to_string(), Some(err.into()))
     }
 }
 
 impl From<PoolError> for RepositoryError {
     fn from(err: PoolError) -> Self {
-        RepositoryError::ConnectionError(format!("Connection error: {err}"))
+        RepositoryError::ConnectionError(err.to_string(), Some(err.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error as _;
+
+    use super::*;
+
+    #[test]
+    fn unclassified_database_errors_keep_their_source() {
+        let mut conn =
+            <diesel::SqliteConnection as diesel::Connection>::establish(":memory:").unwrap();
+        let err = RepositoryError::from(
+            diesel::RunQueryDsl::execute(diesel::sql_query("SELECT * FROM missing"), &mut conn)
+                .unwrap_err(),
+        );
+        assert!(matches!(err, RepositoryError::DatabaseError(..)));
+        assert_eq!(err.code(), "repository.database");
+        assert!(err.to_string().contains("no such table: missing"));
+        assert!(err.source().is_some());
+
+        let err = RepositoryError::from(DieselError::DeserializationError(
+            "invalid date".to_string().into(),
+        ));
+        assert!(matches!(err, RepositoryError::ValidationError(..)));
+        assert_eq!(err.source().unwrap().to_string(), "invalid date");
+
+        let err = RepositoryError::validation("Name is required");
+        assert_eq!(err.to_string(), "Validation error: Name is required");
+        assert!(err.source().is_none());
+        assert!(RepositoryError::busy("locked").is_busy());
+    }
+
+    #[test]
+    fn connection_errors_are_prefixed_once() {
+        let err = RepositoryError::from(R2D2Error::ConnectionError(
+            diesel::ConnectionError::BadConnection("unreachable".to_string()),
+        ));
+        let message = err.to_string();
+        assert!(message.starts_with("Connection error: "));
+        assert!(!message.contains("Connection error: Connection error"));
+        assert!(err.source().is_some());
     }
 }
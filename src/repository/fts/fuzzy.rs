@@ -0,0 +1,423 @@
+//! Typo-tolerant lookups for when full-text search finds nothing.
+//!
+//! FTS5 only matches whole tokens and prefixes, so `Ивнаов` never finds
+//! `Иванов`. An FTS5 `trigram` index does not help much either: short names
+//! with a swapped pair of letters share no trigram at all. Instead,
+//! [`FuzzySearch`] scans the source table and ranks rows with the
+//! `fuzzy_similarity` SQL function, which compares every query word with the
+//! closest word of the row using an edit distance that counts transpositions
+//! as one edit.
+//!
+//! The scan reads every row of the scope, which is fine for the customer and
+//! product lists of a hub but not for large logs. A search must be scoped,
+//! usually to a hub, or explicitly marked [`unscoped`](FuzzySearch::unscoped),
+//! because matches carry the text of the row. It usually runs as the
+//! fallback of an FTS search, with the same scope, or unscoped when the FTS
+//! search is:
+//!
+//! ```ignore
+//! let hits = CUSTOMERS_FTS
+//!     .search(query.to_match())
+//!     .scope("hub_id", user.hub_id)
+//!     .fuzzy_fallback(&search)
+//!     .load(conn)?;
+//! ```
+//!
+//! `fuzzy_similarity` is registered on every connection of pools built with
+//! [`DbPoolBuilder::unicode_functions`](crate::db::DbPoolBuilder::unicode_functions);
+//! other connections need one call to [`register`].
+
+use diesel::prelude::*;
+use diesel::query_builder::{BoxedSqlQuery, SqlQuery};
+use diesel::sql_types::{BigInt, Double, Nullable, Text};
+use diesel::sqlite::{Sqlite, SqliteConnection};
+
+use crate::db::unicode::normalize;
+use crate::repository::errors::{RepositoryError, RepositoryResult};
+
+use super::index::{FtsIndex, FtsMatch, Total, ident};
+
+/// Similarity below which rows are not returned.
+pub const DEFAULT_MIN_SIMILARITY: f64 = 0.7;
+
+/// Query words shorter than this are ignored.
+const MIN_WORD_CHARS: usize = 3;
+
+/// Weight of a match against the beginning of a longer word.
+const PREFIX_WEIGHT: f64 = 0.9;
+
+diesel::define_sql_function! {
+    /// Similarity between `text` and `query`, see [`similarity`].
+    fn fuzzy_similarity(text: Nullable<Text>, query: Text) -> Double;
+}
+
+/// Register `fuzzy_similarity(text, query)` on `conn`.
+pub fn register(conn: &mut SqliteConnection) -> QueryResult<()> {
+    fuzzy_similarity_utils::register_impl(conn, |text: Option<String>, query: String| {
+        text.map_or(0.0, |text| similarity(&text, &query))
+    })
+}
+
+/// How well `query` matches `text`, from `0.0` to `1.0`.
+///
+/// Both sides are normalized with [`normalize`]. Every query word of at least
+/// three letters is scored against the closest word of `text`, either as a
+/// whole word or as the beginning of a longer one, and the scores are
+/// averaged.
+pub fn similarity(text: &str, query: &str) -> f64 {
+    let text = normalize(text);
+    let query = normalize(query);
+    let text_words = words(&text);
+    let query_words = words(&query)
+        .into_iter()
+        .filter(|w| w.len() >= MIN_WORD_CHARS)
+        .collect::<Vec<_>>();
+    if query_words.is_empty() || text_words.is_empty() {
+        return 0.0;
+    }
+
+    let total: f64 = query_words
+        .iter()
+        .map(|q| {
+            text_words
+                .iter()
+                .map(|w| word_similarity(q, w))
+                .fold(0.0, f64::max)
+        })
+        .sum();
+    total / query_words.len() as f64
+}
+
+fn words(text: &str) -> Vec<Vec<char>> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.chars().collect())
+        .collect()
+}
+
+fn word_similarity(query: &[char], word: &[char]) -> f64 {
+    let whole = ratio(query, word);
+    if word.len() <= query.len() {
+        return whole;
+    }
+    let prefix = ratio(query, &word[..query.len()]) * PREFIX_WEIGHT;
+    whole.max(prefix)
+}
+
+fn ratio(a: &[char], b: &[char]) -> f64 {
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+    1.0 - edit_distance(a, b) as f64 / longest as f64
+}
+
+/// Optimal string alignment distance: insertions, deletions, substitutions
+/// and transpositions of adjacent characters all cost one.
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let width = b.len() + 1;
+    let mut rows = vec![vec![0usize; width]; a.len() + 1];
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        rows[i][0] = i;
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut best = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                best = best.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = best;
+        }
+    }
+    rows[a.len()][b.len()]
+}
+
+/// Rows a [`FuzzySearch`] may read.
+#[derive(Clone, Debug)]
+enum Scope {
+    Unset,
+    All,
+    Column(String, i64),
+}
+
+/// Fuzzy lookup over text columns of a table.
+#[derive(Clone, Debug)]
+pub struct FuzzySearch {
+    table: String,
+    id_column: String,
+    columns: Vec<String>,
+    scope: Scope,
+    min_similarity: f64,
+    limit: i64,
+    offset: i64,
+}
+
+/// A row returned by [`FuzzySearch::load`].
+#[derive(Clone, Debug, PartialEq, QueryableByName)]
+pub struct FuzzyMatch {
+    /// Primary key of the matching row.
+    #[diesel(sql_type = BigInt)]
+    pub rowid: i64,
+    /// Similarity between `0.0` and `1.0`; higher is better.
+    #[diesel(sql_type = Double)]
+    pub similarity: f64,
+    /// The searched columns joined with spaces.
+    #[diesel(sql_type = Text)]
+    pub text: String,
+}
+
+impl From<FuzzyMatch> for FtsMatch {
+    fn from(m: FuzzyMatch) -> Self {
+        FtsMatch {
+            rowid: m.rowid,
+            rank: -m.similarity,
+            snippet: m.text,
+            highlight: None,
+        }
+    }
+}
+
+impl FuzzySearch {
+    /// Search `columns` of `table`, returning its `id` column.
+    pub fn new(table: &str, columns: &[&str]) -> Self {
+        Self {
+            table: table.to_string(),
+            id_column: "id".to_string(),
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+            scope: Scope::Unset,
+            min_similarity: DEFAULT_MIN_SIMILARITY,
+            limit: 20,
+            offset: 0,
+        }
+    }
+
+    /// Search the source table and columns of `index`.
+    pub fn for_index(index: &FtsIndex) -> Self {
+        Self::new(index.content_table(), &index.columns()).id_column(index.content_rowid_column())
+    }
+
+    /// Integer primary key of the table.
+    pub fn id_column(mut self, column: &str) -> Self {
+        self.id_column = column.to_string();
+        self
+    }
+
+    /// Only read rows whose `column` equals `value`, e.g.
+    /// `.scope("hub_id", hub_id)`.
+    pub fn scope(mut self, column: &str, value: i64) -> Self {
+        self.scope = Scope::Column(column.to_string(), value);
+        self
+    }
+
+    /// Read every row of the table, for tables that are not owned by a hub.
+    pub fn unscoped(mut self) -> Self {
+        self.scope = Scope::All;
+        self
+    }
+
+    /// Similarity below which rows are skipped, [`DEFAULT_MIN_SIMILARITY`]
+    /// by default.
+    pub fn min_similarity(mut self, min_similarity: f64) -> Self {
+        self.min_similarity = min_similarity;
+        self
+    }
+
+    pub fn limit(mut self, limit: i64) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    pub fn offset(mut self, offset: i64) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Rows similar to `query`, most similar first.
+    ///
+    /// Fails with [`RepositoryError::ValidationError`] when neither
+    /// [`scope`](Self::scope) nor [`unscoped`](Self::unscoped) was called.
+    pub fn load(
+        &self,
+        conn: &mut SqliteConnection,
+        query: &str,
+    ) -> RepositoryResult<Vec<FuzzyMatch>> {
+        let sql = format!(
+            "SELECT rowid, similarity, text FROM ({})
+             ORDER BY similarity DESC, rowid LIMIT ? OFFSET ?",
+            self.scored_sql()?
+        );
+        let query = diesel::sql_query(sql)
+            .into_boxed::<Sqlite>()
+            .bind::<Text, _>(query);
+        Ok(self
+            .bind_scope(query)
+            .bind::<Double, _>(self.min_similarity)
+            .bind::<BigInt, _>(self.limit)
+            .bind::<BigInt, _>(self.offset)
+            .load(conn)?)
+    }
+
+    /// Number of rows similar to `query`, ignoring limit and offset.
+    pub fn count(&self, conn: &mut SqliteConnection, query: &str) -> RepositoryResult<i64> {
+        let sql = format!("SELECT COUNT(*) AS total FROM ({})", self.scored_sql()?);
+        let query = diesel::sql_query(sql)
+            .into_boxed::<Sqlite>()
+            .bind::<Text, _>(query);
+        let total = self
+            .bind_scope(query)
+            .bind::<Double, _>(self.min_similarity)
+            .get_result::<Total>(conn)?;
+        Ok(total.total)
+    }
+
+    fn bind_scope<'q>(
+        &self,
+        query: BoxedSqlQuery<'q, Sqlite, SqlQuery>,
+    ) -> BoxedSqlQuery<'q, Sqlite, SqlQuery> {
+        match &self.scope {
+            Scope::Column(_, value) => query.bind::<BigInt, _>(*value),
+            Scope::Unset | Scope::All => query,
+        }
+    }
+
+    /// Rows of the scope scoring at least the threshold, binding the query,
+    /// the scope value and the threshold.
+    fn scored_sql(&self) -> RepositoryResult<String> {
+        let filter = match &self.scope {
+            Scope::Unset => {
+                return Err(RepositoryError::validation(format!(
+                    "Fuzzy search over {} needs a scope",
+                    self.table
+                )));
+            }
+            Scope::All => String::new(),
+            Scope::Column(column, _) => format!(" WHERE {} = ?", ident(column)),
+        };
+        let text = self
+            .columns
+            .iter()
+            .map(|c| format!("COALESCE({}, '')", ident(c)))
+            .collect::<Vec<_>>()
+            .join(" || ' ' || ");
+        Ok(format!(
+            "SELECT rowid, text, similarity FROM (
+                 SELECT {id} AS rowid, TRIM({text}) AS text,
+                     fuzzy_similarity({text}, ?) AS similarity
+                 FROM {table}{filter})
+             WHERE similarity >= ?",
+            id = ident(&self.id_column),
+            table = ident(&self.table),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::fts::index::MATCH_START;
+    use diesel::connection::SimpleConnection;
+
+    #[test]
+    fn similarity_tolerates_typos_and_prefixes() {
+        assert!(similarity("Иван Иванов", "Ивнаов") > 0.8);
+        assert!(similarity("Пётр Сидоров", "сидоро") > 0.8);
+        assert!(similarity("Ёлкин", "елкин") == 1.0);
+        assert!(similarity("Иван Иванов", "Петров") < 0.5);
+        assert_eq!(similarity("Иван", "ab"), 0.0);
+    }
+
+    #[test]
+    fn edit_distance_counts_transpositions_once() {
+        let chars = |s: &str| s.chars().collect::<Vec<_>>();
+        assert_eq!(edit_distance(&chars("ивнаов"), &chars("иванов")), 1);
+        assert_eq!(edit_distance(&chars("kitten"), &chars("sitting")), 3);
+        assert_eq!(edit_distance(&chars(""), &chars("abc")), 3);
+    }
+
+    #[test]
+    fn fts_search_falls_back_to_fuzzy_matches() {
+        let mut conn = SqliteConnection::establish(":memory:").unwrap();
+        register(&mut conn).unwrap();
+        conn.batch_execute(
+            "CREATE TABLE customers (id INTEGER PRIMARY KEY, hub_id INTEGER NOT NULL, name TEXT NOT NULL, email TEXT);",
+        )
+        .unwrap();
+        let index = FtsIndex::new("customers", &["name", "email"]);
+        index.create(&mut conn).unwrap();
+        conn.batch_execute(
+            "INSERT INTO customers (hub_id, name, email) VALUES (1, 'Иван Иванов', NULL);
+             INSERT INTO customers (hub_id, name, email) VALUES (1, 'Иван Петров', 'petrov@example.com');
+             INSERT INTO customers (hub_id, name, email) VALUES (1, 'Анна Иванова', NULL);
+             INSERT INTO customers (hub_id, name, email) VALUES (2, 'Иван Иванов', 'other@example.com');",
+        )
+        .unwrap();
+
+        let exact = index
+            .search(r#""петров"*"#)
+            .scope("hub_id", 1)
+            .fuzzy_fallback("петров")
+            .load(&mut conn)
+            .unwrap();
+        assert_eq!(exact.iter().map(|m| m.rowid).collect::<Vec<_>>(), [2]);
+        assert!(exact[0].snippet.contains(MATCH_START));
+
+        let fuzzy = index
+            .search(r#""ивнаов"*"#)
+            .scope("hub_id", 1)
+            .fuzzy_fallback("Ивнаов")
+            .load(&mut conn)
+            .unwrap();
+        assert_eq!(fuzzy.iter().map(|m| m.rowid).collect::<Vec<_>>(), [1, 3]);
+        assert!(fuzzy[0].rank < fuzzy[1].rank);
+        assert_eq!(fuzzy[0].snippet, "Иван Иванов");
+        let search = index
+            .search(r#""ивнаов"*"#)
+            .scope("hub_id", 1)
+            .fuzzy_fallback("Ивнаов");
+        assert_eq!(search.count(&mut conn).unwrap(), 2);
+        let second_page = search.offset(1).load(&mut conn).unwrap();
+        assert_eq!(second_page.iter().map(|m| m.rowid).collect::<Vec<_>>(), [3]);
+
+        let none = index.search(r#""ивнаов"*"#).load(&mut conn).unwrap();
+        assert!(none.is_empty());
+
+        let unscoped = index.search(r#""ивнаов"*"#).fuzzy_fallback("Ивнаов");
+        assert_eq!(
+            unscoped
+                .load(&mut conn)
+                .unwrap()
+                .iter()
+                .map(|m| m.rowid)
+                .collect::<Vec<_>>(),
+            [1, 4, 3]
+        );
+        assert_eq!(unscoped.count(&mut conn).unwrap(), 3);
+    }
+
+    #[test]
+    fn fuzzy_search_requires_a_scope() {
+        let mut conn = SqliteConnection::establish(":memory:").unwrap();
+        register(&mut conn).unwrap();
+        conn.batch_execute(
+            "CREATE TABLE customers (id INTEGER PRIMARY KEY, hub_id INTEGER NOT NULL, name TEXT NOT NULL);
+             INSERT INTO customers (hub_id, name) VALUES (1, 'Иван Иванов');
+             INSERT INTO customers (hub_id, name) VALUES (2, 'Иван Иванов');",
+        )
+        .unwrap();
+        let search = FuzzySearch::new("customers", &["name"]);
+
+        assert!(matches!(
+            search.load(&mut conn, "Ивнаов"),
+            Err(RepositoryError::ValidationError(..))
+        ));
+        let scoped = search.clone().scope("hub_id", 2);
+        let hits = scoped.load(&mut conn, "Ивнаов").unwrap();
+        assert_eq!(hits.iter().map(|m| m.rowid).collect::<Vec<_>>(), [2]);
+        assert_eq!(scoped.count(&mut conn, "Ивнаов").unwrap(), 1);
+        assert_eq!(search.unscoped().count(&mut conn, "Ивнаов").unwrap(), 2);
+    }
+}
@@ -0,0 +1,623 @@
+//! External-content FTS5 indexes over regular tables.
+//!
+//! [`FtsIndex`] generates the virtual table and the triggers that keep it in
+//! sync with its source table, runs maintenance commands and searches the
+//! index:
+//!
+//! ```ignore
+//! static CUSTOMERS_FTS: LazyLock<FtsIndex> =
+//!     LazyLock::new(|| FtsIndex::new("customers", &["name", "email", "phone"]));
+//!
+//! // up.sql / down.sql
+//! println!("{}", CUSTOMERS_FTS.create_sql());
+//! println!("{}", CUSTOMERS_FTS.drop_sql());
+//!
+//! let columns = CUSTOMERS_FTS.columns();
+//! let query = FtsQueryParser::new().columns(&columns).parse(&search)?;
+//! let hits = CUSTOMERS_FTS
+//!     .search(query.to_match())
+//!     .scope("hub_id", user.hub_id)
+//!     .highlight("name")
+//!     .limit(20)
+//!     .load(conn)?;
+//! ```
+//!
+//! The index covers the whole source table. Searches over hub-owned records
+//! must be scoped, otherwise they page through and count the rows of every
+//! hub.
+//!
+//! Snippets and highlights are raw column text with markers around matched
+//! tokens. With the default markers, [`FtsMatch::snippet_html`] and
+//! [`FtsMatch::highlight_html`] escape the text and wrap matches in `<mark>`.
+
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use diesel::query_builder::{BoxedSqlQuery, SqlQuery};
+use diesel::sql_types::{BigInt, Double, Integer, Nullable, Text};
+use diesel::sqlite::{Sqlite, SqliteConnection};
+
+use crate::repository::errors::{RepositoryError, RepositoryResult};
+
+use super::fuzzy::FuzzySearch;
+
+/// Default marker inserted before a matched token.
+pub const MATCH_START: &str = "\u{E000}";
+/// Default marker inserted after a matched token.
+pub const MATCH_END: &str = "\u{E001}";
+
+const DEFAULT_TOKENIZE: &str = "unicode61 remove_diacritics 2";
+
+/// Definition of an FTS5 index whose content lives in another table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FtsIndex {
+    name: String,
+    content: String,
+    content_rowid: String,
+    columns: Vec<String>,
+    tokenize: String,
+    normalized: bool,
+}
+
+impl FtsIndex {
+    /// Index `columns` of `content_table` in `<content_table>_fts`, keyed by
+    /// its `id` column.
+    pub fn new(content_table: &str, columns: &[&str]) -> Self {
+        Self {
+            name: format!("{content_table}_fts"),
+            content: content_table.to_string(),
+            content_rowid: "id".to_string(),
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+            tokenize: DEFAULT_TOKENIZE.to_string(),
+            normalized: false,
+        }
+    }
+
+    /// Name of the virtual table.
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    /// Integer primary key of the source table.
+    pub fn content_rowid(mut self, column: &str) -> Self {
+        self.content_rowid = column.to_string();
+        self
+    }
+
+    /// FTS5 `tokenize` option, `unicode61 remove_diacritics 2` by default.
+    pub fn tokenize(mut self, tokenize: &str) -> Self {
+        self.tokenize = tokenize.to_string();
+        self
+    }
+
+    /// Index text through the `normalize_text` SQL function, so `ё` and `е`
+    /// match each other. Queries must be normalized the same way, see
+    /// [`RussianNormalizer`](super::RussianNormalizer).
+    ///
+    /// Every connection writing the source table needs the function, i.e. a
+    /// pool built with
+    /// [`DbPoolBuilder::unicode_functions`](crate::db::DbPoolBuilder::unicode_functions).
+    /// Highlights are computed on the original text and do not mark words
+    /// that only match after normalization.
+    pub fn normalized(mut self, enable: bool) -> Self {
+        self.normalized = enable;
+        self
+    }
+
+    /// Name of the virtual table.
+    pub fn table(&self) -> &str {
+        &self.name
+    }
+
+    /// Source table of the index.
+    pub fn content_table(&self) -> &str {
+        &self.content
+    }
+
+    /// Integer primary key of the source table.
+    pub fn content_rowid_column(&self) -> &str {
+        &self.content_rowid
+    }
+
+    /// Indexed columns, usable as the whitelist of
+    /// [`FtsQueryParser::columns`](super::FtsQueryParser::columns).
+    pub fn columns(&self) -> Vec<&str> {
+        self.columns.iter().map(String::as_str).collect()
+    }
+
+    /// DDL creating the virtual table and its sync triggers.
+    pub fn create_sql(&self) -> String {
+        let fts = ident(&self.name);
+        let content = ident(&self.content);
+        let rowid = ident(&self.content_rowid);
+        let columns = self.column_list();
+        let new_values = self.value_list("new.");
+        let old_values = self.value_list("old.");
+        let watched = format!("{rowid}, {columns}");
+
+        format!(
+            "CREATE VIRTUAL TABLE IF NOT EXISTS {fts} USING fts5({columns}, content={}, content_rowid={}, tokenize={});
+CREATE TRIGGER IF NOT EXISTS {ai} AFTER INSERT ON {content} BEGIN
+    INSERT INTO {fts}(rowid, {columns}) VALUES (new.{rowid}, {new_values});
+END;
+CREATE TRIGGER IF NOT EXISTS {ad} AFTER DELETE ON {content} BEGIN
+    INSERT INTO {fts}({fts}, rowid, {columns}) VALUES ('delete', old.{rowid}, {old_values});
+END;
+CREATE TRIGGER IF NOT EXISTS {au} AFTER UPDATE OF {watched} ON {content} BEGIN
+    INSERT INTO {fts}({fts}, rowid, {columns}) VALUES ('delete', old.{rowid}, {old_values});
+    INSERT INTO {fts}(rowid, {columns}) VALUES (new.{rowid}, {new_values});
+END;",
+            literal(&self.content),
+            literal(&self.content_rowid),
+            literal(&self.tokenize),
+            ai = self.trigger("ai"),
+            ad = self.trigger("ad"),
+            au = self.trigger("au"),
+        )
+    }
+
+    /// DDL dropping the triggers and the virtual table.
+    pub fn drop_sql(&self) -> String {
+        format!(
+            "DROP TRIGGER IF EXISTS {};
+DROP TRIGGER IF EXISTS {};
+DROP TRIGGER IF EXISTS {};
+DROP TABLE IF EXISTS {};",
+            self.trigger("ai"),
+            self.trigger("ad"),
+            self.trigger("au"),
+            ident(&self.name)
+        )
+    }
+
+    /// Execute [`create_sql`](Self::create_sql).
+    pub fn create(&self, conn: &mut SqliteConnection) -> RepositoryResult<()> {
+        conn.batch_execute(&self.create_sql())?;
+        Ok(())
+    }
+
+    /// Re-read every row of the source table, for example after bulk loads
+    /// that bypassed the triggers.
+    pub fn rebuild(&self, conn: &mut SqliteConnection) -> RepositoryResult<()> {
+        if !self.normalized {
+            return self.command(conn, "rebuild");
+        }
+        // The built-in rebuild would index the raw column values.
+        self.command(conn, "delete-all")?;
+        conn.batch_execute(&format!(
+            "INSERT INTO {}(rowid, {}) SELECT {}, {} FROM {}",
+            ident(&self.name),
+            self.column_list(),
+            ident(&self.content_rowid),
+            self.value_list(""),
+            ident(&self.content)
+        ))?;
+        Ok(())
+    }
+
+    /// Merge the index b-trees to speed up queries.
+    pub fn optimize(&self, conn: &mut SqliteConnection) -> RepositoryResult<()> {
+        self.command(conn, "optimize")
+    }
+
+    /// Search the index with an FTS5 `MATCH` expression, best matches first.
+    pub fn search(&self, match_expr: impl Into<String>) -> FtsSearch<'_> {
+        FtsSearch {
+            index: self,
+            match_expr: match_expr.into(),
+            highlight: None,
+            markers: (MATCH_START.to_string(), MATCH_END.to_string()),
+            ellipsis: "…".to_string(),
+            snippet_tokens: 16,
+            scope: None,
+            limit: 20,
+            offset: 0,
+            fuzzy: None,
+        }
+    }
+
+    fn command(&self, conn: &mut SqliteConnection, command: &str) -> RepositoryResult<()> {
+        let fts = ident(&self.name);
+        conn.batch_execute(&format!("INSERT INTO {fts}({fts}) VALUES ('{command}')"))?;
+        Ok(())
+    }
+
+    fn column_list(&self) -> String {
+        self.columns
+            .iter()
+            .map(|c| ident(c))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Column values as inserted into the index.
+    fn value_list(&self, prefix: &str) -> String {
+        self.columns
+            .iter()
+            .map(|c| {
+                if self.normalized {
+                    format!("normalize_text({prefix}{})", ident(c))
+                } else {
+                    format!("{prefix}{}", ident(c))
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn trigger(&self, suffix: &str) -> String {
+        ident(&format!("{}_{suffix}", self.name))
+    }
+}
+
+/// A search over an [`FtsIndex`].
+#[derive(Clone, Debug)]
+pub struct FtsSearch<'a> {
+    index: &'a FtsIndex,
+    match_expr: String,
+    highlight: Option<String>,
+    markers: (String, String),
+    ellipsis: String,
+    snippet_tokens: i32,
+    scope: Option<(String, i64)>,
+    limit: i64,
+    offset: i64,
+    fuzzy: Option<String>,
+}
+
+/// A row returned by [`FtsSearch::load`].
+#[derive(Clone, Debug, PartialEq, QueryableByName)]
+pub struct FtsMatch {
+    /// Primary key of the matching row in the source table.
+    #[diesel(sql_type = BigInt)]
+    pub rowid: i64,
+    /// BM25 rank, or the negated similarity of a fuzzy match; lower is
+    /// better.
+    #[diesel(sql_type = Double)]
+    pub rank: f64,
+    /// Fragment of the best matching column. Fuzzy matches carry the
+    /// searched columns joined with spaces and no markers.
+    #[diesel(sql_type = Text)]
+    pub snippet: String,
+    /// Full text of the column passed to [`FtsSearch::highlight`].
+    #[diesel(sql_type = Nullable<Text>)]
+    pub highlight: Option<String>,
+}
+
+impl FtsMatch {
+    /// [`snippet`](Self::snippet) as escaped HTML with `<mark>` around matches.
+    pub fn snippet_html(&self) -> String {
+        marked_html(&self.snippet)
+    }
+
+    /// [`highlight`](Self::highlight) as escaped HTML with `<mark>` around
+    /// matches.
+    pub fn highlight_html(&self) -> Option<String> {
+        self.highlight.as_deref().map(marked_html)
+    }
+}
+
+#[derive(QueryableByName)]
+pub(super) struct Total {
+    #[diesel(sql_type = BigInt)]
+    pub(super) total: i64,
+}
+
+impl FtsSearch<'_> {
+    /// Also return the full text of `column` with matches marked.
+    pub fn highlight(mut self, column: &str) -> Self {
+        self.highlight = Some(column.to_string());
+        self
+    }
+
+    /// Text inserted around matched tokens instead of [`MATCH_START`] and
+    /// [`MATCH_END`].
+    pub fn markers(mut self, start: &str, end: &str) -> Self {
+        self.markers = (start.to_string(), end.to_string());
+        self
+    }
+
+    /// Text marking truncated snippets, `…` by default.
+    pub fn ellipsis(mut self, ellipsis: &str) -> Self {
+        self.ellipsis = ellipsis.to_string();
+        self
+    }
+
+    /// Maximum number of tokens in a snippet, between 1 and 64.
+    pub fn snippet_tokens(mut self, tokens: i32) -> Self {
+        self.snippet_tokens = tokens.clamp(1, 64);
+        self
+    }
+
+    /// Only match rows of the source table whose `column` equals `value`,
+    /// e.g. `.scope("hub_id", hub_id)`. The filter applies before paging and
+    /// counting.
+    pub fn scope(mut self, column: &str, value: i64) -> Self {
+        self.scope = Some((column.to_string(), value));
+        self
+    }
+
+    pub fn limit(mut self, limit: i64) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    pub fn offset(mut self, offset: i64) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// When the `MATCH` expression finds nothing, rank rows by their
+    /// similarity to `text` instead, see [`FuzzySearch`].
+    ///
+    /// `text` is what the user typed, before it was parsed into the
+    /// expression. The fallback reads the same [`scope`](Self::scope), and
+    /// every row of the table when the search is not scoped.
+    pub fn fuzzy_fallback(mut self, text: &str) -> Self {
+        self.fuzzy = Some(text.to_string());
+        self
+    }
+
+    /// Load one page of matches.
+    pub fn load(&self, conn: &mut SqliteConnection) -> RepositoryResult<Vec<FtsMatch>> {
+        let matches = self.load_matches(conn)?;
+        match &self.fuzzy {
+            Some(text)
+                if matches.is_empty() && (self.offset == 0 || self.count_matches(conn)? == 0) =>
+            {
+                Ok(self
+                    .fuzzy_search()
+                    .load(conn, text)?
+                    .into_iter()
+                    .map(FtsMatch::from)
+                    .collect())
+            }
+            _ => Ok(matches),
+        }
+    }
+
+    /// Number of matching rows, ignoring limit and offset.
+    pub fn count(&self, conn: &mut SqliteConnection) -> RepositoryResult<i64> {
+        let total = self.count_matches(conn)?;
+        match &self.fuzzy {
+            Some(text) if total == 0 => self.fuzzy_search().count(conn, text),
+            _ => Ok(total),
+        }
+    }
+
+    fn fuzzy_search(&self) -> FuzzySearch {
+        let search = FuzzySearch::for_index(self.index)
+            .limit(self.limit)
+            .offset(self.offset);
+        match &self.scope {
+            Some((column, value)) => search.scope(column, *value),
+            None => search.unscoped(),
+        }
+    }
+
+    fn load_matches(&self, conn: &mut SqliteConnection) -> RepositoryResult<Vec<FtsMatch>> {
+        let highlight = match &self.highlight {
+            Some(column) => self
+                .index
+                .columns
+                .iter()
+                .position(|c| c == column)
+                .map(|i| i as i32)
+                .ok_or_else(|| {
+                    RepositoryError::validation(format!(
+                        "Column {column} is not indexed by {}",
+                        self.index.name
+                    ))
+                })
+                .map(Some)?,
+            None => None,
+        };
+        let fts = ident(&self.index.name);
+        let sql = format!(
+            "SELECT {fts}.rowid AS rowid, {fts}.rank AS rank,
+                 snippet({fts}, -1, ?, ?, ?, ?) AS snippet,
+                 CASE WHEN ? IS NULL THEN NULL ELSE highlight({fts}, ?, ?, ?) END AS highlight
+             {} ORDER BY {fts}.rank LIMIT ? OFFSET ?",
+            self.matching_sql()
+        );
+        let (start, end) = &self.markers;
+        let query = diesel::sql_query(sql)
+            .into_boxed::<Sqlite>()
+            .bind::<Text, _>(start)
+            .bind::<Text, _>(end)
+            .bind::<Text, _>(&self.ellipsis)
+            .bind::<Integer, _>(self.snippet_tokens)
+            .bind::<Nullable<Integer>, _>(highlight)
+            .bind::<Integer, _>(highlight.unwrap_or(0))
+            .bind::<Text, _>(start)
+            .bind::<Text, _>(end);
+        Ok(self
+            .bind_matching(query)
+            .bind::<BigInt, _>(self.limit)
+            .bind::<BigInt, _>(self.offset)
+            .load(conn)?)
+    }
+
+    fn count_matches(&self, conn: &mut SqliteConnection) -> RepositoryResult<i64> {
+        let query = diesel::sql_query(format!("SELECT COUNT(*) AS total {}", self.matching_sql()))
+            .into_boxed::<Sqlite>();
+        let total = self.bind_matching(query).get_result::<Total>(conn)?;
+        Ok(total.total)
+    }
+
+    /// `FROM ... WHERE ...` of the matching rows, joined with the source
+    /// table when the search is scoped.
+    fn matching_sql(&self) -> String {
+        let fts = ident(&self.index.name);
+        match &self.scope {
+            Some((column, _)) => format!(
+                "FROM {fts} JOIN {content} AS scoped ON scoped.{rowid} = {fts}.rowid
+                 WHERE {fts} MATCH ? AND scoped.{column} = ?",
+                content = ident(&self.index.content),
+                rowid = ident(&self.index.content_rowid),
+                column = ident(column),
+            ),
+            None => format!("FROM {fts} WHERE {fts} MATCH ?"),
+        }
+    }
+
+    /// Bind the parameters of [`matching_sql`](Self::matching_sql).
+    fn bind_matching<'q>(
+        &'q self,
+        query: BoxedSqlQuery<'q, Sqlite, SqlQuery>,
+    ) -> BoxedSqlQuery<'q, Sqlite, SqlQuery> {
+        let query = query.bind::<Text, _>(&self.match_expr);
+        match &self.scope {
+            Some((_, value)) => query.bind::<BigInt, _>(*value),
+            None => query,
+        }
+    }
+}
+
+/// Escape `text` for HTML and turn the default match markers into `<mark>`.
+pub fn marked_html(text: &str) -> String {
+    let mut html = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            '\u{E000}' => html.push_str("<mark>"),
+            '\u{E001}' => html.push_str("</mark>"),
+            _ => html.push(c),
+        }
+    }
+    html
+}
+
+pub(super) fn ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn database() -> (SqliteConnection, FtsIndex) {
+        let mut conn = SqliteConnection::establish(":memory:").unwrap();
+        conn.batch_execute(
+            "CREATE TABLE customers (id INTEGER PRIMARY KEY, name TEXT NOT NULL, email TEXT NOT NULL, note TEXT);
+             INSERT INTO customers (name, email) VALUES ('Ivan Petrov', 'ivan@example.com');",
+        )
+        .unwrap();
+        let index = FtsIndex::new("customers", &["name", "email"]);
+        index.create(&mut conn).unwrap();
+        (conn, index)
+    }
+
+    fn ids(conn: &mut SqliteConnection, index: &FtsIndex, expr: &str) -> Vec<i64> {
+        index
+            .search(expr)
+            .load(conn)
+            .unwrap()
+            .into_iter()
+            .map(|m| m.rowid)
+            .collect()
+    }
+
+    #[test]
+    fn triggers_keep_index_in_sync() {
+        let (mut conn, index) = database();
+        // Rows inserted before the index existed are only found after a rebuild.
+        assert!(ids(&mut conn, &index, "ivan").is_empty());
+        index.rebuild(&mut conn).unwrap();
+        assert_eq!(ids(&mut conn, &index, "ivan"), [1]);
+
+        conn.batch_execute(
+            "INSERT INTO customers (name, email) VALUES ('Petr Ivanov', 'petr@gmail.com');
+             UPDATE customers SET name = 'Ivan Sidorov' WHERE id = 1;
+             UPDATE customers SET note = 'ignored' WHERE id = 2;",
+        )
+        .unwrap();
+        assert_eq!(ids(&mut conn, &index, "petr"), [2]);
+        assert_eq!(ids(&mut conn, &index, "sidorov"), [1]);
+        assert!(ids(&mut conn, &index, "petrov").is_empty());
+
+        conn.batch_execute("DELETE FROM customers WHERE id = 2")
+            .unwrap();
+        assert!(ids(&mut conn, &index, "petr").is_empty());
+        index.optimize(&mut conn).unwrap();
+    }
+
+    #[test]
+    fn search_returns_rank_snippet_and_highlight() {
+        let (mut conn, index) = database();
+        index.rebuild(&mut conn).unwrap();
+
+        let search = index.search(r#""petrov""#).highlight("name");
+        let hits = search.load(&mut conn).unwrap();
+
+        assert_eq!(search.count(&mut conn).unwrap(), 1);
+        assert_eq!(hits.len(), 1);
+        assert!(hits[0].rank < 0.0);
+        assert_eq!(hits[0].snippet_html(), "Ivan <mark>Petrov</mark>");
+        assert_eq!(
+            hits[0].highlight_html().as_deref(),
+            Some("Ivan <mark>Petrov</mark>")
+        );
+        assert!(
+            index
+                .search("ivan")
+                .highlight("note")
+                .load(&mut conn)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn scoped_search_pages_and_counts_one_hub() {
+        let mut conn = SqliteConnection::establish(":memory:").unwrap();
+        conn.batch_execute(
+            "CREATE TABLE customers (id INTEGER PRIMARY KEY, hub_id INTEGER NOT NULL, name TEXT NOT NULL);
+             INSERT INTO customers (hub_id, name) VALUES (2, 'Ivan Petrov');
+             INSERT INTO customers (hub_id, name) VALUES (2, 'Ivan Sidorov');
+             INSERT INTO customers (hub_id, name) VALUES (1, 'Ivan Ivanov');
+             INSERT INTO customers (hub_id, name) VALUES (1, 'Ivan Smirnov');",
+        )
+        .unwrap();
+        let index = FtsIndex::new("customers", &["name"]);
+        index.create(&mut conn).unwrap();
+        index.rebuild(&mut conn).unwrap();
+
+        let search = index.search("ivan").scope("hub_id", 1).limit(1);
+        assert_eq!(search.count(&mut conn).unwrap(), 2);
+        let mut ids = vec![
+            search.load(&mut conn).unwrap()[0].rowid,
+            search.clone().offset(1).load(&mut conn).unwrap()[0].rowid,
+        ];
+        ids.sort();
+        assert_eq!(ids, [3, 4]);
+        assert!(search.offset(2).load(&mut conn).unwrap().is_empty());
+        assert_eq!(index.search("ivan").count(&mut conn).unwrap(), 4);
+    }
+
+    #[test]
+    fn drop_sql_removes_everything() {
+        let (mut conn, index) = database();
+
+        conn.batch_execute(&index.drop_sql()).unwrap();
+
+        conn.batch_execute("INSERT INTO customers (name, email) VALUES ('a', 'b')")
+            .unwrap();
+        assert!(index.search("a").load(&mut conn).is_err());
+    }
+
+    #[test]
+    fn marked_html_escapes_text() {
+        assert_eq!(
+            marked_html("<b>\u{E000}Tom & Jerry\u{E001}</b>"),
+            "&lt;b&gt;<mark>Tom &amp; Jerry</mark>&lt;/b&gt;"
+        );
+    }
+}
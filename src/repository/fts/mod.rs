@@ -0,0 +1,17 @@
+//! SQLite FTS5 helpers.
+//!
+//! [`query`] compiles the search syntax typed by users into safe `MATCH`
+//! expressions, and [`index`] manages external-content indexes and searches
+//! them with ranking and highlighting. [`russian`] adapts queries to Russian
+//! text: `ё` folding, light stemming and keyboard layout correction, and
+//! [`fuzzy`] ranks rows by similarity when a query with a typo finds nothing.
+
+pub mod fuzzy;
+pub mod index;
+pub mod query;
+pub mod russian;
+
+pub use fuzzy::{FuzzyMatch, FuzzySearch};
+pub use index::{FtsIndex, FtsMatch, FtsSearch};
+pub use query::{FtsQuery, FtsQueryError, FtsQueryParser, FtsTerm};
+pub use russian::RussianNormalizer;
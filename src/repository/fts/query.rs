@@ -0,0 +1,454 @@
+//! User-facing search syntax compiled to FTS5 `MATCH` expressions.
+//!
+//! The syntax is deliberately small:
+//!
+//! * `ivan petrov` — every term must match;
+//! * `"ivan petrov"` — exact phrase;
+//! * `-blocked` or `-"on hold"` — exclude a term or phrase;
+//! * `ivan OR petr` — either term; `OR` binds tighter than the implicit AND;
+//! * `name:ivan`, `name:"ivan petrov"` — restrict to a whitelisted column;
+//! * `iva*` — prefix search.
+//!
+//! Every term is emitted as a quoted FTS5 string, so punctuation, `AND`,
+//! `NOT`, `NEAR` and other FTS5 syntax typed by users is matched literally.
+//!
+//! ```ignore
+//! let expr = FtsQueryParser::new()
+//!     .columns(&["name", "email", "phone"])
+//!     .parse(&params.search)?
+//!     .to_match();
+//! ```
+
+use std::fmt;
+
+use thiserror::Error;
+
+/// Errors produced while parsing a search query.
+///
+/// Positions are byte offsets into the raw query.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum FtsQueryError {
+    #[error("search query is empty")]
+    Empty,
+
+    #[error("unterminated quote starting at {position}")]
+    UnterminatedQuote { position: usize },
+
+    #[error("unknown search column `{column}` at {position}")]
+    UnknownColumn { column: String, position: usize },
+
+    #[error("`OR` at {position} must stand between two terms")]
+    DanglingOr { position: usize },
+
+    #[error("excluded term at {position} cannot be part of `OR`")]
+    ExclusionInOr { position: usize },
+
+    #[error("search query only excludes terms")]
+    OnlyExclusions,
+}
+
+/// A single term or phrase of a parsed query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FtsTerm {
+    /// Column the term is restricted to.
+    pub column: Option<String>,
+    /// Text as typed, without quotes, column and `*`.
+    pub text: String,
+    /// Written in double quotes.
+    pub phrase: bool,
+    /// Matches every token starting with the last token of `text`.
+    pub prefix: bool,
+}
+
+impl fmt::Display for FtsTerm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(column) = &self.column {
+            write!(f, "{column}:")?;
+        }
+        write!(f, "\"{}\"", self.text.replace('"', "\"\""))?;
+        if self.prefix {
+            f.write_str("*")?;
+        }
+        Ok(())
+    }
+}
+
+/// A parsed search query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FtsQuery {
+    /// Groups that must all match; the terms of a group are alternatives.
+    pub required: Vec<Vec<FtsTerm>>,
+    /// Terms that must not match.
+    pub excluded: Vec<FtsTerm>,
+}
+
+impl FtsQuery {
+    /// The FTS5 `MATCH` expression.
+    pub fn to_match(&self) -> String {
+        self.to_string()
+    }
+
+    /// Every term of the query, required ones first.
+    pub fn terms(&self) -> impl Iterator<Item = &FtsTerm> {
+        self.required.iter().flatten().chain(&self.excluded)
+    }
+}
+
+impl fmt::Display for FtsQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let wrap = !self.excluded.is_empty()
+            && (self.required.len() > 1 || self.required.iter().any(|g| g.len() > 1));
+        if wrap {
+            f.write_str("(")?;
+        }
+        for (i, group) in self.required.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" AND ")?;
+            }
+            if group.len() > 1 {
+                f.write_str("(")?;
+            }
+            for (j, term) in group.iter().enumerate() {
+                if j > 0 {
+                    f.write_str(" OR ")?;
+                }
+                write!(f, "{term}")?;
+            }
+            if group.len() > 1 {
+                f.write_str(")")?;
+            }
+        }
+        if wrap {
+            f.write_str(")")?;
+        }
+        for term in &self.excluded {
+            write!(f, " NOT {term}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parser for the search syntax described in the [module docs](self).
+#[derive(Debug, Clone)]
+pub struct FtsQueryParser<'a> {
+    columns: &'a [&'a str],
+    prefix_last: bool,
+}
+
+impl Default for FtsQueryParser<'_> {
+    fn default() -> Self {
+        Self {
+            columns: &[],
+            prefix_last: true,
+        }
+    }
+}
+
+enum Token {
+    Term {
+        term: FtsTerm,
+        negated: bool,
+        position: usize,
+    },
+    Or {
+        position: usize,
+    },
+}
+
+impl<'a> FtsQueryParser<'a> {
+    /// Parser without searchable columns that prefix-matches the last term,
+    /// like [`build_fts_match_query`](crate::repository::build_fts_match_query).
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Columns that may be targeted with `column:term`.
+    pub fn columns(mut self, columns: &'a [&'a str]) -> Self {
+        self.columns = columns;
+        self
+    }
+
+    /// Treat a trailing bare term as a prefix, for search-as-you-type.
+    pub fn prefix_last(mut self, enable: bool) -> Self {
+        self.prefix_last = enable;
+        self
+    }
+
+    /// Parse `raw` into a query.
+    pub fn parse(&self, raw: &str) -> Result<FtsQuery, FtsQueryError> {
+        let mut tokens = self.tokenize(raw)?;
+
+        if self.prefix_last
+            && let Some(Token::Term {
+                term,
+                negated: false,
+                ..
+            }) = tokens.last_mut()
+            && !term.phrase
+        {
+            term.prefix = true;
+        }
+
+        let mut required: Vec<Vec<FtsTerm>> = Vec::new();
+        let mut excluded = Vec::new();
+        let mut pending_or = None;
+        let mut last_negated = false;
+
+        for token in tokens {
+            match token {
+                Token::Or { position } => {
+                    if required.is_empty() || pending_or.is_some() {
+                        return Err(FtsQueryError::DanglingOr { position });
+                    }
+                    if last_negated {
+                        return Err(FtsQueryError::ExclusionInOr { position });
+                    }
+                    pending_or = Some(position);
+                }
+                Token::Term {
+                    term,
+                    negated: true,
+                    position,
+                } => {
+                    if pending_or.is_some() {
+                        return Err(FtsQueryError::ExclusionInOr { position });
+                    }
+                    excluded.push(term);
+                    last_negated = true;
+                }
+                Token::Term { term, .. } => {
+                    match (pending_or.take(), required.last_mut()) {
+                        (Some(_), Some(group)) => group.push(term),
+                        _ => required.push(vec![term]),
+                    }
+                    last_negated = false;
+                }
+            }
+        }
+
+        if let Some(position) = pending_or {
+            return Err(FtsQueryError::DanglingOr { position });
+        }
+        if required.is_empty() {
+            return Err(if excluded.is_empty() {
+                FtsQueryError::Empty
+            } else {
+                FtsQueryError::OnlyExclusions
+            });
+        }
+        Ok(FtsQuery { required, excluded })
+    }
+
+    fn tokenize(&self, raw: &str) -> Result<Vec<Token>, FtsQueryError> {
+        let mut tokens = Vec::new();
+        let mut rest = raw;
+
+        loop {
+            let trimmed = rest.trim_start();
+            if trimmed.is_empty() {
+                return Ok(tokens);
+            }
+            let position = raw.len() - trimmed.len();
+            let (token, tail) = self.next_token(raw, position)?;
+            if let Some(token) = token {
+                tokens.push(token);
+            }
+            rest = tail;
+        }
+    }
+
+    /// Parse the token starting at `position` and return it with the rest of
+    /// the input. Tokens without any letters or digits are dropped.
+    fn next_token<'r>(
+        &self,
+        raw: &'r str,
+        position: usize,
+    ) -> Result<(Option<Token>, &'r str), FtsQueryError> {
+        let mut input = &raw[position..];
+
+        let negated = input.len() > 1
+            && input.starts_with('-')
+            && !input[1..].starts_with(char::is_whitespace);
+        if negated {
+            input = &input[1..];
+        }
+
+        let bare_len = input
+            .find(|c: char| c.is_whitespace() || c == '"')
+            .unwrap_or(input.len());
+        let bare = &input[..bare_len];
+
+        if bare == "OR" && !negated {
+            return Ok((Some(Token::Or { position }), &input[bare_len..]));
+        }
+
+        let mut column = None;
+        let mut word = bare;
+        if let Some((name, value)) = bare.split_once(':')
+            && is_identifier(name)
+        {
+            let column_position = raw.len() - input.len();
+            if !self.columns.contains(&name) {
+                return Err(FtsQueryError::UnknownColumn {
+                    column: name.to_string(),
+                    position: column_position,
+                });
+            }
+            column = Some(name.to_string());
+            word = value;
+        }
+
+        let (text, phrase, prefix, tail) = if word.is_empty() && input[bare_len..].starts_with('"')
+        {
+            let quote = raw.len() - input.len() + bare_len;
+            let body = &input[bare_len + 1..];
+            let end = body
+                .find('"')
+                .ok_or(FtsQueryError::UnterminatedQuote { position: quote })?;
+            let after = &body[end + 1..];
+            let prefix = after.starts_with('*');
+            let after = after.trim_start_matches('*');
+            (&body[..end], true, prefix, after)
+        } else {
+            let text = word.trim_end_matches('*');
+            (text, false, text.len() < word.len(), &input[bare_len..])
+        };
+
+        if !text.chars().any(char::is_alphanumeric) {
+            return Ok((None, tail));
+        }
+        let term = FtsTerm {
+            column,
+            text: text.to_string(),
+            phrase,
+            prefix,
+        };
+        Ok((
+            Some(Token::Term {
+                term,
+                negated,
+                position,
+            }),
+            tail,
+        ))
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile(raw: &str) -> Result<String, FtsQueryError> {
+        FtsQueryParser::new()
+            .columns(&["name", "email"])
+            .parse(raw)
+            .map(|q| q.to_match())
+    }
+
+    #[test]
+    fn terms_are_quoted_and_last_one_prefixed() {
+        assert_eq!(compile("ivan petrov").unwrap(), r#""ivan" AND "petrov"*"#);
+        assert_eq!(
+            compile("john.doe@example.com").unwrap(),
+            r#""john.doe@example.com"*"#
+        );
+        assert_eq!(
+            compile("iva* AND NEAR(").unwrap(),
+            r#""iva"* AND "AND" AND "NEAR("*"#
+        );
+    }
+
+    #[test]
+    fn phrases_exclusions_and_or() {
+        assert_eq!(
+            compile(r#""ivan petrov" -blocked"#).unwrap(),
+            r#""ivan petrov" NOT "blocked""#
+        );
+        assert_eq!(
+            compile(r#"ivan OR petr smith -"on hold""#).unwrap(),
+            r#"(("ivan" OR "petr") AND "smith") NOT "on hold""#
+        );
+        assert_eq!(
+            FtsQueryParser::new()
+                .prefix_last(false)
+                .parse(r#"say "hi there"* "#)
+                .unwrap()
+                .to_match(),
+            r#""say" AND "hi there"*"#
+        );
+    }
+
+    #[test]
+    fn column_filters_use_the_whitelist() {
+        assert_eq!(
+            compile(r#"name:"ivan petrov" email:gmail"#).unwrap(),
+            r#"name:"ivan petrov" AND email:"gmail"*"#
+        );
+        assert_eq!(compile("10:30").unwrap(), r#""10:30"*"#);
+        assert_eq!(
+            compile("phone:123"),
+            Err(FtsQueryError::UnknownColumn {
+                column: "phone".into(),
+                position: 0
+            })
+        );
+    }
+
+    #[test]
+    fn structured_errors() {
+        assert_eq!(compile("  ... "), Err(FtsQueryError::Empty));
+        assert_eq!(compile("-spam"), Err(FtsQueryError::OnlyExclusions));
+        assert_eq!(
+            compile(r#"ivan "petr"#),
+            Err(FtsQueryError::UnterminatedQuote { position: 5 })
+        );
+        assert_eq!(
+            compile("OR ivan"),
+            Err(FtsQueryError::DanglingOr { position: 0 })
+        );
+        assert_eq!(
+            compile("ivan OR"),
+            Err(FtsQueryError::DanglingOr { position: 5 })
+        );
+        assert_eq!(
+            compile("ivan OR -petr"),
+            Err(FtsQueryError::ExclusionInOr { position: 8 })
+        );
+    }
+
+    #[test]
+    fn compiled_queries_are_valid_fts5() {
+        use diesel::connection::SimpleConnection;
+        use diesel::prelude::*;
+        use diesel::sql_types::Text;
+
+        use crate::testing::RowCount;
+
+        let mut conn = SqliteConnection::establish(":memory:").unwrap();
+        conn.batch_execute(
+            "CREATE VIRTUAL TABLE people USING fts5(name, email);
+             INSERT INTO people VALUES ('Ivan Petrov', 'ivan@example.com');
+             INSERT INTO people VALUES ('Petr Ivanov', 'petr@gmail.com');",
+        )
+        .unwrap();
+        let mut count = |raw: &str| {
+            diesel::sql_query("SELECT COUNT(*) AS count FROM people WHERE people MATCH ?")
+                .bind::<Text, _>(compile(raw).unwrap())
+                .get_result::<RowCount>(&mut conn)
+                .unwrap()
+                .count
+        };
+
+        assert_eq!(count(r#""ivan petrov""#), 1);
+        assert_eq!(count("ivan -gmail"), 1);
+        assert_eq!(count("name:ivan"), 2);
+        assert_eq!(count("email:gmail.com"), 1);
+        assert_eq!(count(r#"NOT OR "a ""#), 0);
+    }
+}
@@ -0,0 +1,337 @@
+//! Russian-aware normalization of search queries.
+//!
+//! Users type `ежик` for `ёжик`, search for another form of a word, or forget
+//! to switch the keyboard layout and type `bdfyjd` for `иванов`.
+//! [`RussianNormalizer`] folds query terms the way
+//! [`unicode::normalize`](crate::db::unicode::normalize) folds the index
+//! (case, `ё` into `е`, `ß` into `ss`, whitespace), cuts inflectional endings
+//! from prefix terms and offers a second query typed in the other layout:
+//!
+//! ```ignore
+//! let normalizer = RussianNormalizer::default();
+//! for expr in normalizer.match_queries(&parser, &search)? {
+//!     let hits = CUSTOMERS_FTS.search(expr).load(conn)?;
+//!     if !hits.is_empty() {
+//!         return Ok(hits);
+//!     }
+//! }
+//! ```
+//!
+//! Folding only helps when the index was folded the same way, see
+//! [`FtsIndex::normalized`](super::FtsIndex::normalized).
+
+use super::query::{FtsQuery, FtsQueryError, FtsQueryParser};
+use crate::db::unicode;
+
+/// Inflectional endings removed by [`stem`], longest first.
+const ENDINGS: &[&str] = &[
+    "иями", "ями", "ами", "ией", "иям", "ием", "иях", "ого", "его", "ому", "ему", "ыми", "ими",
+    "ая", "яя", "ое", "ее", "ые", "ие", "ой", "ей", "ий", "ый", "ую", "юю", "ою", "ею", "ом", "ем",
+    "ам", "ям", "ах", "ях", "ию", "ью", "ия", "ья", "ии", "а", "я", "о", "е", "ы", "и", "у", "ю",
+    "ь", "й",
+];
+
+/// Shortest stem [`stem`] leaves behind.
+const MIN_STEM_CHARS: usize = 4;
+
+/// Keys of the QWERTY layout and the ЙЦУКЕН letters on the same keys.
+const LAYOUT: &[(char, char)] = &[
+    ('q', 'й'),
+    ('w', 'ц'),
+    ('e', 'у'),
+    ('r', 'к'),
+    ('t', 'е'),
+    ('y', 'н'),
+    ('u', 'г'),
+    ('i', 'ш'),
+    ('o', 'щ'),
+    ('p', 'з'),
+    ('[', 'х'),
+    (']', 'ъ'),
+    ('a', 'ф'),
+    ('s', 'ы'),
+    ('d', 'в'),
+    ('f', 'а'),
+    ('g', 'п'),
+    ('h', 'р'),
+    ('j', 'о'),
+    ('k', 'л'),
+    ('l', 'д'),
+    (';', 'ж'),
+    ('\'', 'э'),
+    ('z', 'я'),
+    ('x', 'ч'),
+    ('c', 'с'),
+    ('v', 'м'),
+    ('b', 'и'),
+    ('n', 'т'),
+    ('m', 'ь'),
+    (',', 'б'),
+    ('.', 'ю'),
+    ('`', 'ё'),
+];
+
+/// Remove one inflectional ending from a Cyrillic word.
+///
+/// The stemmer is deliberately light: it is meant for prefix queries, so it
+/// only cuts case and number endings and never leaves fewer than four
+/// letters. Words containing anything but Cyrillic letters are returned
+/// unchanged.
+pub fn stem(word: &str) -> String {
+    let lower = word.to_lowercase();
+    if !lower.chars().all(is_cyrillic) {
+        return word.to_string();
+    }
+    let chars = lower.chars().count();
+    for ending in ENDINGS {
+        if lower.ends_with(ending) && chars - ending.chars().count() >= MIN_STEM_CHARS {
+            return lower[..lower.len() - ending.len()].to_string();
+        }
+    }
+    lower
+}
+
+/// `text` as if it had been typed in the other keyboard layout.
+///
+/// Latin text is converted to ЙЦУКЕН and Cyrillic text to QWERTY. Returns
+/// `None` when the text mixes both alphabets or has no letters to convert,
+/// and for Cyrillic text with letters such as `ж` or `ё` that sit on
+/// punctuation keys, since a correctly typed Russian word would otherwise
+/// turn into a search for its Latin fragments.
+/// `OR` operators and `column:` prefixes of the search syntax are kept.
+pub fn switch_layout(text: &str) -> Option<String> {
+    let latin = text.chars().filter(char::is_ascii_alphabetic).count();
+    let cyrillic = text.chars().filter(|c| is_cyrillic(*c)).count();
+    let to_cyrillic = match (latin, cyrillic) {
+        (0, 0) => return None,
+        (_, 0) => true,
+        (0, _) => false,
+        _ => return None,
+    };
+    if !to_cyrillic
+        && text
+            .chars()
+            .filter(|c| is_cyrillic(*c))
+            .any(|c| !to_latin_key(c).is_ascii_alphabetic())
+    {
+        return None;
+    }
+
+    let words = text.split(' ').map(|word| {
+        if word == "OR" {
+            return word.to_string();
+        }
+        let (column, rest) = match word.split_once(':') {
+            Some((column, rest)) if to_cyrillic && !column.is_empty() => (Some(column), rest),
+            _ => (None, word),
+        };
+        let switched: String = rest
+            .chars()
+            .map(|c| {
+                if to_cyrillic {
+                    to_cyrillic_key(c)
+                } else {
+                    to_latin_key(c)
+                }
+            })
+            .collect();
+        match column {
+            Some(column) => format!("{column}:{switched}"),
+            None => switched,
+        }
+    });
+    Some(words.collect::<Vec<_>>().join(" "))
+}
+
+fn to_cyrillic_key(c: char) -> char {
+    let lower = c.to_ascii_lowercase();
+    match LAYOUT.iter().find(|(key, _)| *key == lower) {
+        Some((_, letter)) if c.is_ascii_uppercase() => {
+            letter.to_uppercase().next().unwrap_or(*letter)
+        }
+        Some((_, letter)) => *letter,
+        None => c,
+    }
+}
+
+fn to_latin_key(c: char) -> char {
+    let lower = c.to_lowercase().next().unwrap_or(c);
+    match LAYOUT.iter().find(|(_, letter)| *letter == lower) {
+        Some((key, _)) if lower != c => key.to_ascii_uppercase(),
+        Some((key, _)) => *key,
+        None => c,
+    }
+}
+
+fn is_cyrillic(c: char) -> bool {
+    matches!(c, 'а'..='я' | 'А'..='Я' | 'ё' | 'Ё')
+}
+
+/// Applies Unicode folding, stemming and layout correction to search queries.
+#[derive(Clone, Debug)]
+pub struct RussianNormalizer {
+    /// Stem terms that are searched as prefixes.
+    pub stemming: bool,
+    /// Offer a second query typed in the other keyboard layout.
+    pub layout_correction: bool,
+}
+
+impl Default for RussianNormalizer {
+    fn default() -> Self {
+        Self {
+            stemming: true,
+            layout_correction: true,
+        }
+    }
+}
+
+impl RussianNormalizer {
+    /// Fold every term with [`unicode::normalize`] and stem single-word
+    /// prefix terms.
+    pub fn normalize(&self, mut query: FtsQuery) -> FtsQuery {
+        let terms = query
+            .required
+            .iter_mut()
+            .flatten()
+            .chain(query.excluded.iter_mut());
+        for term in terms {
+            term.text = unicode::normalize(&term.text);
+            if self.stemming && term.prefix && !term.phrase {
+                term.text = stem(&term.text);
+            }
+        }
+        query
+    }
+
+    /// `MATCH` expressions to try in order: the normalized query and, when
+    /// layout correction is enabled, the normalized query in the other
+    /// layout.
+    ///
+    /// Errors of the layout-switched query are ignored.
+    pub fn match_queries(
+        &self,
+        parser: &FtsQueryParser<'_>,
+        raw: &str,
+    ) -> Result<Vec<String>, FtsQueryError> {
+        let mut queries = vec![self.normalize(parser.parse(raw)?).to_match()];
+        if self.layout_correction
+            && let Some(switched) = switch_layout(raw)
+            && let Ok(query) = parser.parse(&switched)
+        {
+            let expr = self.normalize(query).to_match();
+            if !queries.contains(&expr) {
+                queries.push(expr);
+            }
+        }
+        Ok(queries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::fts::FtsIndex;
+    use diesel::connection::SimpleConnection;
+    use diesel::prelude::*;
+
+    #[test]
+    fn stems_only_long_cyrillic_words() {
+        assert_eq!(stem("Иванова"), "иванов");
+        assert_eq!(stem("компании"), "компан");
+        assert_eq!(stem("ежик"), "ежик");
+        assert_eq!(stem("новые"), "новы");
+        assert_eq!(stem("gmail"), "gmail");
+    }
+
+    #[test]
+    fn switches_keyboard_layout_both_ways() {
+        assert_eq!(switch_layout("bdfyjd").as_deref(), Some("иванов"));
+        assert_eq!(
+            switch_layout("Bdfyjd OR Gtnhjd").as_deref(),
+            Some("Иванов OR Петров")
+        );
+        assert_eq!(switch_layout("name:bdfy").as_deref(), Some("name:иван"));
+        assert_eq!(switch_layout("шмфт").as_deref(), Some("ivan"));
+        assert_eq!(switch_layout("ёжик"), None);
+        assert_eq!(switch_layout("ivan иван"), None);
+        assert_eq!(switch_layout("123"), None);
+    }
+
+    #[test]
+    fn match_queries_fold_stem_and_add_layout_variant() {
+        let parser = FtsQueryParser::new();
+        let normalizer = RussianNormalizer::default();
+
+        assert_eq!(
+            normalizer.match_queries(&parser, "ёжика").unwrap(),
+            [r#""ежик"*"#]
+        );
+        assert_eq!(
+            normalizer.match_queries(&parser, "шмфт").unwrap(),
+            [r#""шмфт"*"#, r#""ivan"*"#]
+        );
+        assert_eq!(
+            normalizer.match_queries(&parser, "bdfyjdf").unwrap(),
+            [r#""bdfyjdf"*"#, r#""иванов"*"#]
+        );
+        assert_eq!(
+            normalizer
+                .match_queries(&parser, r#""Ёжик в тумане""#)
+                .unwrap()[0],
+            r#""ежик в тумане""#
+        );
+    }
+
+    #[test]
+    fn normalize_applies_full_casefold() {
+        let parser = FtsQueryParser::new();
+        let normalizer = RussianNormalizer::default();
+
+        assert_eq!(
+            normalizer.match_queries(&parser, "Straße").unwrap()[0],
+            r#""strasse"*"#
+        );
+        assert_eq!(
+            normalizer.match_queries(&parser, "ΟΔΟΣ").unwrap()[0],
+            r#""οδοσ"*"#
+        );
+        assert_eq!(
+            normalizer
+                .match_queries(&parser, r#""Ёлка   в лесу""#)
+                .unwrap()[0],
+            r#""елка в лесу""#
+        );
+    }
+
+    #[test]
+    fn normalized_index_matches_folded_queries() {
+        let mut conn = SqliteConnection::establish(":memory:").unwrap();
+        unicode::register(&mut conn).unwrap();
+        conn.batch_execute(
+            "CREATE TABLE books (id INTEGER PRIMARY KEY, title TEXT NOT NULL);
+             INSERT INTO books (title) VALUES ('Ёжик в тумане');",
+        )
+        .unwrap();
+        let index = FtsIndex::new("books", &["title"]).normalized(true);
+        index.create(&mut conn).unwrap();
+        index.rebuild(&mut conn).unwrap();
+        conn.batch_execute("INSERT INTO books (title) VALUES ('Ёлка')")
+            .unwrap();
+
+        let parser = FtsQueryParser::new();
+        let normalizer = RussianNormalizer::default();
+        let find = |conn: &mut SqliteConnection, raw: &str| {
+            normalizer
+                .match_queries(&parser, raw)
+                .unwrap()
+                .into_iter()
+                .map(|expr| index.search(expr).load(conn).unwrap())
+                .find(|hits| !hits.is_empty())
+                .map(|hits| hits[0].rowid)
+        };
+
+        assert_eq!(find(&mut conn, "ежика"), Some(1));
+        assert_eq!(find(&mut conn, "tkrf"), Some(2));
+        assert_eq!(find(&mut conn, "ЁЖИК"), Some(1));
+    }
+}
@@ -0,0 +1,53 @@
+//! Repository traits for records owned by a hub.
+//!
+//! Every call takes the hub of the current user, so a record of another hub
+//! behaves exactly like a missing one and reports
+//! [`RepositoryError::NotFound`](super::errors::RepositoryError::NotFound).
+//! Services depend on the traits instead of a Diesel implementation:
+//!
+//! ```ignore
+//! pub fn rename_customer<R>(repo: &R, user: &AuthenticatedUser, id: i32, name: &str) -> ServiceResult<Customer>
+//! where
+//!     R: HubWriter<Customer, Update = CustomerUpdate>,
+//! {
+//!     Ok(repo.update(user.hub_id, id, &CustomerUpdate { name: name.to_string() })?)
+//! }
+//! ```
+//!
+//! and are tested against `memory::InMemoryRepository` from the `testing`
+//! feature.
+
+use crate::pagination::{Paginated, Pagination};
+use crate::repository::errors::RepositoryResult;
+
+/// A record that belongs to a hub.
+pub trait HubRecord {
+    fn id(&self) -> i32;
+    fn hub_id(&self) -> i32;
+}
+
+/// Read access to the records of a hub.
+pub trait HubReader<T: HubRecord> {
+    /// The record `id` of `hub_id`.
+    fn get_by_id(&self, hub_id: i32, id: i32) -> RepositoryResult<T>;
+
+    /// One page of the records of `hub_id`.
+    fn list(&self, hub_id: i32, pagination: &Pagination) -> RepositoryResult<Paginated<T>>;
+}
+
+/// Write access to the records of a hub.
+pub trait HubWriter<T: HubRecord> {
+    /// Data needed to create a record.
+    type New;
+    /// Changes applied by [`update`](Self::update).
+    type Update;
+
+    /// Create a record in `hub_id` and return it.
+    fn create(&self, hub_id: i32, new: &Self::New) -> RepositoryResult<T>;
+
+    /// Apply `update` to the record `id` of `hub_id` and return the result.
+    fn update(&self, hub_id: i32, id: i32, update: &Self::Update) -> RepositoryResult<T>;
+
+    /// Delete the record `id` of `hub_id`.
+    fn delete(&self, hub_id: i32, id: i32) -> RepositoryResult<()>;
+}
@@ -0,0 +1,373 @@
+//! In-memory implementation of the hub repository traits.
+//!
+//! Enabled by the `testing` feature. [`InMemoryRepository`] keeps records in
+//! a mutex-guarded map and mimics the errors of the SQLite repositories:
+//! missing records and records of other hubs are
+//! [`RepositoryError::NotFound`], and duplicates of a registered unique key
+//! are [`RepositoryError::ConstraintViolation`] with the columns a
+//! `UNIQUE (hub_id, ...)` constraint would report:
+//!
+//! ```ignore
+//! let repo = InMemoryRepository::new(
+//!     "customers",
+//!     |id, hub_id, new: &NewCustomer| Customer { id, hub_id, email: new.email.clone() },
+//!     |customer, update: &CustomerUpdate| customer.email = update.email.clone(),
+//! )
+//! .unique(&["email"], |customer| Some(customer.email.clone()));
+//!
+//! let err = register_customer(&repo, &user, "a@example.com").unwrap_err();
+//! ```
+
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::pagination::{Paginated, Pagination};
+use crate::repository::errors::{
+    ConstraintKind, ConstraintViolation, RepositoryError, RepositoryResult,
+};
+use crate::repository::hub::{HubReader, HubRecord, HubWriter};
+
+type CreateFn<T, N> = Box<dyn Fn(i32, i32, &N) -> T + Send + Sync>;
+type UpdateFn<T, U> = Box<dyn Fn(&mut T, &U) + Send + Sync>;
+type KeyFn<T> = Box<dyn Fn(&T) -> Option<String> + Send + Sync>;
+
+struct UniqueKey<T> {
+    columns: Vec<String>,
+    key: KeyFn<T>,
+}
+
+struct State<T> {
+    next_id: i32,
+    records: BTreeMap<i32, T>,
+}
+
+/// Thread-safe fake of a hub repository for service tests.
+pub struct InMemoryRepository<T, N, U> {
+    table: String,
+    create: CreateFn<T, N>,
+    update: UpdateFn<T, U>,
+    unique: Vec<UniqueKey<T>>,
+    state: Mutex<State<T>>,
+}
+
+impl<T, N, U> InMemoryRepository<T, N, U>
+where
+    T: HubRecord + Clone,
+{
+    /// Repository of `table` building records with `create(id, hub_id, new)`
+    /// and changing them with `update(record, update)`.
+    pub fn new(
+        table: &str,
+        create: impl Fn(i32, i32, &N) -> T + Send + Sync + 'static,
+        update: impl Fn(&mut T, &U) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            table: table.to_string(),
+            create: Box::new(create),
+            update: Box::new(update),
+            unique: Vec::new(),
+            state: Mutex::new(State {
+                next_id: 1,
+                records: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// Reject two records of a hub with the same `key`, like a
+    /// `UNIQUE (hub_id, columns...)` constraint. Records whose key is `None`
+    /// never conflict, as with SQL `NULL`.
+    pub fn unique(
+        mut self,
+        columns: &[&str],
+        key: impl Fn(&T) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.unique.push(UniqueKey {
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+            key: Box::new(key),
+        });
+        self
+    }
+
+    /// Store `record` as is, e.g. to seed a test. Later ids continue after
+    /// the largest one stored.
+    pub fn insert(&self, record: T) {
+        let mut state = self.lock();
+        state.next_id = state.next_id.max(record.id() + 1);
+        state.records.insert(record.id(), record);
+    }
+
+    /// All stored records ordered by id.
+    pub fn records(&self) -> Vec<T> {
+        self.lock().records.values().cloned().collect()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        // A test that panicked while holding the lock leaves consistent data.
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn check_unique(&self, state: &State<T>, record: &T) -> RepositoryResult<()> {
+        for unique in &self.unique {
+            let Some(key) = (unique.key)(record) else {
+                continue;
+            };
+            let duplicate = state.records.values().any(|other| {
+                other.id() != record.id()
+                    && other.hub_id() == record.hub_id()
+                    && (unique.key)(other).as_ref() == Some(&key)
+            });
+            if duplicate {
+                return Err(self.unique_violation(&unique.columns));
+            }
+        }
+        Ok(())
+    }
+
+    fn unique_violation(&self, columns: &[String]) -> RepositoryError {
+        let columns = std::iter::once("hub_id".to_string())
+            .chain(columns.iter().cloned())
+            .collect::<Vec<_>>();
+        let message = format!(
+            "UNIQUE constraint failed: {}",
+            columns
+                .iter()
+                .map(|c| format!("{}.{c}", self.table))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        RepositoryError::ConstraintViolation(ConstraintViolation::parse(
+            ConstraintKind::Unique,
+            &message,
+        ))
+    }
+}
+
+fn owned_by<T: HubRecord>(record: Option<&T>, hub_id: i32) -> RepositoryResult<&T> {
+    record
+        .filter(|record| record.hub_id() == hub_id)
+        .ok_or(RepositoryError::NotFound)
+}
+
+impl<T, N, U> HubReader<T> for InMemoryRepository<T, N, U>
+where
+    T: HubRecord + Clone,
+{
+    fn get_by_id(&self, hub_id: i32, id: i32) -> RepositoryResult<T> {
+        owned_by(self.lock().records.get(&id), hub_id).cloned()
+    }
+
+    fn list(&self, hub_id: i32, pagination: &Pagination) -> RepositoryResult<Paginated<T>> {
+        let state = self.lock();
+        let records = state
+            .records
+            .values()
+            .filter(|record| record.hub_id() == hub_id)
+            .collect::<Vec<_>>();
+        let per_page = pagination.per_page.max(1);
+        let items = records
+            .iter()
+            .skip((pagination.page.max(1) - 1).saturating_mul(per_page))
+            .take(per_page)
+            .map(|record| (*record).clone())
+            .collect();
+        Ok(Paginated::from_total(items, pagination, records.len()))
+    }
+}
+
+impl<T, N, U> HubWriter<T> for InMemoryRepository<T, N, U>
+where
+    T: HubRecord + Clone,
+{
+    type New = N;
+    type Update = U;
+
+    fn create(&self, hub_id: i32, new: &N) -> RepositoryResult<T> {
+        let mut state = self.lock();
+        let record = (self.create)(state.next_id, hub_id, new);
+        self.check_unique(&state, &record)?;
+        state.next_id += 1;
+        state.records.insert(record.id(), record.clone());
+        Ok(record)
+    }
+
+    fn update(&self, hub_id: i32, id: i32, update: &U) -> RepositoryResult<T> {
+        let mut state = self.lock();
+        let mut record = owned_by(state.records.get(&id), hub_id)?.clone();
+        (self.update)(&mut record, update);
+        self.check_unique(&state, &record)?;
+        state.records.insert(id, record.clone());
+        Ok(record)
+    }
+
+    fn delete(&self, hub_id: i32, id: i32) -> RepositoryResult<()> {
+        let mut state = self.lock();
+        owned_by(state.records.get(&id), hub_id)?;
+        state.records.remove(&id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Customer {
+        id: i32,
+        hub_id: i32,
+        email: String,
+        phone: Option<String>,
+    }
+
+    impl HubRecord for Customer {
+        fn id(&self) -> i32 {
+            self.id
+        }
+
+        fn hub_id(&self) -> i32 {
+            self.hub_id
+        }
+    }
+
+    struct NewCustomer {
+        email: String,
+        phone: Option<String>,
+    }
+
+    type Repo = InMemoryRepository<Customer, NewCustomer, String>;
+
+    fn repo() -> Repo {
+        InMemoryRepository::new(
+            "customers",
+            |id, hub_id, new: &NewCustomer| Customer {
+                id,
+                hub_id,
+                email: new.email.clone(),
+                phone: new.phone.clone(),
+            },
+            |customer, email: &String| customer.email = email.clone(),
+        )
+        .unique(&["email"], |c| Some(c.email.clone()))
+        .unique(&["phone"], |c| c.phone.clone())
+    }
+
+    fn new_customer(email: &str) -> NewCustomer {
+        NewCustomer {
+            email: email.to_string(),
+            phone: None,
+        }
+    }
+
+    /// A service written against the traits only.
+    fn register<R>(repo: &R, hub_id: i32, email: &str) -> RepositoryResult<Customer>
+    where
+        R: HubReader<Customer> + HubWriter<Customer, New = NewCustomer>,
+    {
+        let customer = repo.create(hub_id, &new_customer(email))?;
+        repo.get_by_id(hub_id, customer.id)
+    }
+
+    #[test]
+    fn records_are_scoped_to_their_hub() {
+        let repo = repo();
+        let customer = register(&repo, 1, "a@example.com").unwrap();
+        assert_eq!(customer.id, 1);
+
+        assert!(matches!(
+            repo.get_by_id(2, customer.id),
+            Err(RepositoryError::NotFound)
+        ));
+        assert!(matches!(
+            repo.update(2, customer.id, &"b@example.com".to_string()),
+            Err(RepositoryError::NotFound)
+        ));
+        assert!(matches!(
+            repo.delete(2, customer.id),
+            Err(RepositoryError::NotFound)
+        ));
+
+        repo.delete(1, customer.id).unwrap();
+        assert!(matches!(
+            repo.get_by_id(1, customer.id),
+            Err(RepositoryError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn duplicates_within_a_hub_are_unique_violations() {
+        let repo = repo();
+        register(&repo, 1, "a@example.com").unwrap();
+        register(&repo, 2, "a@example.com").unwrap();
+        let other = register(&repo, 1, "b@example.com").unwrap();
+
+        let err = register(&repo, 1, "a@example.com").unwrap_err();
+        assert_eq!(err.code(), "repository.unique_violation");
+        let RepositoryError::ConstraintViolation(violation) = &err else {
+            panic!("expected a constraint violation, got {err:?}");
+        };
+        assert_eq!(violation.table.as_deref(), Some("customers"));
+        assert_eq!(violation.columns, ["hub_id", "email"]);
+
+        assert!(
+            repo.update(1, other.id, &"a@example.com".to_string())
+                .is_err()
+        );
+        assert_eq!(
+            repo.update(1, other.id, &"c@example.com".to_string())
+                .unwrap()
+                .email,
+            "c@example.com"
+        );
+        assert_eq!(repo.records().len(), 3);
+    }
+
+    #[test]
+    fn list_paginates_hub_records() {
+        let repo = repo();
+        repo.insert(Customer {
+            id: 10,
+            hub_id: 1,
+            email: "seed@example.com".into(),
+            phone: None,
+        });
+        for i in 0..4 {
+            register(&repo, 1, &format!("{i}@example.com")).unwrap();
+        }
+        register(&repo, 2, "other@example.com").unwrap();
+
+        let page = repo
+            .list(
+                1,
+                &Pagination {
+                    page: 2,
+                    per_page: 2,
+                },
+            )
+            .unwrap();
+        assert_eq!(page.total_items, Some(5));
+        assert_eq!(page.total_pages, 3);
+        assert_eq!(
+            page.items.iter().map(|c| c.id).collect::<Vec<_>>(),
+            [12, 13]
+        );
+    }
+
+    #[test]
+    fn concurrent_creates_get_distinct_ids() {
+        let repo = Arc::new(repo());
+        let handles = (0..8)
+            .map(|i| {
+                let repo = Arc::clone(&repo);
+                std::thread::spawn(move || register(&*repo, 1, &format!("{i}@example.com")))
+            })
+            .collect::<Vec<_>>();
+        let mut ids = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap().unwrap().id)
+            .collect::<Vec<_>>();
+        ids.sort();
+        assert_eq!(ids, (1..=8).collect::<Vec<_>>());
+    }
+}
@@ -1,14 +1,26 @@
 //! Helpers for implementing data repositories.
 //!
 //! This module collects error types and other utilities used by repository
-//! implementations throughout the project.
+//! implementations throughout the project. Full-text search helpers live in
+//! [`fts`], and [`constraints`] maps constraint violations to form field
+//! errors. [`hub`] defines generic hub-scoped reader and writer traits; the
+//! `testing` feature adds an in-memory implementation in `memory` for
+//! service tests.
 
+pub mod constraints;
 pub mod errors;
+pub mod fts;
+pub mod hub;
+#[cfg(any(test, feature = "testing"))]
+pub mod memory;
 
 /// Prepares user input for SQLite FTS5 MATCH by:
 /// - replacing non-alphanumeric chars with spaces
 /// - collapsing multiple spaces
 /// - appending `*` for prefix search if not already present
+///
+/// Use [`fts::FtsQueryParser`] to support phrases, exclusions, `OR` and
+/// column filters.
 pub fn build_fts_match_query(raw: &str) -> Option<String> {
     let mut result = String::with_capacity(raw.len());
 
@@ -1,24 +1,36 @@
 use thiserror::Error;
 
+use crate::dto::mutation::ApiFieldErrorDto;
+
 /// Generic error type used by service layer functions.
+///
+/// Wrapped errors stay reachable through [`std::error::Error::source`], and
+/// [`ServiceError::code`] gives clients a stable identifier to switch on.
 #[derive(Debug, Error)]
 pub enum ServiceError {
-    /// The user is not authorized to perform the operation.
+    /// The user is not authenticated.
     #[error("unauthorized")]
     Unauthorized,
 
+    /// The user is authenticated but may not perform the operation.
+    #[error("forbidden")]
+    Forbidden,
+
     /// Requested resource was not found.
     #[error("not found")]
     NotFound,
 
-    /// The resource already exists.
+    /// The resource already exists. Conflicts reported by a database
+    /// constraint keep the
+    /// [`RepositoryError::ConstraintViolation`](crate::repository::errors::RepositoryError::ConstraintViolation)
+    /// as their source, with the table, columns and name of the constraint.
     #[error("conflict")]
-    Conflict,
+    Conflict(#[source] Option<Box<dyn std::error::Error + Send + Sync + 'static>>),
 
     /// Persistence layer failures.
     #[cfg(feature = "db")]
     #[error("repository error: {0}")]
-    Repository(crate::repository::errors::RepositoryError),
+    Repository(#[source] crate::repository::errors::RepositoryError),
 
     /// ZmqSenderError
     #[cfg(feature = "zeromq")]
@@ -29,6 +41,13 @@ pub enum ServiceError {
     #[error("form error: {0}")]
     Form(String),
 
+    /// Input rejected with messages attached to individual fields.
+    #[error("validation error: {message}")]
+    Validation {
+        message: String,
+        field_errors: Vec<ApiFieldErrorDto>,
+    },
+
     /// Problems with environment or configuration.
     #[error("configuration error: {0}")]
     Config(String),
@@ -45,16 +64,105 @@ pub enum ServiceError {
 /// Convenient alias for results returned from service functions.
 pub type ServiceResult<T> = Result<T, ServiceError>;
 
+impl ServiceError {
+    /// Stable identifier of the error kind, such as `service.conflict`.
+    ///
+    /// Repository errors keep their own code, e.g. `repository.busy`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ServiceError::Unauthorized => "service.unauthorized",
+            ServiceError::Forbidden => "service.forbidden",
+            ServiceError::NotFound => "service.not_found",
+            ServiceError::Conflict(_) => "service.conflict",
+            #[cfg(feature = "db")]
+            ServiceError::Repository(err) => err.code(),
+            #[cfg(feature = "zeromq")]
+            ServiceError::ZmqSender(_) => "service.zmq_sender",
+            ServiceError::Form(_) => "service.form",
+            ServiceError::Validation { .. } => "service.validation",
+            ServiceError::Config(_) => "service.config",
+            ServiceError::Internal => "service.internal",
+            ServiceError::TypeConstraint(_) => "service.type_constraint",
+        }
+    }
+}
+
 // Manual From implementation for RepositoryError
 #[cfg(feature = "db")]
 impl From<crate::repository::errors::RepositoryError> for ServiceError {
     fn from(err: crate::repository::errors::RepositoryError) -> Self {
         match err {
             crate::repository::errors::RepositoryError::NotFound => ServiceError::NotFound,
-            crate::repository::errors::RepositoryError::ConstraintViolation(_) => {
-                ServiceError::Conflict
+            err @ crate::repository::errors::RepositoryError::ConstraintViolation(..) => {
+                ServiceError::Conflict(Some(err.into()))
             }
             other => ServiceError::Repository(other),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_are_stable() {
+        assert_eq!(ServiceError::Forbidden.code(), "service.forbidden");
+        assert_eq!(ServiceError::Conflict(None).code(), "service.conflict");
+        let validation = ServiceError::Validation {
+            message: "Ошибка валидации формы.".into(),
+            field_errors: vec![ApiFieldErrorDto {
+                field: "email".into(),
+                message: "Некорректный email.".into(),
+            }],
+        };
+        assert_eq!(validation.code(), "service.validation");
+        assert_eq!(
+            validation.to_string(),
+            "validation error: Ошибка валидации формы."
+        );
+    }
+
+    #[cfg(feature = "db")]
+    #[test]
+    fn repository_errors_keep_their_source() {
+        use std::error::Error;
+
+        let err = ServiceError::from(crate::repository::errors::RepositoryError::from(
+            diesel::result::Error::DatabaseError(
+                diesel::result::DatabaseErrorKind::Unknown,
+                Box::new("disk I/O error".to_string()),
+            ),
+        ));
+        assert_eq!(err.code(), "repository.database");
+        let repository = err.source().unwrap();
+        assert_eq!(repository.source().unwrap().to_string(), "disk I/O error");
+    }
+
+    #[cfg(feature = "db")]
+    #[test]
+    fn constraint_violations_are_conflicts() {
+        use std::error::Error;
+
+        use crate::repository::errors::{ConstraintKind, ConstraintViolation, RepositoryError};
+
+        let err = ServiceError::from(RepositoryError::ConstraintViolation(
+            ConstraintViolation::parse(
+                ConstraintKind::Unique,
+                "UNIQUE constraint failed: customers.hub_id, customers.email",
+            ),
+        ));
+        assert!(matches!(err, ServiceError::Conflict(Some(_))));
+        assert_eq!(err.code(), "service.conflict");
+        let source = err
+            .source()
+            .unwrap()
+            .downcast_ref::<RepositoryError>()
+            .unwrap();
+        let RepositoryError::ConstraintViolation(violation) = source else {
+            panic!("unexpected source: {source:?}");
+        };
+        assert_eq!(violation.table.as_deref(), Some("customers"));
+        assert_eq!(violation.columns, ["hub_id", "email"]);
+    }
+}
@@ -0,0 +1,287 @@
+//! Database fixtures for repository tests.
+//!
+//! Enabled by the `testing` feature, which services add to their
+//! `dev-dependencies`:
+//!
+//! ```ignore
+//! use pushkind_common::testing::TestDb;
+//!
+//! const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+//!
+//! #[test]
+//! fn creates_customer() {
+//!     let db = TestDb::builder()
+//!         .migrations(&MIGRATIONS)
+//!         .seed(|conn| insert_hub(conn, 1))
+//!         .build();
+//!
+//!     db.test_transaction(|conn| {
+//!         let repo = DieselCustomerRepository::new(conn);
+//!         assert!(repo.create(&new_customer()).is_ok());
+//!         assert_eq!(count_rows(conn, "customers"), 1);
+//!     });
+//! }
+//! ```
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use diesel::connection::Connection;
+use diesel::migration::MigrationSource;
+use diesel::result::Error as DieselError;
+use diesel::sql_types::BigInt;
+use diesel::sqlite::{Sqlite, SqliteConnection};
+use diesel::{QueryableByName, RunQueryDsl};
+use tempfile::NamedTempFile;
+
+use crate::db::migrations::run_pending_migrations_on;
+use crate::db::{DbConnection, DbPool, DbPoolBuilder, WriteTx};
+use crate::repository::errors::{RepositoryError, RepositoryResult};
+
+type SetupStep = Box<dyn FnOnce(&mut SqliteConnection) -> RepositoryResult<()>>;
+
+static IN_MEMORY_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Temporary database used in tests.
+///
+/// The database is deleted when the value is dropped.
+pub struct TestDb {
+    pool: DbPool,
+    _tempfile: Option<NamedTempFile>,
+}
+
+impl TestDb {
+    /// Empty database in a temporary file.
+    pub fn new() -> Self {
+        Self::builder().build()
+    }
+
+    /// Empty shared-cache in-memory database.
+    pub fn in_memory() -> Self {
+        Self::builder().in_memory().build()
+    }
+
+    /// Configure migrations, seeding and storage before creating the database.
+    pub fn builder() -> TestDbBuilder {
+        TestDbBuilder::default()
+    }
+
+    /// Pool connected to the test database.
+    pub fn pool(&self) -> DbPool {
+        self.pool.clone()
+    }
+
+    /// Check out a connection from the pool.
+    pub fn conn(&self) -> DbConnection {
+        self.pool
+            .get()
+            .expect("Failed to get connection from test pool")
+    }
+
+    /// Run `f` inside a transaction that is always rolled back, so every test
+    /// sees the database exactly as it was seeded.
+    ///
+    /// `f` receives a [`WriteTx`], so write helpers can be called with it.
+    pub fn test_transaction<T, F>(&self, f: F) -> T
+    where
+        F: FnOnce(&mut WriteTx<'_>) -> T,
+    {
+        let mut conn = self.conn();
+        let mut output = None;
+        let result = conn.transaction::<(), DieselError, _>(|conn| {
+            output = Some(f(&mut WriteTx::new(conn)));
+            Err(DieselError::RollbackTransaction)
+        });
+        match result {
+            Err(DieselError::RollbackTransaction) => {}
+            other => panic!("Failed to roll back test transaction: {other:?}"),
+        }
+        output.expect("test transaction closure did not run")
+    }
+}
+
+impl Default for TestDb {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Builder for [`TestDb`].
+///
+/// Setup steps run once, in the order they were added, on a single
+/// connection before the database is handed to the test.
+pub struct TestDbBuilder {
+    pool_builder: DbPoolBuilder,
+    in_memory: bool,
+    steps: Vec<SetupStep>,
+}
+
+impl Default for TestDbBuilder {
+    fn default() -> Self {
+        Self {
+            pool_builder: DbPoolBuilder::new().max_size(4),
+            in_memory: false,
+            steps: Vec::new(),
+        }
+    }
+}
+
+impl TestDbBuilder {
+    /// Use a shared-cache in-memory database instead of a temporary file.
+    ///
+    /// Every pooled connection sees the same data; the database disappears
+    /// together with the [`TestDb`].
+    pub fn in_memory(mut self) -> Self {
+        self.in_memory = true;
+        self
+    }
+
+    /// Pool settings for the test database.
+    pub fn pool_builder(mut self, pool_builder: DbPoolBuilder) -> Self {
+        self.pool_builder = pool_builder;
+        self
+    }
+
+    /// Apply all migrations of `source`.
+    pub fn migrations<S>(mut self, source: &'static S) -> Self
+    where
+        S: MigrationSource<Sqlite> + Sync,
+    {
+        self.steps.push(Box::new(move |conn| {
+            run_pending_migrations_on(conn, source)
+                .map(|_| ())
+                .map_err(|e| RepositoryError::Unexpected(e.to_string()))
+        }));
+        self
+    }
+
+    /// Insert fixture data after the migrations added so far.
+    pub fn seed<F>(mut self, seed: F) -> Self
+    where
+        F: FnOnce(&mut SqliteConnection) -> RepositoryResult<()> + 'static,
+    {
+        self.steps.push(Box::new(seed));
+        self
+    }
+
+    /// Create the database and run the setup steps.
+    ///
+    /// # Panics
+    ///
+    /// Panics when the database cannot be created or a setup step fails.
+    pub fn build(self) -> TestDb {
+        let (url, tempfile, pool_builder) = if self.in_memory {
+            let id = IN_MEMORY_COUNTER.fetch_add(1, Ordering::Relaxed);
+            let url = format!(
+                "file:pushkind-test-{}-{id}?mode=memory&cache=shared",
+                std::process::id()
+            );
+            // Keep one connection open for the lifetime of the pool, otherwise
+            // SQLite drops the in-memory database.
+            let pool_builder = self
+                .pool_builder
+                .min_idle(Some(1))
+                .idle_timeout(None)
+                .enable_wal(false);
+            (url, None, pool_builder)
+        } else {
+            let tempfile = NamedTempFile::new().expect("Failed to create temp file");
+            let url = tempfile
+                .path()
+                .to_str()
+                .expect("Temp file path is not valid UTF-8")
+                .to_string();
+            (url, Some(tempfile), self.pool_builder)
+        };
+
+        let pool = pool_builder
+            .build(&url)
+            .expect("Failed to establish SQLite connection.");
+
+        let mut conn = pool.get().expect("Failed to get connection from test pool");
+        for step in self.steps {
+            step(&mut conn).expect("Test database setup failed");
+        }
+        drop(conn);
+
+        TestDb {
+            pool,
+            _tempfile: tempfile,
+        }
+    }
+}
+
+/// Result row of a `SELECT COUNT(*) AS count ...` raw query.
+#[derive(Debug, QueryableByName)]
+pub struct RowCount {
+    #[diesel(sql_type = BigInt)]
+    pub count: i64,
+}
+
+/// Number of rows in `table`.
+///
+/// # Panics
+///
+/// Panics when the query fails, e.g. because the table does not exist.
+pub fn count_rows(conn: &mut SqliteConnection, table: &str) -> i64 {
+    diesel::sql_query(format!("SELECT COUNT(*) AS count FROM {table}"))
+        .get_result::<RowCount>(conn)
+        .expect("Failed to count rows")
+        .count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::connection::SimpleConnection;
+
+    fn count(conn: &mut SqliteConnection) -> i64 {
+        count_rows(conn, "items")
+    }
+
+    fn seeded(builder: TestDbBuilder) -> TestDb {
+        builder
+            .seed(|conn| {
+                conn.batch_execute(
+                    "CREATE TABLE items (id INTEGER PRIMARY KEY); INSERT INTO items DEFAULT VALUES;",
+                )?;
+                Ok(())
+            })
+            .build()
+    }
+
+    #[test]
+    fn test_transaction_rolls_back() {
+        let db = seeded(TestDb::builder());
+
+        let inside = db.test_transaction(|conn| {
+            conn.batch_execute("INSERT INTO items DEFAULT VALUES")
+                .unwrap();
+            count(conn)
+        });
+
+        assert_eq!(inside, 2);
+        assert_eq!(count(&mut db.conn()), 1);
+    }
+
+    #[test]
+    fn in_memory_database_is_shared_between_connections() {
+        let db = seeded(TestDb::builder().in_memory());
+
+        let mut first = db.conn();
+        let mut second = db.conn();
+        first
+            .batch_execute("INSERT INTO items DEFAULT VALUES")
+            .unwrap();
+
+        assert_eq!(count(&mut second), 2);
+    }
+
+    #[test]
+    fn in_memory_databases_are_isolated() {
+        let first = seeded(TestDb::builder().in_memory());
+        let second = TestDb::in_memory();
+
+        assert_eq!(count(&mut first.conn()), 1);
+        assert!(second.conn().batch_execute("SELECT * FROM items").is_err());
+    }
+}
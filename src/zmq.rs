@@ -1,6 +1,6 @@
 use serde::Serialize;
 use std::{future::Future, pin::Pin, thread, time::Duration};
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
 use zmq;
 
 /// How many messages to buffer before applying backpressure.
@@ -50,10 +50,13 @@ impl ZmqSenderOptions {
     }
 }
 
-/// Payload variants the thread can send.
+/// Reply from the socket thread once it tried to send an envelope.
+type Ack = oneshot::Sender<Result<(), zmq::Error>>;
+
+/// Payload variants the thread can send, with an optional ack channel.
 enum Envelope {
-    Bytes(Vec<u8>),
-    Multipart(Vec<Vec<u8>>),
+    Bytes(Vec<u8>, Option<Ack>),
+    Multipart(Vec<Vec<u8>>, Option<Ack>),
 }
 
 /// Future returned by the [`ZmqSenderTrait`] trait methods.
@@ -69,6 +72,23 @@ pub trait ZmqSenderTrait: Send + Sync {
 
     /// Send multipart frames (awaits if the queue is full).
     fn send_multipart<'a>(&'a self, frames: Vec<Vec<u8>>) -> SendFuture<'a>;
+
+    /// Send raw bytes and wait until the socket itself accepted them.
+    ///
+    /// [`send_bytes`](Self::send_bytes) returns as soon as the message is
+    /// queued for the socket thread, so later socket failures are only
+    /// logged. Use this variant when the caller must know the outcome. The
+    /// default implementation forwards to `send_bytes`, which is correct for
+    /// senders that do not queue.
+    fn send_bytes_acked<'a>(&'a self, bytes: Vec<u8>) -> SendFuture<'a> {
+        self.send_bytes(bytes)
+    }
+
+    /// Send multipart frames and wait until the socket itself accepted them,
+    /// see [`send_bytes_acked`](Self::send_bytes_acked).
+    fn send_multipart_acked<'a>(&'a self, frames: Vec<Vec<u8>>) -> SendFuture<'a> {
+        self.send_multipart(frames)
+    }
 }
 
 /// Convenience helpers automatically available for all [`ZmqSenderTrait`] implementors.
@@ -116,12 +136,14 @@ pub enum ZmqSenderError {
     #[error("sender thread is closed")]
     ChannelClosed,
     #[error("create ZMQ socket: {0}")]
-    SocketCreate(zmq::Error),
+    SocketCreate(#[source] zmq::Error),
     #[error("connect {endpoint} failed: {source}")]
     Connect {
         endpoint: String,
         source: zmq::Error,
     },
+    #[error("ZMQ send failed: {0}")]
+    Send(#[source] zmq::Error),
 }
 
 impl ZmqSender {
@@ -156,10 +178,14 @@ impl ZmqSender {
             }
 
             while let Some(env) = rx.blocking_recv() {
-                let res = match env {
-                    Envelope::Bytes(b) => sock.send(b, 0),
-                    Envelope::Multipart(frames) => sock.send_multipart(frames, 0),
+                let (res, ack) = match env {
+                    Envelope::Bytes(b, ack) => (sock.send(b, 0), ack),
+                    Envelope::Multipart(frames, ack) => (sock.send_multipart(frames, 0), ack),
                 };
+                if let Some(ack) = ack {
+                    // The caller may have given up waiting; nothing to do then.
+                    let _ = ack.send(res);
+                }
                 if let Err(e) = res {
                     // You can swap for `log::error!` if you prefer structured logging here.
                     log::error!("[ZmqSender {:?}] send error: {e}", kind);
@@ -172,21 +198,35 @@ impl ZmqSender {
 
         Ok(Self { tx })
     }
+
+    async fn enqueue(&self, envelope: Envelope) -> Result<(), ZmqSenderError> {
+        self.tx
+            .send(envelope)
+            .await
+            .map_err(|_| ZmqSenderError::ChannelClosed)
+    }
+
+    async fn enqueue_acked(
+        &self,
+        envelope: impl FnOnce(Ack) -> Envelope,
+    ) -> Result<(), ZmqSenderError> {
+        let (ack, reply) = oneshot::channel();
+        self.enqueue(envelope(ack)).await?;
+        reply
+            .await
+            .map_err(|_| ZmqSenderError::ChannelClosed)?
+            .map_err(ZmqSenderError::Send)
+    }
 }
 
 impl ZmqSenderTrait for ZmqSender {
     fn send_bytes<'a>(&'a self, bytes: Vec<u8>) -> SendFuture<'a> {
-        let tx = self.tx.clone();
-        Box::pin(async move {
-            tx.send(Envelope::Bytes(bytes))
-                .await
-                .map_err(|_| ZmqSenderError::ChannelClosed)
-        })
+        Box::pin(self.enqueue(Envelope::Bytes(bytes, None)))
     }
 
     fn try_send_bytes(&self, bytes: Vec<u8>) -> Result<(), ZmqSenderError> {
         self.tx
-            .try_send(Envelope::Bytes(bytes))
+            .try_send(Envelope::Bytes(bytes, None))
             .map_err(|e| match e {
                 mpsc::error::TrySendError::Full(_) => ZmqSenderError::QueueFull,
                 mpsc::error::TrySendError::Closed(_) => ZmqSenderError::ChannelClosed,
@@ -194,12 +234,15 @@ impl ZmqSenderTrait for ZmqSender {
     }
 
     fn send_multipart<'a>(&'a self, frames: Vec<Vec<u8>>) -> SendFuture<'a> {
-        let tx = self.tx.clone();
-        Box::pin(async move {
-            tx.send(Envelope::Multipart(frames))
-                .await
-                .map_err(|_| ZmqSenderError::ChannelClosed)
-        })
+        Box::pin(self.enqueue(Envelope::Multipart(frames, None)))
+    }
+
+    fn send_bytes_acked<'a>(&'a self, bytes: Vec<u8>) -> SendFuture<'a> {
+        Box::pin(self.enqueue_acked(move |ack| Envelope::Bytes(bytes, Some(ack))))
+    }
+
+    fn send_multipart_acked<'a>(&'a self, frames: Vec<Vec<u8>>) -> SendFuture<'a> {
+        Box::pin(self.enqueue_acked(move |ack| Envelope::Multipart(frames, Some(ack))))
     }
 }
 
@@ -1,11 +1,62 @@
-#![cfg(feature = "db")]
+#![cfg(feature = "testing")]
 
-mod common;
+use pushkind_common::testing::TestDb;
 
 #[test]
 fn test_creates_and_removes_db_files() {
-    let test_db = common::TestDb::new();
+    let test_db = TestDb::new();
     let pool = test_db.pool();
     let conn = pool.get();
     assert!(conn.is_ok());
 }
+
+mod migrations {
+    use diesel::connection::SimpleConnection;
+    use pushkind_common::db::migrations::{
+        EmbeddedMigrations, MigrationError, embed_migrations, migration_status,
+        run_pending_migrations,
+    };
+
+    use super::TestDb;
+
+    const MIGRATIONS: EmbeddedMigrations = embed_migrations!("tests/fixtures/migrations");
+
+    #[test]
+    fn runs_pending_migrations_once() {
+        let test_db = TestDb::new();
+        let pool = test_db.pool();
+
+        let applied = run_pending_migrations(&pool, &MIGRATIONS).unwrap();
+        assert_eq!(applied, vec!["20240101000000", "20240201000000"]);
+
+        let applied = run_pending_migrations(&pool, &MIGRATIONS).unwrap();
+        assert!(applied.is_empty());
+
+        let mut conn = pool.get().unwrap();
+        let status = migration_status(&mut conn, &MIGRATIONS).unwrap();
+        assert_eq!(status.applied, vec!["20240101000000", "20240201000000"]);
+        assert!(status.pending.is_empty());
+        assert!(!status.has_drift());
+        conn.batch_execute("INSERT INTO customers (hub_id, name, email) VALUES (1, 'Анна', NULL)")
+            .unwrap();
+    }
+
+    #[test]
+    fn refuses_to_run_on_schema_drift() {
+        let test_db = TestDb::new();
+        let pool = test_db.pool();
+        run_pending_migrations(&pool, &MIGRATIONS).unwrap();
+
+        pool.get()
+            .unwrap()
+            .batch_execute(
+                "INSERT INTO __diesel_schema_migrations (version) VALUES ('20990101000000')",
+            )
+            .unwrap();
+
+        let error = run_pending_migrations(&pool, &MIGRATIONS).unwrap_err();
+        assert!(
+            matches!(error, MigrationError::Drift(ref unknown) if unknown == &["20990101000000"])
+        );
+    }
+}
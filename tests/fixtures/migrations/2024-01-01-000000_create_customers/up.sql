@@ -0,0 +1,5 @@
+CREATE TABLE customers (
+    id INTEGER PRIMARY KEY NOT NULL,
+    hub_id INTEGER NOT NULL,
+    name TEXT NOT NULL
+);